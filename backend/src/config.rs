@@ -1,16 +1,123 @@
 use serde::Deserialize;
 
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    S3,
+    Local,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProviderKind {
+    #[default]
+    Bedrock,
+    Local,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub jwt_secret: String,
     pub database_url: String,
+    #[serde(default)]
     pub aws_profile_name: String,
+    #[serde(default)]
     pub s3_bucket_name: String,
     pub s3_document_prefix: String,
     pub redis_url: String,
     pub opensearch_url: String,
+    #[serde(default)]
     pub bedrock_model_id: String,
     pub otel_exporter_otlp_endpoint: String,
+    // Object storage: "s3" (default) or "local" for development without AWS access
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    #[serde(default = "default_local_storage_path")]
+    pub local_storage_path: String,
+    // Base URL clients use to reach this server, used for local presigned upload URLs
+    #[serde(default = "default_public_base_url")]
+    pub public_base_url: String,
+    // Embedding model: "bedrock" (default) or "local" to embed in-process on the CPU
+    #[serde(default)]
+    pub embedding_provider: EmbeddingProviderKind,
+    // Word vectors (GloVe/word2vec text format) for the local provider; without
+    // them the local provider falls back to hashed character trigrams
+    pub local_embedding_model_path: Option<String>,
+    // Must match the dimension of the OpenSearch embedding indexes, and of the
+    // word vectors if any
+    #[serde(default = "default_local_embedding_dimension")]
+    pub local_embedding_dimension: usize,
+    // Days a deleted file stays in the trash before the worker removes it for good
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    // Seconds a claimed job can go without a lease extension before another
+    // worker picks it up
+    #[serde(default = "default_job_visibility_timeout_secs")]
+    pub job_visibility_timeout_secs: u64,
+    // Jobs each worker process runs in parallel
+    #[serde(default = "default_worker_concurrency")]
+    pub worker_concurrency: usize,
+    // Requests per second each worker process sends to the embedding provider
+    // and to OpenSearch, shared by its jobs; 0 disables the limit
+    #[serde(default = "default_embedding_requests_per_second")]
+    pub embedding_requests_per_second: f64,
+    #[serde(default)]
+    pub opensearch_requests_per_second: f64,
+    // Seconds the HTTP server and the worker get on SIGTERM/SIGINT to finish
+    // in-flight requests and jobs
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    // Match files against every user's files instead of only the uploader's,
+    // and show users the other users' files they match
+    #[serde(default)]
+    pub cross_tenant_deduplication: bool,
+    // Seconds an access token is valid for, and days a refresh token can be
+    // traded for a new pair
+    #[serde(default = "default_access_token_ttl_secs")]
+    pub access_token_ttl_secs: u64,
+    #[serde(default = "default_refresh_token_ttl_days")]
+    pub refresh_token_ttl_days: u32,
+}
+
+fn default_local_storage_path() -> String {
+    "./storage".to_string()
+}
+
+fn default_public_base_url() -> String {
+    "http://localhost:8080".to_string()
+}
+
+fn default_local_embedding_dimension() -> usize {
+    1536
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_job_visibility_timeout_secs() -> u64 {
+    300
+}
+
+fn default_worker_concurrency() -> usize {
+    4
+}
+
+fn default_embedding_requests_per_second() -> f64 {
+    10.0
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    25
+}
+
+fn default_access_token_ttl_secs() -> u64 {
+    900
+}
+
+fn default_refresh_token_ttl_days() -> u32 {
+    30
 }
 
 impl Config {
@@ -1,36 +1,52 @@
-use bcrypt::{DEFAULT_COST, hash};
+use serde::Serialize;
 use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+#[derive(Serialize, Debug)]
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl User {
+    fn from_row(row: &sqlx::postgres::PgRow) -> Self {
+        User {
+            user_id: row.get("id"),
+            username: row.get("username"),
+            email: row.get("email"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
 
-pub async fn get_user_by_email(
+/// The user with this email and their password hash
+pub async fn get_user_with_password_hash(
     pool: &PgPool,
     email: &str,
-) -> Result<Option<(String, String)>, sqlx::Error> {
-    let row = sqlx::query("SELECT email, password_hash FROM users WHERE email = $1")
-        .bind(email)
-        .fetch_optional(pool)
-        .await?;
+) -> Result<Option<(User, String)>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT id, username, email, created_at, password_hash FROM users WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
 
-    match row {
-        Some(row) => {
-            let username: String = row.get("email");
-            let password_hash: String = row.get("password_hash");
-            Ok(Some((username, password_hash)))
-        }
-        None => Ok(None),
-    }
+    Ok(row.map(|row| (User::from_row(&row), row.get("password_hash"))))
 }
 
+/// Creates a user with an already hashed password
 pub async fn create_user(
     pool: &PgPool,
     username: &str,
     email: &str,
-    password: &str,
-) -> Result<(i32, String, String), sqlx::Error> {
-    let password_hash = hash(password, DEFAULT_COST).expect("Failed to hash password");
+    password_hash: &str,
+) -> Result<User, sqlx::Error> {
     let row = sqlx::query(
         r#"
         INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3)
-        RETURNING id, username, email
+        RETURNING id, username, email, created_at
     "#,
     )
     .bind(username)
@@ -39,7 +55,52 @@ pub async fn create_user(
     .fetch_one(pool)
     .await?;
 
-    Ok((row.get("id"), row.get("email"), row.get("username")))
+    Ok(User::from_row(&row))
+}
+
+pub async fn get_user_by_id(pool: &PgPool, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, username, email, created_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(User::from_row))
+}
+
+pub async fn get_password_hash(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| row.get("password_hash")))
+}
+
+pub async fn update_password_hash(
+    pool: &PgPool,
+    user_id: Uuid,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2")
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_email(pool: &PgPool, user_id: Uuid, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET email = $1, updated_at = NOW() WHERE id = $2")
+        .bind(email)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -49,44 +110,26 @@ mod users_db_test {
     use sqlx::PgPool;
     use std::env;
 
-    // Helper function to create an in-memory SQLite database for testing
+    const TEST_PASSWORD_HASH: &str = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewdBPj/RK.s5uDfm"; // bcrypt hash for "password123"
+
     async fn setup_test_db() -> PgPool {
         from_filename("../.env").ok();
 
         let database_url = env::var("DATABASE_URL").expect("Database url not configured");
-        // For testing, we'll use SQLite instead of PostgreSQL for simplicity
         let pool = PgPool::connect(&database_url)
             .await
             .expect("Failed to create test database");
-
-        // Create the users table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS users (
-                id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
-                username TEXT UNIQUE NOT NULL,
-                email TEXT UNIQUE NOT NULL,
-                password_hash TEXT NOT NULL,
-                created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
-        )
-        .execute(&pool)
-        .await
-        .expect("Failed to create users table");
-
-        // Clear out test data from previous runs
-        sqlx::query("DELETE FROM users where email like '%test%'")
-            .execute(&pool)
+        sqlx::migrate!("./src/migrations")
+            .run(&pool)
             .await
-            .expect("Error deleting data from 'users' table");
+            .expect("Failed to run migrations");
 
         pool
     }
 
-    // Helper function to insert a test user
+    // Helper function to insert a test user, replacing one left by a previous run
     async fn insert_test_user(pool: &PgPool, username: &str, email: &str, password_hash: &str) {
+        delete_test_user(pool, username, email).await;
         sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3)")
             .bind(username)
             .bind(email)
@@ -96,94 +139,87 @@ mod users_db_test {
             .expect("Failed to insert test user");
     }
 
+    async fn delete_test_user(pool: &PgPool, username: &str, email: &str) {
+        sqlx::query("DELETE FROM users WHERE username = $1 OR email = $2")
+            .bind(username)
+            .bind(email)
+            .execute(pool)
+            .await
+            .expect("Error deleting data from 'users' table");
+    }
+
     #[tokio::test]
-    async fn test_get_user_by_email_success() {
+    async fn test_get_user_with_password_hash_success() {
         // Setup
         let pool = setup_test_db().await;
         let test_username = "testuser2";
         let test_email = "test2@example.com";
-        let test_password_hash = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewdBPj/RK.s5uDfm"; // bcrypt hash for "password123"
-
-        // Insert test user
-        insert_test_user(&pool, test_username, test_email, test_password_hash).await;
+        insert_test_user(&pool, test_username, test_email, TEST_PASSWORD_HASH).await;
 
         // Test
-        let result = get_user_by_email(&pool, test_username).await;
+        let result = get_user_with_password_hash(&pool, test_email).await;
 
         // Assert
         assert!(result.is_ok(), "Expected successful result");
         let user_data = result.unwrap();
         assert!(user_data.is_some(), "Expected user to be found");
 
-        let (username, password_hash) = user_data.unwrap();
-        assert_eq!(username, test_username);
-        assert_eq!(password_hash, test_password_hash);
+        let (user, password_hash) = user_data.unwrap();
+        assert_eq!(user.username, test_username);
+        assert_eq!(user.email, test_email);
+        assert_eq!(password_hash, TEST_PASSWORD_HASH);
     }
 
     #[tokio::test]
-    async fn test_get_user_by_email_not_found() {
+    async fn test_get_user_with_password_hash_not_found() {
         // Setup
         let pool = setup_test_db().await;
-        let nonexistent_username = "nonexistent_user";
 
         // Test
-        let result = get_user_by_email(&pool, nonexistent_username).await;
+        let result = get_user_with_password_hash(&pool, "nonexistent@example.com").await;
 
         // Assert
         assert!(
             result.is_ok(),
             "Expected successful result even when user not found"
         );
-        let user_data = result.unwrap();
-        assert!(user_data.is_none(), "Expected no user to be found");
+        assert!(result.unwrap().is_none(), "Expected no user to be found");
     }
 
     #[tokio::test]
-    async fn test_get_user_by_email_case_sensitive() {
+    async fn test_get_user_with_password_hash_looks_up_email_not_username() {
         // Setup
         let pool = setup_test_db().await;
-        let test_username = "TestUser";
-        let test_email = "test@example.com";
-        let test_password_hash = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewdBPj/RK.s5uDfm";
-
-        // Insert test user with specific case
-        insert_test_user(&pool, test_username, test_email, test_password_hash).await;
+        let test_username = "user@domain.com";
+        let test_email = "test4@domain.com";
+        insert_test_user(&pool, test_username, test_email, TEST_PASSWORD_HASH).await;
 
-        // Test with exact case
-        let result_exact = get_user_by_email(&pool, "TestUser").await;
-        assert!(result_exact.is_ok());
-        assert!(result_exact.unwrap().is_some());
+        // Test
+        let by_username = get_user_with_password_hash(&pool, test_username).await;
+        let by_email = get_user_with_password_hash(&pool, test_email).await;
 
-        // Test with different case
-        let result_different_case = get_user_by_email(&pool, "testuser").await;
-        assert!(result_different_case.is_ok());
-        assert!(
-            result_different_case.unwrap().is_none(),
-            "Username search should be case-sensitive"
-        );
+        // Assert
+        assert!(by_username.unwrap().is_none());
+        let (user, _) = by_email.unwrap().expect("Expected user to be found");
+        assert_eq!(user.username, test_username);
     }
 
     #[tokio::test]
-    async fn test_get_user_by_email_with_special_characters() {
+    async fn test_get_user_with_password_hash_case_sensitive() {
         // Setup
         let pool = setup_test_db().await;
-        let test_username = "user@domain.com";
-        let test_email = "test4@domain.com";
-        let test_password_hash = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewdBPj/RK.s5uDfm";
+        insert_test_user(&pool, "TestUser", "Test3@example.com", TEST_PASSWORD_HASH).await;
 
-        // Insert test user with special characters
-        insert_test_user(&pool, test_username, test_email, test_password_hash).await;
-
-        // Test
-        let result = get_user_by_email(&pool, test_username).await;
-
-        // Assert
-        assert!(result.is_ok());
-        let user_data = result.unwrap();
-        assert!(user_data.is_some());
+        // Test with exact case
+        let result_exact = get_user_with_password_hash(&pool, "Test3@example.com").await;
+        assert!(result_exact.unwrap().is_some());
 
-        let (username, _) = user_data.unwrap();
-        assert_eq!(username, test_username);
+        // Test with different case
+        let result_different_case = get_user_with_password_hash(&pool, "test3@example.com").await;
+        assert!(
+            result_different_case.unwrap().is_none(),
+            "Email lookup should be case-sensitive"
+        );
     }
 
     #[tokio::test]
@@ -192,22 +228,23 @@ mod users_db_test {
         let pool = setup_test_db().await;
         let test_username = "newuser";
         let test_email = "test5@example.com";
-        let test_password_hash = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewdBPj/RK.s5uDfm";
+        delete_test_user(&pool, test_username, test_email).await;
 
-        // Create user using the create_user function
-        let create_result = create_user(&pool, test_username, test_email, test_password_hash).await;
-        assert!(create_result.is_ok(), "Failed to create user");
+        // Create user using the create_user function, which stores the hash as given
+        let created = create_user(&pool, test_username, test_email, TEST_PASSWORD_HASH)
+            .await
+            .expect("Failed to create user");
+        assert_eq!(created.username, test_username);
+        assert_eq!(created.email, test_email);
 
         // Retrieve the user
-        let result = get_user_by_email(&pool, test_username).await;
+        let (user, password_hash) = get_user_with_password_hash(&pool, test_email)
+            .await
+            .unwrap()
+            .expect("Expected user to be found");
 
         // Assert
-        assert!(result.is_ok());
-        let user_data = result.unwrap();
-        assert!(user_data.is_some());
-
-        let (username, password_hash) = user_data.unwrap();
-        assert_eq!(username, test_username);
-        assert_eq!(password_hash, test_password_hash);
+        assert_eq!(user.user_id, created.user_id);
+        assert_eq!(password_hash, TEST_PASSWORD_HASH);
     }
 }
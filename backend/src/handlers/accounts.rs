@@ -0,0 +1,119 @@
+use crate::config::Config;
+use crate::handlers::auth::account_error_response;
+use crate::middleware::AuthenticatedUser;
+use crate::services::accounts::{change_email, change_password, delete_account, get_account};
+use crate::services::auth::logout;
+use crate::services::storage::ObjectStorage;
+use actix_web::{HttpResponse, Responder, delete, get, put, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    /// The current password, confirming the change
+    pub password: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+#[get("/me")]
+pub async fn get_me(user: AuthenticatedUser, pool: web::Data<PgPool>) -> impl Responder {
+    match get_account(&pool, user.user_id).await {
+        Ok(account) => HttpResponse::Ok().json(account),
+        Err(e) => account_error_response(e),
+    }
+}
+
+/// Change the password, ending every login session including the one the
+/// request was made with
+#[put("/me/password")]
+pub async fn update_password(
+    user: AuthenticatedUser,
+    req_body: web::Json<ChangePasswordRequest>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    match change_password(
+        &pool,
+        user.user_id,
+        &req_body.current_password,
+        &req_body.new_password,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Password changed, log in again"
+        })),
+        Err(e) => account_error_response(e),
+    }
+}
+
+#[put("/me/email")]
+pub async fn update_email_address(
+    user: AuthenticatedUser,
+    req_body: web::Json<ChangeEmailRequest>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    match change_email(&pool, user.user_id, &req_body.password, &req_body.email).await {
+        Ok(account) => HttpResponse::Ok().json(account),
+        Err(e) => account_error_response(e),
+    }
+}
+
+/// Delete the account, with the files the user uploaded, their jobs and
+/// workspaces nobody else belongs to
+#[delete("/me")]
+pub async fn delete_me(
+    user: AuthenticatedUser,
+    req_body: web::Json<DeleteAccountRequest>,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
+) -> impl Responder {
+    let purged = match delete_account(
+        &pool,
+        storage.get_ref().as_ref(),
+        &config.opensearch_url,
+        user.user_id,
+        &req_body.password,
+    )
+    .await
+    {
+        Ok(purged) => purged,
+        Err(e) => return account_error_response(e),
+    };
+
+    // The account is gone either way; the token only fails later instead
+    if let Err(e) = logout(
+        &pool,
+        user.user_id,
+        user.token_id,
+        user.token_expires_at,
+        None,
+    )
+    .await
+    {
+        log::error!(
+            "Failed to revoke token of deleted account {}: {:?}",
+            user.user_id,
+            e
+        );
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Account deleted",
+        "files_deleted": purged
+    }))
+}
@@ -0,0 +1,68 @@
+use crate::middleware::AuthenticatedUser;
+use crate::services::api_keys::{ApiKeyScope, create_api_key, revoke_api_key, user_api_keys};
+use actix_web::{HttpResponse, Responder, delete, get, post, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+/// Create an API key. The response is the only time the key is shown.
+#[post("/api-keys")]
+pub async fn create_api_key_handler(
+    user: AuthenticatedUser,
+    req_body: web::Json<CreateApiKeyRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let name = req_body.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return HttpResponse::BadRequest().json("API key name must be 1-100 characters");
+    }
+    if req_body.scopes.is_empty() {
+        return HttpResponse::BadRequest().json("API key needs at least one scope");
+    }
+
+    match create_api_key(db_pool.get_ref(), user.user_id, name, &req_body.scopes).await {
+        Ok(api_key) => HttpResponse::Created().json(api_key),
+        Err(e) => {
+            log::error!("Failed to create API key: {}", e);
+            HttpResponse::InternalServerError().json("Failed to create API key")
+        }
+    }
+}
+
+#[get("/api-keys")]
+pub async fn get_api_keys(user: AuthenticatedUser, db_pool: web::Data<PgPool>) -> impl Responder {
+    match user_api_keys(db_pool.get_ref(), user.user_id).await {
+        Ok(api_keys) => HttpResponse::Ok().json(serde_json::json!({ "api_keys": api_keys })),
+        Err(e) => {
+            log::error!("Failed to fetch API keys: {}", e);
+            HttpResponse::InternalServerError().json("Failed to fetch API keys")
+        }
+    }
+}
+
+#[delete("/api-keys/{api_key_id}")]
+pub async fn revoke_api_key_handler(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let api_key_id = path.into_inner();
+
+    match revoke_api_key(db_pool.get_ref(), user.user_id, api_key_id).await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "API key revoked",
+            "api_key_id": api_key_id
+        })),
+        Ok(false) => HttpResponse::NotFound().json("API key not found"),
+        Err(e) => {
+            log::error!("Failed to revoke API key: {}", e);
+            HttpResponse::InternalServerError().json("Failed to revoke API key")
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::config::Config;
-use crate::services::auth::{AuthError, authenticate_user, generate_jwt_token};
+use crate::middleware::AuthenticatedUser;
+use crate::services::accounts::{AccountError, authenticate};
+use crate::services::auth::{AuthError, issue_tokens, logout, refresh_tokens};
 use actix_web::{HttpResponse, Responder, post, web};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
@@ -10,6 +12,17 @@ struct LoginRequestBody {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct RefreshRequestBody {
+    refresh_token: String,
+}
+
+#[derive(Deserialize, Default)]
+struct LogoutRequestBody {
+    /// Also ends the session this refresh token belongs to
+    refresh_token: Option<String>,
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     message: String,
@@ -19,6 +32,9 @@ struct ErrorResponse {
 #[derive(Serialize)]
 struct SuccessResponse {
     token: String,
+    refresh_token: String,
+    /// Seconds until `token` expires
+    expires_in: u64,
     success: bool,
     message: String,
     username: String,
@@ -34,40 +50,26 @@ pub async fn login(
     let password = &req_body.password;
 
     // 1. check if user credentials are valid
-    // 2. Generate a JWT token and return the token
-    // 3. This endpoint will only be hit if JWT is expired
-    match authenticate_user(&pool, email, password).await {
-        Ok(true) => {
-            let token_result = generate_jwt_token(email, &config.jwt_secret);
-            match token_result {
-                Ok(token) => {
-                    let success_response = SuccessResponse {
-                        token,
-                        success: true,
-                        message: format!("Welcome: {}", email),
-                        username: email.to_owned(),
-                    };
-
-                    HttpResponse::Ok().json(success_response)
-                }
-                Err(auth_error) => {
-                    let error_response = ErrorResponse {
-                        message: match auth_error {
-                            AuthError::TokenGeneration => {
-                                "Failed to generate authentication token".to_string()
-                            }
-                            AuthError::InvalidCredentials => "Invalid credentials".to_string(),
-                            AuthError::UserNotFound => "User not found".to_string(),
-                            AuthError::InvalidToken => "Invalid token".to_string(),
-                        },
-                        success: false,
-                    };
+    // 2. Generate an access and a refresh token and return them
+    // 3. This endpoint will only be hit if the refresh token has expired too
+    match authenticate(&pool, email, password).await {
+        Ok(user) => match issue_tokens(&pool, user.user_id, &user.username, &config).await {
+            Ok(tokens) => {
+                let success_response = SuccessResponse {
+                    token: tokens.access_token,
+                    refresh_token: tokens.refresh_token,
+                    expires_in: tokens.expires_in,
+                    success: true,
+                    message: format!("Welcome: {}", user.username),
+                    username: user.username,
+                };
 
-                    HttpResponse::InternalServerError().json(error_response)
-                }
+                HttpResponse::Ok().json(success_response)
             }
-        }
-        Ok(false) | Err(_) => {
+            Err(auth_error) => token_error_response(auth_error),
+        },
+        Err(AccountError::Database(e)) => token_error_response(AuthError::Database(e)),
+        Err(_) => {
             let error_response = ErrorResponse {
                 message: String::from("Invalid username or password"),
                 success: false,
@@ -77,3 +79,66 @@ pub async fn login(
         }
     }
 }
+
+/// Trade a refresh token for a new access token and refresh token
+#[post("/auth/refresh")]
+pub async fn refresh(
+    req_body: web::Json<RefreshRequestBody>,
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    match refresh_tokens(&pool, &req_body.refresh_token, &config).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(auth_error) => token_error_response(auth_error),
+    }
+}
+
+/// Revoke the request's access token, and the session of the refresh token
+/// if one is given
+#[post("/auth/logout")]
+pub async fn logout_user(
+    user: AuthenticatedUser,
+    req_body: Option<web::Json<LogoutRequestBody>>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    let req_body = req_body.map(web::Json::into_inner).unwrap_or_default();
+
+    match logout(
+        &pool,
+        user.user_id,
+        user.token_id,
+        user.token_expires_at,
+        req_body.refresh_token.as_deref(),
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Logged out"
+        })),
+        Err(auth_error) => token_error_response(auth_error),
+    }
+}
+
+fn token_error_response(auth_error: AuthError) -> HttpResponse {
+    let (message, unauthorized) = match auth_error {
+        AuthError::TokenGeneration => ("Failed to generate authentication token", false),
+        AuthError::InvalidCredentials => ("Invalid credentials", true),
+        AuthError::InvalidToken => ("Invalid token", true),
+        AuthError::ExpiredToken => ("Token has expired", true),
+        AuthError::Database(e) => {
+            log::error!("Failed to update login session: {}", e);
+            ("Failed to update login session", false)
+        }
+    };
+    let error_response = ErrorResponse {
+        message: message.to_string(),
+        success: false,
+    };
+
+    if unauthorized {
+        HttpResponse::Unauthorized().json(error_response)
+    } else {
+        HttpResponse::InternalServerError().json(error_response)
+    }
+}
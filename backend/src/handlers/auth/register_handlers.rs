@@ -1,4 +1,4 @@
-use crate::database::users::create_user;
+use crate::services::accounts::{AccountError, register};
 use actix_web::{HttpResponse, Responder, post, web};
 use serde::Deserialize;
 use sqlx::PgPool;
@@ -10,54 +10,44 @@ struct RegisterRequest {
     password: String,
 }
 
-enum RegisterPayloadError {
-    InvalidEmail,
-    InvalidUserName,
-    WeakPassword,
-}
-
-fn validate_register_payload(
-    username: &str,
-    email: &str,
-    password: &str,
-) -> Result<(), RegisterPayloadError> {
-    // Username: 3-32 chars
-    if username.len() < 3 || username.len() > 32 {
-        return Err(RegisterPayloadError::InvalidUserName);
-    }
-    // Email: basic check for '@'
-    if !email.contains('@') || !email.contains('.') {
-        return Err(RegisterPayloadError::InvalidEmail);
-    }
-    // Password: at least 8 chars
-    if password.len() < 8 {
-        return Err(RegisterPayloadError::WeakPassword);
-    }
-
-    Ok(())
-}
-
 #[post("/auth/register")]
 pub async fn register_user(
     req_body: web::Json<RegisterRequest>,
     pool: web::Data<PgPool>,
 ) -> impl Responder {
-    let username = &req_body.username;
-    let password = &req_body.password;
-    let email = &req_body.email;
-
-    if let Err(validate_error) = validate_register_payload(username, email, password) {
-        let error_message = match validate_error {
-            RegisterPayloadError::InvalidEmail => "Invalid Email",
-            RegisterPayloadError::InvalidUserName => "Invalid Username",
-            RegisterPayloadError::WeakPassword => "Weak password",
-        };
-
-        return HttpResponse::BadRequest().json(error_message);
+    match register(
+        &pool,
+        &req_body.username,
+        &req_body.email,
+        &req_body.password,
+    )
+    .await
+    {
+        Ok(user) => HttpResponse::Created().json(user),
+        Err(e) => account_error_response(e),
     }
+}
 
-    match create_user(&pool, username, email, password).await {
-        Ok(_) => HttpResponse::Created().body("Sucess"),
-        Err(msg) => HttpResponse::InternalServerError().json(msg.to_string()),
+/// Response for a failed account operation. The client matches on these
+/// messages, so they stay as they are.
+pub(crate) fn account_error_response(error: AccountError) -> HttpResponse {
+    match error {
+        AccountError::InvalidEmail => HttpResponse::BadRequest().json("Invalid Email"),
+        AccountError::InvalidUsername => HttpResponse::BadRequest().json("Invalid Username"),
+        AccountError::WeakPassword => HttpResponse::BadRequest().json("Weak password"),
+        AccountError::InvalidCredentials => HttpResponse::Forbidden().json("Incorrect password"),
+        AccountError::NotFound => HttpResponse::NotFound().json("User not found"),
+        AccountError::AlreadyExists => {
+            HttpResponse::Conflict().json("Username or email already exists")
+        }
+        AccountError::LastOwner => HttpResponse::Conflict()
+            .json("Make another member an owner of your shared workspaces first"),
+        AccountError::Hashing => {
+            HttpResponse::InternalServerError().json("Failed to hash password")
+        }
+        AccountError::Database(e) => {
+            log::error!("Failed to update account: {}", e);
+            HttpResponse::InternalServerError().json("Failed to update account")
+        }
     }
 }
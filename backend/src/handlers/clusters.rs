@@ -0,0 +1,379 @@
+use crate::config::Config;
+use crate::middleware::WorkspaceMember;
+use crate::services::resolution::{
+    CLUSTER_ACCESS, KEEPER_ORDER, ResolutionAction, ResolutionError, ResolutionOutcome,
+    ResolutionPlan, apply_resolution, default_keeper, plan_cluster_resolution,
+};
+use crate::services::storage::ObjectStorage;
+use crate::services::workspaces::Permission;
+use actix_web::{HttpResponse, Responder, get, post, web};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Per-cluster sizes of the files outside the trash, counting only bytes of
+/// files that still hold content. The keeper is the largest of them (the
+/// highest-quality copy of near-duplicate images and documents), so
+/// everything else in the cluster is reclaimable. Counts only files of the
+/// workspace bound to `$1`, or every workspace's files when `$2`
+/// (cross-tenant deduplication) is true.
+const CLUSTER_STATS_QUERY: &str = "SELECT cluster_id, COUNT(*)::BIGINT AS size, \
+         COALESCE(SUM(file_size) FILTER (WHERE reference_file_id IS NULL), 0)::BIGINT AS total_bytes, \
+         (COALESCE(SUM(file_size) FILTER (WHERE reference_file_id IS NULL), 0) \
+             - COALESCE(MAX(file_size) FILTER (WHERE reference_file_id IS NULL), 0))::BIGINT AS reclaimable_bytes \
+     FROM File WHERE cluster_id IS NOT NULL AND deleted_at IS NULL AND (workspace_id = $1 OR $2) \
+     GROUP BY cluster_id";
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterSort {
+    #[default]
+    ReclaimableBytes,
+    Size,
+    Score,
+    CreatedAt,
+}
+
+impl ClusterSort {
+    fn column(self) -> &'static str {
+        match self {
+            ClusterSort::ReclaimableBytes => "stats.reclaimable_bytes",
+            ClusterSort::Size => "stats.size",
+            ClusterSort::Score => "c.intra_similarity_score",
+            ClusterSort::CreatedAt => "c.created_at",
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct ClustersQuery {
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub min_score: Option<f64>,
+    pub max_score: Option<f64>,
+    #[serde(default)]
+    pub sort: ClusterSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ClusterSummary {
+    pub cluster_id: i32,
+    pub size: i64,
+    pub intra_similarity_score: f64,
+    pub total_bytes: i64,
+    pub reclaimable_bytes: i64,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct ClusterMember {
+    pub file_id: i32,
+    pub file_name: String,
+    pub file_size: Option<i64>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    /// Kept file this member's content was replaced with, if any
+    pub reference_file_id: Option<i32>,
+    pub is_keeper: bool,
+}
+
+#[derive(Serialize)]
+pub struct ClusterPair {
+    pub file_id: i32,
+    pub matched_file_id: i32,
+    pub match_type: String,
+    pub similarity_score: f64,
+    pub shared_bytes: i64,
+}
+
+#[derive(Serialize)]
+pub struct ClusterDetails {
+    #[serde(flatten)]
+    pub summary: ClusterSummary,
+    pub keeper_file_id: Option<i32>,
+    pub members: Vec<ClusterMember>,
+    pub pairs: Vec<ClusterPair>,
+}
+
+#[get("/clusters")]
+pub async fn get_clusters(
+    member: WorkspaceMember,
+    query: web::Query<ClustersQuery>,
+    config: web::Data<Config>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100); // Max 100 clusters per request
+    let offset = query.offset.unwrap_or(0).max(0);
+    let direction = match query.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+
+    // The sort column comes from a fixed list, never from the request text
+    let sql = format!(
+        "SELECT c.cluster_id, c.intra_similarity_score, c.created_at, \
+                stats.size, stats.total_bytes, stats.reclaimable_bytes, COUNT(*) OVER () AS total \
+         FROM Cluster c \
+         JOIN ({}) stats ON stats.cluster_id = c.cluster_id \
+         WHERE {} \
+           AND stats.size >= COALESCE($3, 0) AND stats.size <= COALESCE($4, stats.size) \
+           AND c.intra_similarity_score >= COALESCE($5, 0) \
+           AND c.intra_similarity_score <= COALESCE($6, c.intra_similarity_score) \
+         ORDER BY {} {}, c.cluster_id \
+         LIMIT $7 OFFSET $8",
+        CLUSTER_STATS_QUERY,
+        CLUSTER_ACCESS,
+        query.sort.column(),
+        direction
+    );
+
+    let result = sqlx::query(&sql)
+        .bind(member.workspace_id)
+        .bind(config.cross_tenant_deduplication)
+        .bind(query.min_size)
+        .bind(query.max_size)
+        .bind(query.min_score)
+        .bind(query.max_score)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db_pool.get_ref())
+        .await;
+
+    match result {
+        Ok(rows) => {
+            let total: i64 = rows.first().map(|row| row.get("total")).unwrap_or(0);
+            let clusters: Vec<ClusterSummary> = rows.iter().map(cluster_summary).collect();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "clusters": clusters,
+                "total": total,
+                "limit": limit,
+                "offset": offset
+            }))
+        }
+        Err(e) => {
+            log::error!("Failed to fetch clusters: {}", e);
+            HttpResponse::InternalServerError().json("Failed to fetch clusters")
+        }
+    }
+}
+
+#[get("/clusters/{cluster_id}")]
+pub async fn get_cluster_by_id(
+    member: WorkspaceMember,
+    path: web::Path<i32>,
+    config: web::Data<Config>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let cluster_id = path.into_inner();
+
+    match fetch_cluster_details(
+        db_pool.get_ref(),
+        member.workspace_id,
+        config.cross_tenant_deduplication,
+        cluster_id,
+    )
+    .await
+    {
+        Ok(Some(details)) => HttpResponse::Ok().json(details),
+        Ok(None) => HttpResponse::NotFound().json("Cluster not found"),
+        Err(e) => {
+            log::error!("Failed to fetch cluster {}: {}", cluster_id, e);
+            HttpResponse::InternalServerError().json("Failed to fetch cluster")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResolveClusterRequest {
+    pub action: ResolutionAction,
+    /// Defaults to the cluster's keeper
+    pub keeper_file_id: Option<i32>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[post("/clusters/{cluster_id}/resolve")]
+pub async fn resolve_cluster(
+    member: WorkspaceMember,
+    path: web::Path<i32>,
+    req_body: web::Json<ResolveClusterRequest>,
+    config: web::Data<Config>,
+    db_pool: web::Data<PgPool>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
+) -> impl Responder {
+    if let Err(response) = member.authorize(Permission::Resolve) {
+        return response;
+    }
+    let cluster_id = path.into_inner();
+
+    let plan = plan_cluster_resolution(
+        db_pool.get_ref(),
+        member.workspace_id,
+        member.user_id,
+        cluster_id,
+        req_body.keeper_file_id,
+        req_body.action,
+    )
+    .await;
+
+    resolution_response(plan, req_body.dry_run, &config, &db_pool, &storage).await
+}
+
+/// Apply a resolution plan, or just return it for a dry run
+pub(crate) async fn resolution_response(
+    plan: Result<ResolutionPlan, ResolutionError>,
+    dry_run: bool,
+    config: &Config,
+    db_pool: &PgPool,
+    storage: &Arc<dyn ObjectStorage>,
+) -> HttpResponse {
+    let outcome = match plan {
+        Ok(plan) if dry_run => Ok(ResolutionOutcome {
+            resolution_id: None,
+            dry_run,
+            plan,
+            storage_errors: Vec::new(),
+        }),
+        Ok(plan) => apply_resolution(db_pool, storage.as_ref(), &config.opensearch_url, plan).await,
+        Err(e) => Err(e),
+    };
+
+    match outcome {
+        Ok(outcome) => HttpResponse::Ok().json(outcome),
+        Err(ResolutionError::NotFound) => HttpResponse::NotFound().json("Not found"),
+        Err(ResolutionError::InvalidKeeper) => {
+            HttpResponse::BadRequest().json("Keeper must be a member that still holds its content")
+        }
+        Err(ResolutionError::NotDuplicates) => {
+            HttpResponse::BadRequest().json("Files are not recorded as duplicates")
+        }
+        Err(ResolutionError::NothingToResolve) => {
+            HttpResponse::Conflict().json("Nothing left to resolve")
+        }
+        Err(ResolutionError::Database(e)) => {
+            log::error!("Failed to resolve duplicates: {}", e);
+            HttpResponse::InternalServerError().json("Failed to resolve duplicates")
+        }
+    }
+}
+
+fn cluster_summary(row: &sqlx::postgres::PgRow) -> ClusterSummary {
+    ClusterSummary {
+        cluster_id: row.get("cluster_id"),
+        size: row.get("size"),
+        intra_similarity_score: row.get("intra_similarity_score"),
+        total_bytes: row.get("total_bytes"),
+        reclaimable_bytes: row.get("reclaimable_bytes"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Load a cluster the workspace can see with its members, keeper first, and
+/// the best recorded match between each pair of members. Other workspaces'
+/// files are only included with cross-tenant deduplication.
+pub async fn fetch_cluster_details(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    cross_tenant: bool,
+    cluster_id: i32,
+) -> Result<Option<ClusterDetails>, sqlx::Error> {
+    let Some(row) = sqlx::query(&format!(
+        "SELECT c.cluster_id, c.intra_similarity_score, c.created_at, \
+                stats.size, stats.total_bytes, stats.reclaimable_bytes \
+         FROM Cluster c \
+         JOIN ({}) stats ON stats.cluster_id = c.cluster_id \
+         WHERE c.cluster_id = $3 AND {}",
+        CLUSTER_STATS_QUERY, CLUSTER_ACCESS
+    ))
+    .bind(workspace_id)
+    .bind(cross_tenant)
+    .bind(cluster_id)
+    .fetch_optional(db_pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let summary = cluster_summary(&row);
+
+    // Most worth keeping first
+    let member_rows = sqlx::query(&format!(
+        "SELECT file_id, file_name, file_size, created_at, reference_file_id FROM File \
+         WHERE cluster_id = $1 AND deleted_at IS NULL AND (workspace_id = $2 OR $3) ORDER BY {}",
+        KEEPER_ORDER
+    ))
+    .bind(cluster_id)
+    .bind(workspace_id)
+    .bind(cross_tenant)
+    .fetch_all(db_pool)
+    .await?;
+
+    // The keeper a resolution defaults to, which is always one of the
+    // workspace's own files even when other workspaces' files are listed
+    let keeper_file_id = default_keeper(db_pool, workspace_id, cluster_id).await?;
+    let mut members: Vec<ClusterMember> = member_rows
+        .iter()
+        .map(|row| ClusterMember {
+            file_id: row.get("file_id"),
+            file_name: row.get("file_name"),
+            file_size: row.get("file_size"),
+            created_at: row.get("created_at"),
+            reference_file_id: row.get("reference_file_id"),
+            is_keeper: Some(row.get("file_id")) == keeper_file_id,
+        })
+        .collect();
+    if let Some(keeper_index) = members.iter().position(|member| member.is_keeper) {
+        let keeper = members.remove(keeper_index);
+        members.insert(0, keeper);
+    }
+
+    let pair_rows = sqlx::query(
+        "SELECT DISTINCT ON (LEAST(m.file_id, m.matched_file_id), GREATEST(m.file_id, m.matched_file_id)) \
+                LEAST(m.file_id, m.matched_file_id) AS file_id, \
+                GREATEST(m.file_id, m.matched_file_id) AS matched_file_id, \
+                m.match_type, m.similarity_score, m.shared_bytes \
+         FROM file_matches m \
+         JOIN File a ON a.file_id = m.file_id \
+         JOIN File b ON b.file_id = m.matched_file_id \
+         WHERE a.cluster_id = $1 AND b.cluster_id = $1 \
+           AND a.deleted_at IS NULL AND b.deleted_at IS NULL \
+           AND ((a.workspace_id = $2 AND b.workspace_id = $2) OR $3) \
+         ORDER BY LEAST(m.file_id, m.matched_file_id), GREATEST(m.file_id, m.matched_file_id), \
+                  m.similarity_score DESC",
+    )
+    .bind(cluster_id)
+    .bind(workspace_id)
+    .bind(cross_tenant)
+    .fetch_all(db_pool)
+    .await?;
+
+    let pairs = pair_rows
+        .iter()
+        .map(|row| ClusterPair {
+            file_id: row.get("file_id"),
+            matched_file_id: row.get("matched_file_id"),
+            match_type: row.get("match_type"),
+            similarity_score: row.get("similarity_score"),
+            shared_bytes: row.get("shared_bytes"),
+        })
+        .collect();
+
+    Ok(Some(ClusterDetails {
+        summary,
+        keeper_file_id,
+        members,
+        pairs,
+    }))
+}
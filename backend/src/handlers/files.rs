@@ -1,9 +1,13 @@
 use crate::config::Config;
+use crate::handlers::clusters::resolution_response;
 use crate::handlers::jobs::create_job_record;
 use crate::metrics::DeduplicationMetrics;
-use crate::services::files::{MultipartUploadParams, S3Client};
-use crate::worker::JobQueue;
-use actix_web::{HttpResponse, Responder, post, web};
+use crate::middleware::WorkspaceMember;
+use crate::services::resolution::{ResolutionAction, plan_pair_resolution};
+use crate::services::storage::{MultipartUploadParams, ObjectStorage};
+use crate::services::workspaces::Permission;
+use crate::worker::{JobPriority, JobQueue};
+use actix_web::{HttpResponse, Responder, get, post, web};
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Row};
 use std::sync::Arc;
@@ -19,6 +23,15 @@ fn is_image_file(file_name: &str) -> bool {
     }
 }
 
+/// Each user's uploads live under their own prefix, so uploads with the same
+/// name from different users don't overwrite each other
+fn object_key(config: &Config, member: &WorkspaceMember, filename: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        config.s3_document_prefix, member.user_id, filename
+    )
+}
+
 #[derive(Deserialize)]
 struct InitializeUploadRequest {
     filename: String,
@@ -29,6 +42,9 @@ struct CompleteUploadRequest {
     filename: String,
     upload_id: String,
     parts: Vec<(i32, String)>,
+    /// Bulk imports should pass "bulk" so they don't hold up interactive uploads
+    #[serde(default)]
+    priority: JobPriority,
 }
 
 #[derive(Deserialize)]
@@ -49,17 +65,44 @@ struct PresignedUrlResponse {
     presigned_url: String,
 }
 
+#[derive(Serialize)]
+struct FileMatch {
+    file_id: i32,
+    file_name: String,
+    /// "exact", "canonical" or "similar"
+    match_type: String,
+    similarity_score: f64,
+    shared_bytes: i64,
+    detected_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ResolveDuplicateRequest {
+    action: ResolutionAction,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct FileDuplicatesResponse {
+    file_id: i32,
+    cluster_id: Option<i32>,
+    matches: Vec<FileMatch>,
+}
+
 #[post("/upload/initiate")]
 pub async fn initiate_upload(
+    member: WorkspaceMember,
     req_body: web::Json<InitializeUploadRequest>,
     config: web::Data<Config>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
 ) -> impl Responder {
-    let s3_client = S3Client::new(&config.aws_profile_name).await;
-    let key = format!("{}/{}", config.s3_document_prefix, req_body.filename);
+    if let Err(response) = member.authorize(Permission::Upload) {
+        return response;
+    }
+    let key = object_key(&config, &member, &req_body.filename);
 
-    let multipart_result = s3_client
-        .create_multipart_upload(&config.s3_bucket_name, &key)
-        .await;
+    let multipart_result = storage.create_multipart_upload(&key).await;
 
     match multipart_result {
         Ok(upload_id) => HttpResponse::Ok().json(UploadSuccessResponse { upload_id }),
@@ -69,24 +112,23 @@ pub async fn initiate_upload(
 
 #[post("/upload/complete")]
 pub async fn complete_upload(
+    member: WorkspaceMember,
     req_body: web::Json<CompleteUploadRequest>,
     config: web::Data<Config>,
     db_pool: web::Data<PgPool>,
     metrics: web::Data<Arc<DeduplicationMetrics>>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
 ) -> impl Responder {
-    let s3_client = S3Client::new(&config.aws_profile_name).await;
-    let key = format!("{}/{}", config.s3_document_prefix, req_body.filename);
+    if let Err(response) = member.authorize(Permission::Upload) {
+        return response;
+    }
+    let key = object_key(&config, &member, &req_body.filename);
 
     // Start timing S3 operation
     let s3_timer = crate::metrics::MetricsTimer::new("s3_complete_upload".to_string());
 
-    let complete_result = s3_client
-        .complete_multipart_upload(
-            &config.s3_bucket_name,
-            &key,
-            req_body.upload_id.clone(),
-            req_body.parts.clone(),
-        )
+    let complete_result = storage
+        .complete_multipart_upload(&key, req_body.upload_id.clone(), req_body.parts.clone())
         .await;
 
     match complete_result {
@@ -103,10 +145,14 @@ pub async fn complete_upload(
 
             // Insert file record into database
             let insert_result = sqlx::query(
-                "INSERT INTO File (file_name, sha256_hash) VALUES ($1, $2) RETURNING file_id",
+                "INSERT INTO File (file_name, sha256_hash, s3_key, owner_id, workspace_id) \
+                 VALUES ($1, $2, $3, $4, $5) RETURNING file_id",
             )
             .bind(&req_body.filename)
             .bind("") // Placeholder hash, will be updated by worker
+            .bind(&key)
+            .bind(member.user_id)
+            .bind(member.workspace_id)
             .fetch_one(db_pool.get_ref())
             .await;
 
@@ -124,6 +170,9 @@ pub async fn complete_upload(
                             req_body.filename.clone(),
                             format!("/tmp/{}", req_body.filename), // Placeholder path
                             key.clone(),
+                            req_body.priority,
+                            Some(member.user_id.to_string()),
+                            Some(member.workspace_id.to_string()),
                         );
 
                         match job_queue.enqueue_deduplication_job(job.clone()).await {
@@ -138,6 +187,9 @@ pub async fn complete_upload(
                                         &req_body.filename,
                                         Some(&format!("/tmp/{}", req_body.filename)),
                                         &key,
+                                        req_body.priority,
+                                        member.user_id,
+                                        member.workspace_id,
                                     )
                                     .await
                                     {
@@ -188,11 +240,15 @@ pub async fn complete_upload(
 
 #[post("/upload/presigned-url")]
 pub async fn generate_presigned_url(
+    member: WorkspaceMember,
     req_body: web::Json<PresignedUrlRequest>,
     config: web::Data<Config>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
 ) -> impl Responder {
-    let s3_client = S3Client::new(&config.aws_profile_name).await;
-    let key = format!("{}/{}", config.s3_document_prefix, req_body.filename);
+    if let Err(response) = member.authorize(Permission::Upload) {
+        return response;
+    }
+    let key = object_key(&config, &member, &req_body.filename);
 
     // Default expiration time is 1 hour (3600 seconds)
     let expires_in = req_body.expires_in_secs.unwrap_or(3600);
@@ -206,8 +262,8 @@ pub async fn generate_presigned_url(
         _ => None,
     };
 
-    let presigned_result = s3_client
-        .generate_presigned_upload_url(&config.s3_bucket_name, &key, expires_in, multipart_params)
+    let presigned_result = storage
+        .generate_presigned_upload_url(&key, expires_in, multipart_params)
         .await;
 
     match presigned_result {
@@ -215,3 +271,156 @@ pub async fn generate_presigned_url(
         Err(_) => HttpResponse::InternalServerError().json("Error generating presigned URL"),
     }
 }
+
+#[get("/files/{file_id}/duplicates")]
+pub async fn get_file_duplicates(
+    member: WorkspaceMember,
+    path: web::Path<i32>,
+    config: web::Data<Config>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let file_id = path.into_inner();
+
+    let cluster_id: Option<i32> =
+        match sqlx::query("SELECT cluster_id FROM File WHERE file_id = $1 AND workspace_id = $2")
+            .bind(file_id)
+            .bind(member.workspace_id)
+            .fetch_optional(db_pool.get_ref())
+            .await
+        {
+            Ok(Some(row)) => row.get("cluster_id"),
+            Ok(None) => return HttpResponse::NotFound().json("File not found"),
+            Err(e) => {
+                log::error!("Failed to fetch file {}: {}", file_id, e);
+                return HttpResponse::InternalServerError().json("Failed to fetch file");
+            }
+        };
+
+    // A match is recorded when either file is processed, so look in both
+    // directions and keep the strongest match per file. Other workspaces'
+    // files are only shown with cross-tenant deduplication.
+    let result = sqlx::query(
+        "SELECT * FROM ( \
+             SELECT DISTINCT ON (f.file_id) f.file_id, f.file_name, m.match_type, \
+                    m.similarity_score, m.shared_bytes, m.created_at \
+             FROM file_matches m \
+             JOIN File f ON f.file_id = CASE WHEN m.file_id = $1 THEN m.matched_file_id ELSE m.file_id END \
+             WHERE (m.file_id = $1 OR m.matched_file_id = $1) AND f.deleted_at IS NULL \
+               AND (f.workspace_id = $2 OR $3) \
+             ORDER BY f.file_id, m.similarity_score DESC, m.created_at DESC \
+         ) matches ORDER BY similarity_score DESC, file_id",
+    )
+    .bind(file_id)
+    .bind(member.workspace_id)
+    .bind(config.cross_tenant_deduplication)
+    .fetch_all(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(rows) => {
+            let matches = rows
+                .into_iter()
+                .map(|row| FileMatch {
+                    file_id: row.get("file_id"),
+                    file_name: row.get("file_name"),
+                    match_type: row.get("match_type"),
+                    similarity_score: row.get("similarity_score"),
+                    shared_bytes: row.get("shared_bytes"),
+                    detected_at: row.get("created_at"),
+                })
+                .collect();
+
+            HttpResponse::Ok().json(FileDuplicatesResponse {
+                file_id,
+                cluster_id,
+                matches,
+            })
+        }
+        Err(e) => {
+            log::error!("Failed to fetch duplicates for file {}: {}", file_id, e);
+            HttpResponse::InternalServerError().json("Failed to fetch duplicates")
+        }
+    }
+}
+
+/// Keep `file_id` and delete or replace `duplicate_file_id`
+#[post("/files/{file_id}/duplicates/{duplicate_file_id}/resolve")]
+pub async fn resolve_duplicate(
+    member: WorkspaceMember,
+    path: web::Path<(i32, i32)>,
+    req_body: web::Json<ResolveDuplicateRequest>,
+    config: web::Data<Config>,
+    db_pool: web::Data<PgPool>,
+    storage: web::Data<Arc<dyn ObjectStorage>>,
+) -> impl Responder {
+    if let Err(response) = member.authorize(Permission::Resolve) {
+        return response;
+    }
+    let (file_id, duplicate_file_id) = path.into_inner();
+
+    let plan = plan_pair_resolution(
+        db_pool.get_ref(),
+        member.workspace_id,
+        member.user_id,
+        file_id,
+        duplicate_file_id,
+        req_body.action,
+    )
+    .await;
+
+    resolution_response(plan, req_body.dry_run, &config, &db_pool, &storage).await
+}
+
+/// Take a file back out of the trash. Its cluster and matches were kept while
+/// it was in the trash, so it reappears in them as before.
+#[post("/files/{file_id}/restore")]
+pub async fn restore_file(
+    member: WorkspaceMember,
+    path: web::Path<i32>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if let Err(response) = member.authorize(Permission::Resolve) {
+        return response;
+    }
+    let file_id = path.into_inner();
+
+    let result = sqlx::query(
+        "UPDATE File SET deleted_at = NULL \
+         WHERE file_id = $1 AND workspace_id = $2 AND deleted_at IS NOT NULL \
+         RETURNING cluster_id",
+    )
+    .bind(file_id)
+    .bind(member.workspace_id)
+    .fetch_optional(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(row)) => {
+            log::info!("Restored file {} from the trash", file_id);
+            HttpResponse::Ok().json(serde_json::json!({
+                "message": "File restored successfully",
+                "file_id": file_id,
+                "cluster_id": row.get::<Option<i32>, _>("cluster_id")
+            }))
+        }
+        Ok(None) => {
+            match sqlx::query("SELECT file_id FROM File WHERE file_id = $1 AND workspace_id = $2")
+                .bind(file_id)
+                .bind(member.workspace_id)
+                .fetch_optional(db_pool.get_ref())
+                .await
+            {
+                Ok(Some(_)) => HttpResponse::Conflict().json("File is not in the trash"),
+                Ok(None) => HttpResponse::NotFound().json("File not found"),
+                Err(e) => {
+                    log::error!("Failed to fetch file {}: {}", file_id, e);
+                    HttpResponse::InternalServerError().json("Failed to restore file")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to restore file {}: {}", file_id, e);
+            HttpResponse::InternalServerError().json("Failed to restore file")
+        }
+    }
+}
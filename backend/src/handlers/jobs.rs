@@ -1,4 +1,7 @@
-use actix_web::{HttpResponse, Responder, delete, get, web};
+use crate::middleware::WorkspaceMember;
+use crate::services::workspaces::Permission;
+use crate::worker::{DeadJobList, JobPriority, JobQueue};
+use actix_web::{HttpResponse, Responder, delete, get, post, web};
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
@@ -11,44 +14,62 @@ pub struct Job {
     pub file_path: Option<String>,
     pub s3_key: String,
     pub status: String,
+    /// "interactive", "bulk" or "reindex"
+    pub priority: String,
     pub error_message: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// What a completed deduplication job found
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobResultSummary {
+    pub exact_duplicates: i32,
+    pub canonical_duplicates: i32,
+    pub similar_files: i32,
+    pub cluster_id: Option<i32>,
+    pub storage_saved_bytes: i64,
+}
+
+#[derive(Serialize)]
+pub struct JobDetails {
+    #[serde(flatten)]
+    pub job: Job,
+    /// Present once the job has completed
+    pub result: Option<JobResultSummary>,
+}
+
 #[derive(Deserialize)]
 pub struct JobsQuery {
     pub status: Option<String>,
+    pub priority: Option<JobPriority>,
     pub limit: Option<i64>,
     pub offset: Option<i64>,
 }
 
 #[get("/jobs")]
-pub async fn get_jobs(query: web::Query<JobsQuery>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn get_jobs(
+    member: WorkspaceMember,
+    query: web::Query<JobsQuery>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
     let limit = query.limit.unwrap_or(50).min(100); // Max 100 jobs per request
     let offset = query.offset.unwrap_or(0);
 
-    let result = if let Some(ref status) = query.status {
-        sqlx::query(
-            "SELECT job_id, file_id, file_name, file_path, s3_key, status, error_message, created_at, updated_at, completed_at 
-             FROM jobs WHERE status = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3"
-        )
-        .bind(status)
-        .bind(limit)
-        .bind(offset)
-        .fetch_all(db_pool.get_ref())
-        .await
-    } else {
-        sqlx::query(
-            "SELECT job_id, file_id, file_name, file_path, s3_key, status, error_message, created_at, updated_at, completed_at 
-             FROM jobs ORDER BY created_at DESC LIMIT $1 OFFSET $2"
-        )
-        .bind(limit)
-        .bind(offset)
-        .fetch_all(db_pool.get_ref())
-        .await
-    };
+    let result = sqlx::query(
+        "SELECT job_id, file_id, file_name, file_path, s3_key, status, priority, error_message, created_at, updated_at, completed_at 
+         FROM jobs 
+         WHERE workspace_id = $1 AND ($2::TEXT IS NULL OR status = $2) AND ($3::TEXT IS NULL OR priority = $3) 
+         ORDER BY created_at DESC LIMIT $4 OFFSET $5",
+    )
+    .bind(member.workspace_id)
+    .bind(query.status.as_deref())
+    .bind(query.priority.map(JobPriority::as_str))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db_pool.get_ref())
+    .await;
 
     match result {
         Ok(rows) => {
@@ -61,6 +82,7 @@ pub async fn get_jobs(query: web::Query<JobsQuery>, db_pool: web::Data<PgPool>)
                     file_path: row.get("file_path"),
                     s3_key: row.get("s3_key"),
                     status: row.get("status"),
+                    priority: row.get("priority"),
                     error_message: row.get("error_message"),
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
@@ -82,17 +104,115 @@ pub async fn get_jobs(query: web::Query<JobsQuery>, db_pool: web::Data<PgPool>)
     }
 }
 
+#[derive(Deserialize)]
+pub struct DeadJobsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// List the caller's dead jobs that have no workspace instead
+    pub unscoped: Option<bool>,
+}
+
+/// The workspace's jobs that failed permanently or ran out of retries, most recent first
+#[get("/jobs/dead")]
+pub async fn get_dead_jobs(
+    member: WorkspaceMember,
+    query: web::Query<DeadJobsQuery>,
+    job_queue: web::Data<JobQueue>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100); // Max 100 jobs per request
+    let offset = query.offset.unwrap_or(0).max(0);
+    let workspace_id = member.workspace_id.to_string();
+    let user_id = member.user_id.to_string();
+    let list = if query.unscoped.unwrap_or(false) {
+        DeadJobList::Unscoped { owner: &user_id }
+    } else {
+        DeadJobList::Workspace(&workspace_id)
+    };
+
+    match job_queue.get_dead_jobs(list, offset, limit).await {
+        Ok((jobs, total)) => HttpResponse::Ok().json(serde_json::json!({
+            "jobs": jobs,
+            "total": total,
+            "limit": limit,
+            "offset": offset
+        })),
+        Err(e) => {
+            log::error!("Failed to fetch dead jobs: {}", e);
+            HttpResponse::InternalServerError().json("Failed to fetch dead jobs")
+        }
+    }
+}
+
+/// Move a dead job of the workspace, or one of the caller's without a
+/// workspace, back onto the queue with a fresh set of attempts
+#[post("/jobs/dead/{job_id}/requeue")]
+pub async fn requeue_dead_job(
+    member: WorkspaceMember,
+    path: web::Path<Uuid>,
+    job_queue: web::Data<JobQueue>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if let Err(response) = member.authorize(Permission::ManageJobs) {
+        return response;
+    }
+    let job_id = path.into_inner();
+    let workspace_id = member.workspace_id.to_string();
+    let user_id = member.user_id.to_string();
+
+    let requeued = match job_queue
+        .requeue_dead_job(DeadJobList::Workspace(&workspace_id), &job_id.to_string())
+        .await
+    {
+        Ok(None) => {
+            job_queue
+                .requeue_dead_job(
+                    DeadJobList::Unscoped { owner: &user_id },
+                    &job_id.to_string(),
+                )
+                .await
+        }
+        requeued => requeued,
+    };
+
+    match requeued {
+        Ok(Some(job)) => {
+            if let Err(e) =
+                update_job_status_in_db(db_pool.get_ref(), job_id, "pending", None).await
+            {
+                log::error!("Failed to update job status in database: {}", e);
+            }
+
+            log::info!("Requeued dead job {}", job_id);
+            HttpResponse::Ok().json(serde_json::json!({
+                "message": "Job requeued",
+                "job": job
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().json("Dead job not found"),
+        Err(e) => {
+            log::error!("Failed to requeue dead job {}: {}", job_id, e);
+            HttpResponse::InternalServerError().json("Failed to requeue job")
+        }
+    }
+}
+
 #[get("/jobs/{job_id}")]
-pub async fn get_job_by_id(path: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn get_job_by_id(
+    member: WorkspaceMember,
+    path: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
     let job_id = path.into_inner();
 
     println!("Job id: {job_id:?}");
 
     match sqlx::query(
-        "SELECT job_id, file_id, file_name, file_path, s3_key, status, error_message, created_at, updated_at, completed_at 
-         FROM jobs WHERE job_id = $1"
+        "SELECT job_id, file_id, file_name, file_path, s3_key, status, priority, error_message, created_at, updated_at, completed_at, 
+                exact_duplicates, canonical_duplicates, similar_files, cluster_id, storage_saved_bytes 
+         FROM jobs WHERE job_id = $1 AND workspace_id = $2"
     )
     .bind(job_id)
+    .bind(member.workspace_id)
     .fetch_optional(db_pool.get_ref())
     .await
     {
@@ -104,13 +224,28 @@ pub async fn get_job_by_id(path: web::Path<Uuid>, db_pool: web::Data<PgPool>) ->
                 file_path: row.get("file_path"),
                 s3_key: row.get("s3_key"),
                 status: row.get("status"),
+                priority: row.get("priority"),
                 error_message: row.get("error_message"),
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
                 completed_at: row.get("completed_at"),
             };
 
-            HttpResponse::Ok().json(job)
+            let result = row
+                .get::<Option<i32>, _>("exact_duplicates")
+                .map(|exact_duplicates| JobResultSummary {
+                    exact_duplicates,
+                    canonical_duplicates: row
+                        .get::<Option<i32>, _>("canonical_duplicates")
+                        .unwrap_or(0),
+                    similar_files: row.get::<Option<i32>, _>("similar_files").unwrap_or(0),
+                    cluster_id: row.get("cluster_id"),
+                    storage_saved_bytes: row
+                        .get::<Option<i64>, _>("storage_saved_bytes")
+                        .unwrap_or(0),
+                });
+
+            HttpResponse::Ok().json(JobDetails { job, result })
         }
         Ok(None) => HttpResponse::NotFound().json("Job not found"),
         Err(e) => {
@@ -121,19 +256,28 @@ pub async fn get_job_by_id(path: web::Path<Uuid>, db_pool: web::Data<PgPool>) ->
 }
 
 #[delete("/jobs/{job_id}")]
-pub async fn delete_job(path: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn delete_job(
+    member: WorkspaceMember,
+    path: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if let Err(response) = member.authorize(Permission::ManageJobs) {
+        return response;
+    }
     let job_id = path.into_inner();
 
     // First check if the job exists
-    match sqlx::query("SELECT job_id FROM jobs WHERE job_id = $1")
+    match sqlx::query("SELECT job_id FROM jobs WHERE job_id = $1 AND workspace_id = $2")
         .bind(job_id)
+        .bind(member.workspace_id)
         .fetch_optional(db_pool.get_ref())
         .await
     {
         Ok(Some(_)) => {
             // Job exists, proceed with deletion
-            match sqlx::query("DELETE FROM jobs WHERE job_id = $1")
+            match sqlx::query("DELETE FROM jobs WHERE job_id = $1 AND workspace_id = $2")
                 .bind(job_id)
+                .bind(member.workspace_id)
                 .execute(db_pool.get_ref())
                 .await
             {
@@ -166,6 +310,7 @@ pub async fn delete_job(path: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> im
 }
 
 /// Create a new job record in the database
+#[allow(clippy::too_many_arguments)]
 pub async fn create_job_record(
     db_pool: &PgPool,
     job_id: Uuid,
@@ -173,16 +318,22 @@ pub async fn create_job_record(
     file_name: &str,
     file_path: Option<&str>,
     s3_key: &str,
+    priority: JobPriority,
+    owner_id: Uuid,
+    workspace_id: Uuid,
 ) -> Result<(), sqlx::Error> {
     sqlx::query(
-        "INSERT INTO jobs (job_id, file_id, file_name, file_path, s3_key, status) 
-         VALUES ($1, $2, $3, $4, $5, 'pending')",
+        "INSERT INTO jobs (job_id, file_id, file_name, file_path, s3_key, status, priority, owner_id, workspace_id) 
+         VALUES ($1, $2, $3, $4, $5, 'pending', $6, $7, $8)",
     )
     .bind(job_id)
     .bind(file_id)
     .bind(file_name)
     .bind(file_path)
     .bind(s3_key)
+    .bind(priority.as_str())
+    .bind(owner_id)
+    .bind(workspace_id)
     .execute(db_pool)
     .await?;
 
@@ -215,3 +366,26 @@ pub async fn update_job_status_in_db(
 
     Ok(())
 }
+
+/// Store the result summary of a completed job in the database
+pub async fn update_job_result_in_db(
+    db_pool: &PgPool,
+    job_id: Uuid,
+    summary: &JobResultSummary,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE jobs SET exact_duplicates = $1, canonical_duplicates = $2, similar_files = $3, 
+         cluster_id = $4, storage_saved_bytes = $5, updated_at = NOW() 
+         WHERE job_id = $6",
+    )
+    .bind(summary.exact_duplicates)
+    .bind(summary.canonical_duplicates)
+    .bind(summary.similar_files)
+    .bind(summary.cluster_id)
+    .bind(summary.storage_saved_bytes)
+    .bind(job_id)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
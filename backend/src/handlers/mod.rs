@@ -1,5 +1,10 @@
+pub mod accounts;
+pub mod api_keys;
 pub mod auth;
+pub mod clusters;
 pub mod files;
 pub mod health;
 pub mod jobs;
+pub mod storage;
 pub mod websocket;
+pub mod workspaces;
@@ -0,0 +1,35 @@
+use crate::config::{Config, StorageBackend};
+use crate::services::storage::{LocalStorage, LocalUploadQuery, StorageError};
+use actix_web::{HttpResponse, Responder, http::header, web};
+
+/// Largest body accepted by the local upload endpoint. Clients upload in 5MB
+/// parts, so this leaves plenty of headroom.
+pub const MAX_LOCAL_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Receives uploads sent to presigned URLs issued by the local storage backend,
+/// standing in for S3 when `STORAGE_BACKEND=local`
+pub async fn upload_local_object(
+    query: web::Query<LocalUploadQuery>,
+    body: web::Bytes,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if config.storage_backend != StorageBackend::Local {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let storage = LocalStorage::from_config(&config);
+    if !storage.verify_presigned(&query) {
+        return HttpResponse::Forbidden().json("Invalid or expired upload URL");
+    }
+
+    match storage.accept_presigned_upload(&query, body).await {
+        Ok(e_tag) => HttpResponse::Ok()
+            .insert_header((header::ETAG, e_tag))
+            .finish(),
+        Err(StorageError::NotFound) => HttpResponse::NotFound().json("Upload not found"),
+        Err(e) => {
+            log::error!("Failed to store local upload for {}: {:?}", query.key, e);
+            HttpResponse::InternalServerError().json("Error storing upload")
+        }
+    }
+}
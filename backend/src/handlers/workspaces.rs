@@ -0,0 +1,212 @@
+use crate::middleware::AuthenticatedUser;
+use crate::services::workspaces::{
+    WorkspaceError, WorkspaceRole, add_member, create_workspace, member_role, remove_member,
+    update_member_role, user_workspaces, workspace_members,
+};
+use actix_web::{HttpResponse, Responder, delete, get, post, put, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateWorkspaceRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddMemberRequest {
+    pub email: String,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: WorkspaceRole,
+}
+
+#[post("/workspaces")]
+pub async fn create_workspace_handler(
+    user: AuthenticatedUser,
+    req_body: web::Json<CreateWorkspaceRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let name = req_body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json("Workspace name is required");
+    }
+
+    match create_workspace(db_pool.get_ref(), name, user.user_id).await {
+        Ok(workspace_id) => HttpResponse::Created().json(serde_json::json!({
+            "workspace_id": workspace_id,
+            "name": name,
+            "role": WorkspaceRole::Owner
+        })),
+        Err(e) => {
+            log::error!("Failed to create workspace: {}", e);
+            HttpResponse::InternalServerError().json("Failed to create workspace")
+        }
+    }
+}
+
+/// Workspaces the user is a member of, with their role in each
+#[get("/workspaces")]
+pub async fn get_workspaces(user: AuthenticatedUser, db_pool: web::Data<PgPool>) -> impl Responder {
+    match user_workspaces(db_pool.get_ref(), user.user_id).await {
+        Ok(workspaces) => HttpResponse::Ok().json(serde_json::json!({ "workspaces": workspaces })),
+        Err(e) => {
+            log::error!("Failed to fetch workspaces: {}", e);
+            HttpResponse::InternalServerError().json("Failed to fetch workspaces")
+        }
+    }
+}
+
+#[get("/workspaces/{workspace_id}/members")]
+pub async fn get_workspace_members(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let workspace_id = path.into_inner();
+
+    if let Err(e) = caller_role(&db_pool, workspace_id, &user).await {
+        return workspace_error_response(e);
+    }
+
+    match workspace_members(db_pool.get_ref(), workspace_id).await {
+        Ok(members) => HttpResponse::Ok().json(serde_json::json!({ "members": members })),
+        Err(e) => workspace_error_response(e.into()),
+    }
+}
+
+#[post("/workspaces/{workspace_id}/members")]
+pub async fn add_workspace_member(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    req_body: web::Json<AddMemberRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let workspace_id = path.into_inner();
+
+    let result = match caller_role(&db_pool, workspace_id, &user).await {
+        Ok(role) => {
+            add_member(
+                db_pool.get_ref(),
+                workspace_id,
+                role,
+                &req_body.email,
+                req_body.role,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(user_id) => HttpResponse::Created().json(serde_json::json!({
+            "workspace_id": workspace_id,
+            "user_id": user_id,
+            "role": req_body.role
+        })),
+        Err(e) => workspace_error_response(e),
+    }
+}
+
+#[put("/workspaces/{workspace_id}/members/{user_id}")]
+pub async fn update_workspace_member(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    req_body: web::Json<UpdateMemberRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let (workspace_id, member_id) = path.into_inner();
+
+    let result = match caller_role(&db_pool, workspace_id, &user).await {
+        Ok(role) => {
+            update_member_role(
+                db_pool.get_ref(),
+                workspace_id,
+                role,
+                member_id,
+                req_body.role,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({
+            "workspace_id": workspace_id,
+            "user_id": member_id,
+            "role": req_body.role
+        })),
+        Err(e) => workspace_error_response(e),
+    }
+}
+
+/// Remove a member, or leave the workspace when the id is the caller's own
+#[delete("/workspaces/{workspace_id}/members/{user_id}")]
+pub async fn remove_workspace_member(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let (workspace_id, member_id) = path.into_inner();
+
+    let result = match caller_role(&db_pool, workspace_id, &user).await {
+        Ok(role) => {
+            remove_member(
+                db_pool.get_ref(),
+                workspace_id,
+                user.user_id,
+                role,
+                member_id,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Member removed",
+            "workspace_id": workspace_id,
+            "user_id": member_id
+        })),
+        Err(e) => workspace_error_response(e),
+    }
+}
+
+/// The caller's role in the workspace. Workspaces they aren't a member of
+/// are reported as not found.
+async fn caller_role(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    user: &AuthenticatedUser,
+) -> Result<WorkspaceRole, WorkspaceError> {
+    member_role(db_pool, workspace_id, user.user_id)
+        .await?
+        .ok_or(WorkspaceError::NotFound)
+}
+
+fn workspace_error_response(error: WorkspaceError) -> HttpResponse {
+    match error {
+        WorkspaceError::NotFound => HttpResponse::NotFound().json("Not found"),
+        WorkspaceError::Forbidden => {
+            HttpResponse::Forbidden().json("Your role can't make this change")
+        }
+        WorkspaceError::AlreadyMember => {
+            HttpResponse::Conflict().json("User is already a member of the workspace")
+        }
+        WorkspaceError::LastOwner => {
+            HttpResponse::Conflict().json("The workspace must keep at least one owner")
+        }
+        WorkspaceError::PersonalWorkspace => {
+            HttpResponse::BadRequest().json("Personal workspaces can't have other members")
+        }
+        WorkspaceError::Database(e) => {
+            log::error!("Failed to update workspace: {}", e);
+            HttpResponse::InternalServerError().json("Failed to update workspace")
+        }
+    }
+}
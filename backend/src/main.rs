@@ -8,24 +8,60 @@ mod services;
 mod worker;
 
 use actix_cors::Cors;
-use actix_web::{App, HttpServer, middleware::Logger, web};
+use actix_web::{App, HttpServer, http::header, middleware::Logger, web};
 use sqlx::PgPool;
 use std::sync::{Arc, Mutex};
 
 use env_logger;
-use handlers::auth::{login, register_user};
-use handlers::files::{complete_upload, generate_presigned_url, initiate_upload};
+use handlers::accounts::{delete_me, get_me, update_email_address, update_password};
+use handlers::api_keys::{create_api_key_handler, get_api_keys, revoke_api_key_handler};
+use handlers::auth::{login, logout_user, refresh, register_user};
+use handlers::clusters::{get_cluster_by_id, get_clusters, resolve_cluster};
+use handlers::files::{
+    complete_upload, generate_presigned_url, get_file_duplicates, initiate_upload,
+    resolve_duplicate, restore_file,
+};
 use handlers::health::{health_check, metrics_test};
-use handlers::jobs::{delete_job, get_job_by_id, get_jobs};
+use handlers::jobs::{delete_job, get_dead_jobs, get_job_by_id, get_jobs, requeue_dead_job};
+use handlers::storage::{MAX_LOCAL_UPLOAD_BYTES, upload_local_object};
 use handlers::websocket::{ConnectionManager, websocket_handler};
+use handlers::workspaces::{
+    add_workspace_member, create_workspace_handler, get_workspace_members, get_workspaces,
+    remove_workspace_member, update_workspace_member,
+};
 use metrics::{BusinessMetrics, DeduplicationMetrics};
 use middleware::Auth;
 use observability::init_observability;
+use services::storage::create_storage;
 use worker::{JobQueue, spawn_worker_process};
 
+/// What this process runs, chosen by the first argument: nothing runs the API
+/// with an embedded worker, `api` only the API and `worker` only a worker, so
+/// the two can be scaled independently
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    All,
+    Api,
+    Worker,
+}
+
+impl Mode {
+    fn from_args() -> Result<Self, String> {
+        match std::env::args().nth(1).as_deref() {
+            None => Ok(Mode::All),
+            Some("api") => Ok(Mode::Api),
+            Some("worker") => Ok(Mode::Worker),
+            Some(other) => Err(format!(
+                "Unknown command '{}', expected 'api' or 'worker'",
+                other
+            )),
+        }
+    }
+}
+
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("Starting HTTP server at http://localhost:8080");
+    let mode = Mode::from_args()?;
 
     // Initialize observability (tracing and metrics)
     init_observability()?;
@@ -42,6 +78,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     sqlx::migrate!("./src/migrations").run(&pool).await?;
 
+    // Initialize object storage (S3 or local disk, depending on config)
+    let storage = create_storage(&env_variables).await;
+
+    log::info!(
+        "🗄️ Object storage initialized: {:?}",
+        env_variables.storage_backend
+    );
+
+    if mode == Mode::Worker {
+        let worker = spawn_worker_process(pool, &env_variables, storage, None).await?;
+        log::info!("Worker process started");
+
+        shutdown_signal().await;
+        log::info!("Shutdown signal received, draining jobs");
+        worker.shutdown().await?;
+        return Ok(());
+    }
+
     // Initialize metrics
     let dedup_metrics = Arc::new(DeduplicationMetrics::new());
     let business_metrics = Arc::new(BusinessMetrics::new());
@@ -57,31 +111,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     log::info!("🔌 WebSocket system initialized");
 
     // Start the worker process
-    spawn_worker_process(
-        pool.clone(),
-        env_variables.redis_url.clone(),
-        env_variables.opensearch_url.clone(),
-        env_variables.aws_profile_name.clone(),
-        env_variables.bedrock_model_id.clone(),
-        Some(connection_manager.clone()),
-    )
-    .await?;
-
-    log::info!("Worker process started");
+    let worker = if mode == Mode::All {
+        let worker = spawn_worker_process(
+            pool.clone(),
+            &env_variables,
+            storage.clone(),
+            Some(connection_manager.clone()),
+        )
+        .await?;
+
+        log::info!("Worker process started");
+        Some(worker)
+    } else {
+        None
+    };
+
+    println!("Starting HTTP server at http://localhost:8080");
 
     let dedup_metrics_clone = dedup_metrics.clone();
     dedup_metrics.record_file_processed("image", 233);
     let business_metrics_clone = business_metrics.clone();
     let connection_manager_clone = connection_manager.clone();
     let job_queue_clone = job_queue.clone();
+    let storage_clone = storage.clone();
+    let shutdown_timeout = env_variables.shutdown_timeout_secs;
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(
                 Cors::default()
                     .allowed_origin("http://localhost:3000")
                     .allow_any_method()
                     .allow_any_header()
+                    .expose_headers(vec![header::ETAG])
                     .supports_credentials(),
             )
             .app_data(web::Data::new(pool.clone()))
@@ -90,26 +152,107 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             .app_data(web::Data::new(business_metrics_clone.clone()))
             .app_data(web::Data::new(connection_manager_clone.clone()))
             .app_data(web::Data::new(job_queue_clone.clone()))
+            .app_data(web::Data::new(storage_clone.clone()))
             .service(health_check)
             .service(metrics_test)
             .service(login)
             .service(register_user)
+            .service(refresh)
             .route("/ws", web::get().to(websocket_handler))
+            // Presigned uploads for the local storage backend carry their own signature
+            .service(
+                web::resource("/storage/upload")
+                    .app_data(web::PayloadConfig::new(MAX_LOCAL_UPLOAD_BYTES))
+                    .route(web::put().to(upload_local_object)),
+            )
             .service(
                 web::scope("")
                     .wrap(Auth::new(env_variables.jwt_secret.clone()))
+                    .service(logout_user)
+                    .service(get_me)
+                    .service(update_password)
+                    .service(update_email_address)
+                    .service(delete_me)
+                    .service(create_api_key_handler)
+                    .service(get_api_keys)
+                    .service(revoke_api_key_handler)
                     .service(initiate_upload)
                     .service(complete_upload)
                     .service(generate_presigned_url)
+                    .service(get_file_duplicates)
+                    .service(get_clusters)
+                    .service(get_cluster_by_id)
+                    .service(resolve_cluster)
+                    .service(resolve_duplicate)
+                    .service(restore_file)
                     .service(get_jobs)
+                    // Registered before /jobs/{job_id}, which would otherwise match "dead"
+                    .service(get_dead_jobs)
+                    .service(requeue_dead_job)
                     .service(get_job_by_id)
-                    .service(delete_job),
+                    .service(delete_job)
+                    .service(create_workspace_handler)
+                    .service(get_workspaces)
+                    .service(get_workspace_members)
+                    .service(add_workspace_member)
+                    .service(update_workspace_member)
+                    .service(remove_workspace_member),
             )
             // enable logger - always register Actix Web Logger middleware last
             .wrap(Logger::default())
     })
     .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
-    .map_err(Into::into)
+    .shutdown_timeout(shutdown_timeout)
+    // Signals are handled below, so the server and the worker stop together
+    .disable_signals()
+    .run();
+
+    let server_handle = server.handle();
+    let shutdown = tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutdown signal received, stopping server and worker");
+
+        let stop_worker = async {
+            if let Some(worker) = worker
+                && let Err(e) = worker.shutdown().await
+            {
+                log::error!("Worker did not shut down cleanly: {}", e);
+            }
+        };
+        tokio::join!(server_handle.stop(true), stop_worker);
+    });
+
+    server.await?;
+    shutdown.await?;
+    Ok(())
+}
+
+/// Resolves on SIGINT (Ctrl+C) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
 }
@@ -15,6 +15,7 @@ pub struct DeduplicationMetrics {
     pub active_jobs: Gauge<i64>,
     pub queue_size: Gauge<i64>,
     pub failed_jobs_total: Counter<u64>,
+    pub retried_jobs_total: Counter<u64>,
     pub opensearch_errors_total: Counter<u64>,
     pub s3_errors_total: Counter<u64>,
 
@@ -71,6 +72,11 @@ impl DeduplicationMetrics {
                 .with_description("Total number of failed deduplication jobs")
                 .build(),
 
+            retried_jobs_total: meter
+                .u64_counter("retried_jobs_total")
+                .with_description("Total number of deduplication job retries scheduled")
+                .build(),
+
             opensearch_errors_total: meter
                 .u64_counter("opensearch_errors_total")
                 .with_description("Total number of OpenSearch errors")
@@ -159,6 +165,13 @@ impl DeduplicationMetrics {
         log::warn!("❌ Job failed: {}", error_type);
     }
 
+    /// Record a retry scheduled for a failed job - increment with error type label
+    pub fn record_job_retry(&self, error_type: &str) {
+        self.retried_jobs_total
+            .add(1, &[KeyValue::new("error_type", error_type.to_string())]);
+        log::warn!("🔁 Job retry scheduled: {}", error_type);
+    }
+
     /// Record OpenSearch error
     pub fn record_opensearch_error(&self, operation: &str) {
         self.opensearch_errors_total
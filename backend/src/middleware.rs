@@ -1,13 +1,154 @@
 use actix_web::{
-    Error, HttpResponse,
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
     body::{BoxBody, EitherBody},
-    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
-    http::StatusCode,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    http::{Method, StatusCode},
+    web,
 };
 use futures_util::future::LocalBoxFuture;
+use sqlx::PgPool;
 use std::future::{Ready, ready};
+use std::rc::Rc;
+use uuid::Uuid;
 
-use crate::services::auth::{AuthError, verify_jwt_token};
+use crate::services::api_keys::{API_KEY_PREFIX, ApiKeyGrant, ApiKeyScope, authenticate_api_key};
+use crate::services::auth::{AuthError, Claims, is_token_revoked, verify_jwt_token};
+use crate::services::workspaces::{Permission, WorkspaceRole, member_role, personal_workspace_id};
+
+/// Header selecting the workspace a request acts in, defaulting to the
+/// user's personal workspace
+pub const WORKSPACE_HEADER: &str = "X-Workspace-Id";
+
+/// The user a request was made by, set by `Auth` from the request's token.
+/// Requests made with an API key carry an `ApiKeyGrant` instead, and can't
+/// use handlers that take this.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    /// Id and expiry of the access token, for revoking it
+    pub token_id: Uuid,
+    pub token_expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let extensions = req.extensions();
+        ready(match extensions.get::<AuthenticatedUser>() {
+            Some(user) => Ok(user.clone()),
+            None if extensions.contains::<ApiKeyGrant>() => Err(ErrorForbidden(
+                "API keys can't be used here, log in instead",
+            )),
+            None => Err(ErrorUnauthorized("Unauthorized")),
+        })
+    }
+}
+
+/// The user a request was made by and their role in the workspace it acts
+/// in. Handlers take it as an argument to scope what they read and change to
+/// the workspace's files, jobs and clusters.
+#[derive(Debug, Clone)]
+pub struct WorkspaceMember {
+    pub user_id: Uuid,
+    pub workspace_id: Uuid,
+    pub role: WorkspaceRole,
+    /// Scopes of the API key the request was made with, if any
+    pub api_key_scopes: Option<Vec<ApiKeyScope>>,
+}
+
+impl WorkspaceMember {
+    /// A 403 response unless the member's role, and the API key's scopes,
+    /// allow `permission`
+    pub fn authorize(&self, permission: Permission) -> Result<(), HttpResponse> {
+        if !self.role.allows(permission) {
+            return Err(HttpResponse::Forbidden().json(format!(
+                "The {} role can't do this in the workspace",
+                self.role.as_str()
+            )));
+        }
+
+        if let Some(scopes) = &self.api_key_scopes {
+            match ApiKeyScope::required_for(permission) {
+                Some(scope) if scopes.contains(&scope) => {}
+                Some(scope) => {
+                    return Err(HttpResponse::Forbidden()
+                        .json(format!("The API key lacks the {} scope", scope.as_str())));
+                }
+                None => {
+                    return Err(HttpResponse::Forbidden().json("API keys can't do this"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for WorkspaceMember {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user_id = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.user_id);
+        let api_key = req.extensions().get::<ApiKeyGrant>().cloned();
+        let reading = req.method() == Method::GET;
+        let db_pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let workspace_id = req.headers().get(WORKSPACE_HEADER).map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| Uuid::parse_str(value).ok())
+        });
+
+        Box::pin(async move {
+            let user_id = user_id
+                .or(api_key.as_ref().map(|api_key| api_key.user_id))
+                .ok_or_else(|| ErrorUnauthorized("Unauthorized"))?;
+            let api_key_scopes = api_key.map(|api_key| api_key.scopes);
+            // Every read needs the read scope, changes are checked by `authorize`
+            if reading
+                && api_key_scopes
+                    .as_ref()
+                    .is_some_and(|scopes| !scopes.contains(&ApiKeyScope::Read))
+            {
+                return Err(ErrorForbidden("The API key lacks the read scope"));
+            }
+            let db_pool = db_pool.ok_or_else(|| ErrorInternalServerError("No database"))?;
+
+            let workspace_id = match workspace_id {
+                Some(Some(workspace_id)) => workspace_id,
+                Some(None) => return Err(ErrorBadRequest("Invalid X-Workspace-Id header")),
+                None => personal_workspace_id(&db_pool, user_id)
+                    .await
+                    .map_err(|e| {
+                        log::error!("Failed to find personal workspace: {}", e);
+                        ErrorInternalServerError("Failed to find workspace")
+                    })?
+                    .ok_or_else(|| ErrorUnauthorized("User not found"))?,
+            };
+
+            let role = member_role(&db_pool, workspace_id, user_id)
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to fetch workspace membership: {}", e);
+                    ErrorInternalServerError("Failed to find workspace")
+                })?
+                .ok_or_else(|| ErrorForbidden("Not a member of this workspace"))?;
+
+            Ok(WorkspaceMember {
+                user_id,
+                workspace_id,
+                role,
+                api_key_scopes,
+            })
+        })
+    }
+}
 
 pub struct Auth {
     jwt_secret: String,
@@ -34,14 +175,14 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddleware {
-            service,
+            service: Rc::new(service),
             jwt_secret: self.jwt_secret.clone(),
         }))
     }
 }
 
 pub struct AuthMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     jwt_secret: String,
 }
 
@@ -61,7 +202,7 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let jwt_secret = self.jwt_secret.clone();
 
-        // Extract and verify the authorization token
+        // Extract the API key or verify the JWT
         let auth_result = req
             .headers()
             .get("Authorization")
@@ -73,47 +214,95 @@ where
                 } else {
                     token
                 };
-                verify_jwt_token(token, &jwt_secret)
+                if token.starts_with(API_KEY_PREFIX) {
+                    Credential::ApiKey(token.to_string())
+                } else {
+                    Credential::Jwt(verify_jwt_token(token, &jwt_secret))
+                }
             });
 
         match auth_result {
-            Some(Ok(_claims)) => {
-                // Authorized → call next service and map into Left
-                let fut = self.service.call(req);
+            Some(Credential::ApiKey(key)) => {
+                let service = self.service.clone();
+                let db_pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+                Box::pin(async move {
+                    let db_pool = db_pool.ok_or_else(|| ErrorInternalServerError("No database"))?;
+                    let grant = authenticate_api_key(&db_pool, &key).await.map_err(|e| {
+                        log::error!("Failed to check API key: {}", e);
+                        ErrorInternalServerError("Failed to verify API key")
+                    })?;
+                    let Some(grant) = grant else {
+                        return Ok(unauthorized(req, "Invalid or revoked API key"));
+                    };
+
+                    req.extensions_mut().insert(grant);
+                    let res = service.call(req).await?;
+                    Ok(res.map_into_left_body())
+                })
+            }
+            Some(Credential::Jwt(Ok(claims))) => {
+                let service = self.service.clone();
+                let db_pool = req.app_data::<web::Data<PgPool>>().cloned();
+
                 Box::pin(async move {
-                    let res = fut.await?;
+                    // Logged out tokens stay valid until they expire, so check them
+                    let db_pool = db_pool.ok_or_else(|| ErrorInternalServerError("No database"))?;
+                    let revoked = is_token_revoked(&db_pool, &claims).await.map_err(|e| {
+                        log::error!("Failed to check token revocation: {}", e);
+                        ErrorInternalServerError("Failed to verify token")
+                    })?;
+                    if revoked {
+                        return Ok(unauthorized(req, "JWT token has been revoked"));
+                    }
+
+                    // Authorized → make the caller available to handlers, call next
+                    // service and map into Left
+                    req.extensions_mut().insert(AuthenticatedUser {
+                        user_id: claims.user_id(),
+                        token_id: claims.token_id(),
+                        token_expires_at: claims.expires_at(),
+                    });
+                    let res = service.call(req).await?;
                     Ok(res.map_into_left_body())
                 })
             }
-            Some(Err(auth_error)) => {
+            Some(Credential::Jwt(Err(auth_error))) => {
                 // Handle specific auth errors with appropriate messages
                 let error_message = match auth_error {
                     AuthError::InvalidToken => "Invalid or malformed JWT token",
                     AuthError::InvalidCredentials => {
                         "JWT token verification failed - invalid signature or expired token"
                     }
-                    AuthError::UserNotFound => "User not found",
+                    AuthError::ExpiredToken => "JWT token has expired",
                     AuthError::TokenGeneration => "Token generation error",
+                    AuthError::Database(_) => "Failed to verify token",
                 };
 
-                let res = req.into_response(HttpResponse::build(StatusCode::UNAUTHORIZED).json(
-                    serde_json::json!({
-                        "error": "Unauthorized",
-                        "message": error_message
-                    }),
-                ));
-                Box::pin(async move { Ok(res.map_into_right_body()) })
+                let res = unauthorized(req, error_message);
+                Box::pin(async move { Ok(res) })
             }
             None => {
                 // No Authorization header provided
-                let res = req.into_response(HttpResponse::build(StatusCode::UNAUTHORIZED).json(
-                    serde_json::json!({
-                        "error": "Unauthorized",
-                        "message": "Authorization header missing"
-                    }),
-                ));
-                Box::pin(async move { Ok(res.map_into_right_body()) })
+                let res = unauthorized(req, "Authorization header missing");
+                Box::pin(async move { Ok(res) })
             }
         }
     }
 }
+
+/// What a request authenticates with
+enum Credential {
+    ApiKey(String),
+    Jwt(Result<Claims, AuthError>),
+}
+
+fn unauthorized<B>(req: ServiceRequest, message: &str) -> ServiceResponse<EitherBody<B, BoxBody>> {
+    req.into_response(
+        HttpResponse::build(StatusCode::UNAUTHORIZED).json(serde_json::json!({
+            "error": "Unauthorized",
+            "message": message
+        })),
+    )
+    .map_into_right_body()
+}
@@ -0,0 +1,3 @@
+-- Exact duplicates share the same SHA-256 hash, so the hash can no longer be unique.
+-- Lookups still go through idx_sha256_hash.
+ALTER TABLE File DROP CONSTRAINT IF EXISTS file_sha256_hash_key;
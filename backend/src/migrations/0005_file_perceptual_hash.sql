@@ -0,0 +1,8 @@
+-- 64-bit perceptual hashes for images, NULL for other files.
+-- Near-duplicate lookups compare Hamming distances with bit_count((phash # $1)::bit(64)).
+ALTER TABLE File ADD COLUMN IF NOT EXISTS ahash BIGINT; -- Average hash
+ALTER TABLE File ADD COLUMN IF NOT EXISTS dhash BIGINT; -- Difference hash
+ALTER TABLE File ADD COLUMN IF NOT EXISTS phash BIGINT; -- DCT hash
+
+-- Hamming distance can't use a b-tree, but this keeps the scan to hashed images only
+CREATE INDEX IF NOT EXISTS idx_file_phash ON File (file_id) WHERE phash IS NOT NULL;
@@ -0,0 +1,16 @@
+-- MinHash signatures of text files, used to estimate Jaccard similarity between candidates
+CREATE TABLE IF NOT EXISTS minhash_signature (
+    file_id INT PRIMARY KEY REFERENCES File(file_id) ON DELETE CASCADE,
+    signature BIGINT[] NOT NULL -- One minimum hash per permutation
+);
+
+-- LSH buckets: files sharing a (band, bucket) pair are near-duplicate candidates
+CREATE TABLE IF NOT EXISTS minhash_band (
+    band INT NOT NULL, -- Index of the band within the signature
+    bucket BIGINT NOT NULL, -- Hash of the signature rows in that band
+    file_id INT NOT NULL REFERENCES File(file_id) ON DELETE CASCADE,
+    PRIMARY KEY (band, bucket, file_id)
+);
+
+-- Lets the worker replace a file's buckets when it is reprocessed
+CREATE INDEX IF NOT EXISTS idx_minhash_band_file_id ON minhash_band (file_id);
@@ -0,0 +1,43 @@
+-- Content-defined chunks shared across files, keyed by the chunk's SHA-256 hash
+CREATE TABLE IF NOT EXISTS chunk (
+    chunk_hash CHAR(64) PRIMARY KEY, -- SHA-256 hash of the chunk bytes
+    chunk_size BIGINT NOT NULL, -- Length of the chunk in bytes
+    ref_count INT NOT NULL DEFAULT 0, -- Number of file_chunk rows referencing this chunk
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Per-file chunk manifest, in file order
+CREATE TABLE IF NOT EXISTS file_chunk (
+    file_id INT NOT NULL REFERENCES File(file_id) ON DELETE CASCADE,
+    chunk_index INT NOT NULL, -- Position of the chunk within the file
+    chunk_hash CHAR(64) NOT NULL REFERENCES chunk(chunk_hash),
+    chunk_offset BIGINT NOT NULL, -- Byte offset of the chunk within the file
+    PRIMARY KEY (file_id, chunk_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_chunk_hash ON file_chunk (chunk_hash);
+
+-- Keep chunk.ref_count in step with the manifests, including rows removed by
+-- the File cascade, and drop chunks once nothing references them
+CREATE OR REPLACE FUNCTION chunk_ref_count_insert() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE chunk SET ref_count = ref_count + 1 WHERE chunk_hash = NEW.chunk_hash;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION chunk_ref_count_delete() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE chunk SET ref_count = ref_count - 1 WHERE chunk_hash = OLD.chunk_hash;
+    DELETE FROM chunk WHERE chunk_hash = OLD.chunk_hash AND ref_count <= 0;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER file_chunk_insert_ref_count
+    AFTER INSERT ON file_chunk
+    FOR EACH ROW EXECUTE FUNCTION chunk_ref_count_insert();
+
+CREATE TRIGGER file_chunk_delete_ref_count
+    AFTER DELETE ON file_chunk
+    FOR EACH ROW EXECUTE FUNCTION chunk_ref_count_delete();
@@ -0,0 +1,5 @@
+-- SHA-256 over normalized content (text with whitespace and Unicode normalized,
+-- image pixels without metadata), NULL when the content can't be normalized
+ALTER TABLE File ADD COLUMN IF NOT EXISTS canonical_hash CHAR(64);
+
+CREATE INDEX IF NOT EXISTS idx_canonical_hash ON File (canonical_hash);
@@ -0,0 +1,21 @@
+-- Duplicate and near-duplicate pairs found by the deduplication worker. Each
+-- row is recorded when file_id is processed; lookups check both columns.
+CREATE TABLE IF NOT EXISTS file_matches (
+    file_id INT NOT NULL REFERENCES File(file_id) ON DELETE CASCADE, -- File that was processed
+    matched_file_id INT NOT NULL REFERENCES File(file_id) ON DELETE CASCADE, -- File it matched
+    match_type VARCHAR(16) NOT NULL, -- 'exact', 'canonical' or 'similar'
+    similarity_score DOUBLE PRECISION NOT NULL, -- 1.0 for exact and canonical matches
+    shared_bytes BIGINT NOT NULL DEFAULT 0, -- Chunk bytes in common, for similar matches
+    job_id UUID REFERENCES jobs(job_id) ON DELETE SET NULL, -- Job that found the match
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (file_id, matched_file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_matches_matched_file_id ON file_matches (matched_file_id);
+
+-- Summary of what a completed job found, NULL until the job completes
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS exact_duplicates INT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS canonical_duplicates INT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS similar_files INT;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS cluster_id INT REFERENCES Cluster(cluster_id) ON DELETE SET NULL;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS storage_saved_bytes BIGINT;
@@ -0,0 +1,12 @@
+-- History of clusters folded into another cluster when a new file matched
+-- members of both. merged_cluster_id no longer exists once the merge is done.
+CREATE TABLE IF NOT EXISTS cluster_merge (
+    merge_id SERIAL PRIMARY KEY,
+    cluster_id INT NOT NULL REFERENCES Cluster(cluster_id) ON DELETE CASCADE, -- Surviving cluster
+    merged_cluster_id INT NOT NULL, -- Cluster that was merged away
+    trigger_file_id INT REFERENCES File(file_id) ON DELETE SET NULL, -- File whose matches bridged the clusters
+    files_moved INT NOT NULL, -- Files reassigned from the merged cluster
+    merged_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cluster_merge_cluster_id ON cluster_merge (cluster_id);
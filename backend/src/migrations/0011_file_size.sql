@@ -0,0 +1,12 @@
+-- Size of the file content in bytes, set by the deduplication worker
+ALTER TABLE File ADD COLUMN IF NOT EXISTS file_size BIGINT;
+
+-- Files processed before this column existed have a chunk manifest to sum
+UPDATE File f SET file_size = sizes.file_size
+FROM (
+    SELECT fc.file_id, SUM(c.chunk_size)::BIGINT AS file_size
+    FROM file_chunk fc
+    JOIN chunk c ON c.chunk_hash = fc.chunk_hash
+    GROUP BY fc.file_id
+) sizes
+WHERE f.file_id = sizes.file_id AND f.file_size IS NULL;
@@ -0,0 +1,28 @@
+-- Object key of the file's content; NULL once the content has been removed
+ALTER TABLE File ADD COLUMN IF NOT EXISTS s3_key VARCHAR(500);
+
+UPDATE File f SET s3_key = latest.s3_key
+FROM (
+    SELECT DISTINCT ON (file_id) file_id, s3_key FROM jobs ORDER BY file_id, created_at DESC
+) latest
+WHERE f.file_id = latest.file_id AND f.s3_key IS NULL;
+
+-- Set when a duplicate's content was replaced with a reference to the kept file
+ALTER TABLE File ADD COLUMN IF NOT EXISTS reference_file_id INT REFERENCES File(file_id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_file_reference_file_id ON File (reference_file_id);
+
+-- One entry per executed resolution action
+CREATE TABLE IF NOT EXISTS resolution_log (
+    resolution_id SERIAL PRIMARY KEY,
+    action VARCHAR(32) NOT NULL, -- 'delete' or 'replace_with_reference'
+    cluster_id INT, -- Cluster that was resolved, NULL for a single duplicate pair
+    keeper_file_id INT NOT NULL, -- File that was kept
+    file_ids INT[] NOT NULL, -- Files that were deleted or replaced
+    file_names TEXT[] NOT NULL,
+    bytes_reclaimed BIGINT NOT NULL,
+    storage_errors TEXT[] NOT NULL DEFAULT '{}', -- Objects that could not be removed from storage
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_resolution_log_created_at ON resolution_log (created_at DESC);
@@ -0,0 +1,5 @@
+-- Set when a file is moved to the trash; the worker purges it once the
+-- retention window has passed
+ALTER TABLE File ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_file_deleted_at ON File (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Priority the job was queued with: 'interactive', 'bulk' or 'reindex'
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS priority VARCHAR(20) NOT NULL DEFAULT 'interactive';
+
+CREATE INDEX IF NOT EXISTS idx_jobs_priority ON jobs (priority);
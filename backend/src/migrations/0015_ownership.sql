@@ -0,0 +1,10 @@
+-- User each file and job belongs to. Rows created before ownership existed
+-- have no owner and stay hidden from every user until assigned one. Clusters
+-- are scoped by their files; see 0016_workspaces.sql.
+ALTER TABLE File ADD COLUMN IF NOT EXISTS owner_id UUID REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS owner_id UUID REFERENCES users(id) ON DELETE CASCADE;
+-- User who carried out the resolution
+ALTER TABLE resolution_log ADD COLUMN IF NOT EXISTS owner_id UUID REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_file_owner_id ON File (owner_id);
+CREATE INDEX IF NOT EXISTS idx_jobs_owner_id ON jobs (owner_id, created_at DESC);
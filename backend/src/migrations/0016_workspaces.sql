@@ -0,0 +1,55 @@
+-- Workspaces group users who deduplicate a shared corpus. Every user also has
+-- a personal workspace, marked by personal_user_id.
+CREATE TABLE IF NOT EXISTS workspaces (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(255) NOT NULL,
+    personal_user_id UUID UNIQUE REFERENCES users(id) ON DELETE CASCADE, -- Set for personal workspaces
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS workspace_members (
+    workspace_id UUID NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR(16) NOT NULL CHECK (role IN ('owner', 'admin', 'reviewer', 'uploader')),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_workspace_members_user_id ON workspace_members (user_id);
+
+-- Files, jobs and clusters belong to a workspace; owner_id stays as the
+-- uploader. Clusters spanning several workspaces, which only cross-tenant
+-- deduplication creates, have no workspace.
+ALTER TABLE File ADD COLUMN IF NOT EXISTS workspace_id UUID REFERENCES workspaces(id) ON DELETE CASCADE;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS workspace_id UUID REFERENCES workspaces(id) ON DELETE CASCADE;
+ALTER TABLE Cluster ADD COLUMN IF NOT EXISTS workspace_id UUID REFERENCES workspaces(id) ON DELETE CASCADE;
+ALTER TABLE resolution_log ADD COLUMN IF NOT EXISTS workspace_id UUID REFERENCES workspaces(id) ON DELETE CASCADE;
+
+-- Existing users get a personal workspace holding what they own
+INSERT INTO workspaces (name, personal_user_id)
+SELECT username, id FROM users
+ON CONFLICT (personal_user_id) DO NOTHING;
+
+INSERT INTO workspace_members (workspace_id, user_id, role)
+SELECT id, personal_user_id, 'owner' FROM workspaces WHERE personal_user_id IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+UPDATE File f SET workspace_id = w.id FROM workspaces w
+WHERE w.personal_user_id = f.owner_id AND f.workspace_id IS NULL;
+UPDATE jobs j SET workspace_id = w.id FROM workspaces w
+WHERE w.personal_user_id = j.owner_id AND j.workspace_id IS NULL;
+-- Clusters belong to the workspace of their files, or none if they span several
+UPDATE Cluster c SET workspace_id = members.workspace_id
+FROM (
+    SELECT cluster_id, MIN(workspace_id::TEXT)::UUID AS workspace_id FROM File
+    WHERE cluster_id IS NOT NULL
+    GROUP BY cluster_id
+    HAVING COUNT(DISTINCT workspace_id) = 1 AND COUNT(workspace_id) = COUNT(*)
+) members
+WHERE members.cluster_id = c.cluster_id AND c.workspace_id IS NULL;
+UPDATE resolution_log r SET workspace_id = w.id FROM workspaces w
+WHERE w.personal_user_id = r.owner_id AND r.workspace_id IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_file_workspace_id ON File (workspace_id);
+CREATE INDEX IF NOT EXISTS idx_jobs_workspace_id ON jobs (workspace_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_cluster_workspace_id ON Cluster (workspace_id);
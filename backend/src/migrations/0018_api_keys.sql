@@ -0,0 +1,15 @@
+-- API keys for machine clients, stored hashed. A key acts as its user,
+-- limited to its scopes.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    prefix VARCHAR(16) NOT NULL, -- Start of the key, to recognize it by
+    key_hash VARCHAR(64) UNIQUE NOT NULL, -- SHA256 of the key
+    scopes TEXT[] NOT NULL, -- upload, read and/or resolve
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys (user_id);
@@ -0,0 +1,3 @@
+-- Access tokens issued before this are rejected, so a password change ends
+-- every login session at once
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_valid_after TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,481 @@
+use crate::database::users::{
+    User, create_user, get_password_hash, get_user_by_id, get_user_with_password_hash,
+    update_email, update_password_hash,
+};
+use crate::services::auth::revoke_user_sessions;
+use crate::services::resolution::{remove_embeddings, remove_objects, unused_object_keys};
+use crate::services::storage::ObjectStorage;
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+// Accounts are the only place passwords are hashed and checked:
+// 1. Registering validates the details and stores a bcrypt hash of the password.
+// 2. Changing the password or email takes the current password. A new
+//    password also ends the user's other login sessions.
+// 3. Deleting an account purges the files the user uploaded and the contents
+//    of workspaces only they belong to, like the trash sweeper does, then the
+//    user along with their memberships and tokens. Uploads that other
+//    members' resolved duplicates refer to hold those files' content, so they
+//    stay in their workspace without an uploader.
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidUsername,
+    InvalidEmail,
+    WeakPassword,
+    InvalidCredentials,
+    NotFound,
+    /// The username or email is taken
+    AlreadyExists,
+    /// The user is the last owner of a workspace that has other members
+    LastOwner,
+    Hashing,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AccountError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+                AccountError::AlreadyExists
+            }
+            e => AccountError::Database(e),
+        }
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), AccountError> {
+    // Username: 3-32 chars
+    if username.len() < 3 || username.len() > 32 {
+        return Err(AccountError::InvalidUsername);
+    }
+    Ok(())
+}
+
+fn validate_email(email: &str) -> Result<(), AccountError> {
+    // Email: basic check for '@'
+    if !email.contains('@') || !email.contains('.') {
+        return Err(AccountError::InvalidEmail);
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), AccountError> {
+    // Password: at least 8 chars
+    if password.len() < 8 {
+        return Err(AccountError::WeakPassword);
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, AccountError> {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|_| AccountError::Hashing)
+}
+
+pub async fn register(
+    pool: &PgPool,
+    username: &str,
+    email: &str,
+    password: &str,
+) -> Result<User, AccountError> {
+    validate_username(username)?;
+    validate_email(email)?;
+    validate_password(password)?;
+
+    Ok(create_user(pool, username, email, &hash_password(password)?).await?)
+}
+
+/// The user with this email, if the password is theirs
+pub async fn authenticate(
+    pool: &PgPool,
+    email: &str,
+    password: &str,
+) -> Result<User, AccountError> {
+    let (user, password_hash) = get_user_with_password_hash(pool, email)
+        .await?
+        .ok_or(AccountError::InvalidCredentials)?;
+    if !bcrypt::verify(password, &password_hash).unwrap_or(false) {
+        return Err(AccountError::InvalidCredentials);
+    }
+
+    Ok(user)
+}
+
+pub async fn get_account(pool: &PgPool, user_id: Uuid) -> Result<User, AccountError> {
+    get_user_by_id(pool, user_id)
+        .await?
+        .ok_or(AccountError::NotFound)
+}
+
+async fn verify_password(pool: &PgPool, user_id: Uuid, password: &str) -> Result<(), AccountError> {
+    let password_hash = get_password_hash(pool, user_id)
+        .await?
+        .ok_or(AccountError::NotFound)?;
+    if !bcrypt::verify(password, &password_hash).unwrap_or(false) {
+        return Err(AccountError::InvalidCredentials);
+    }
+    Ok(())
+}
+
+pub async fn change_password(
+    pool: &PgPool,
+    user_id: Uuid,
+    current_password: &str,
+    new_password: &str,
+) -> Result<(), AccountError> {
+    validate_password(new_password)?;
+    verify_password(pool, user_id, current_password).await?;
+
+    update_password_hash(pool, user_id, &hash_password(new_password)?).await?;
+    revoke_user_sessions(pool, user_id).await?;
+    Ok(())
+}
+
+pub async fn change_email(
+    pool: &PgPool,
+    user_id: Uuid,
+    password: &str,
+    email: &str,
+) -> Result<User, AccountError> {
+    validate_email(email)?;
+    verify_password(pool, user_id, password).await?;
+
+    update_email(pool, user_id, email).await?;
+    get_account(pool, user_id).await
+}
+
+/// Delete the account and everything only it holds, returning how many files
+/// were purged
+pub async fn delete_account(
+    pool: &PgPool,
+    storage: &dyn ObjectStorage,
+    opensearch_url: &str,
+    user_id: Uuid,
+    password: &str,
+) -> Result<usize, AccountError> {
+    verify_password(pool, user_id, password).await?;
+
+    let mut transaction = pool.begin().await?;
+
+    // Lock the memberships so nobody joins or leaves the workspaces meanwhile
+    let rows = sqlx::query(
+        "SELECT m.workspace_id, m.user_id, m.role, w.personal_user_id \
+         FROM workspace_members m JOIN workspaces w ON w.id = m.workspace_id \
+         WHERE m.workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = $1) \
+         FOR UPDATE OF m",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *transaction)
+    .await?;
+    let memberships: Vec<Membership> = rows
+        .iter()
+        .map(|row| Membership {
+            workspace_id: row.get("workspace_id"),
+            user_id: row.get("user_id"),
+            owner: row.get::<String, _>("role") == "owner",
+            personal: row.get::<Option<Uuid>, _>("personal_user_id").is_some(),
+        })
+        .collect();
+    let plan = plan_workspace_cleanup(user_id, &memberships)?;
+
+    let kept = sqlx::query(
+        "UPDATE File k SET owner_id = NULL \
+         WHERE k.owner_id = $1 AND k.workspace_id <> ALL($2) \
+           AND EXISTS (SELECT 1 FROM File r WHERE r.reference_file_id = k.file_id \
+                       AND r.owner_id IS DISTINCT FROM $1 AND r.workspace_id <> ALL($2))",
+    )
+    .bind(user_id)
+    .bind(&plan.removed_workspaces)
+    .execute(&mut *transaction)
+    .await?;
+
+    // Rows cascade to matches, chunk manifests and signatures
+    let rows = sqlx::query(
+        "DELETE FROM File WHERE owner_id = $1 OR workspace_id = ANY($2) \
+         RETURNING file_id, s3_key",
+    )
+    .bind(user_id)
+    .bind(&plan.removed_workspaces)
+    .fetch_all(&mut *transaction)
+    .await?;
+    let file_ids: Vec<i32> = rows.iter().map(|row| row.get("file_id")).collect();
+    let s3_keys: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.get::<Option<String>, _>("s3_key"))
+        .collect();
+    let unused_keys = unused_object_keys(&mut transaction, &s3_keys).await?;
+
+    sqlx::query("DELETE FROM jobs WHERE owner_id = $1 OR workspace_id = ANY($2)")
+        .bind(user_id)
+        .bind(&plan.removed_workspaces)
+        .execute(&mut *transaction)
+        .await?;
+
+    // Clusters and memberships go with their workspaces, and refresh tokens
+    // with the user
+    sqlx::query("DELETE FROM workspaces WHERE id = ANY($1)")
+        .bind(&plan.removed_workspaces)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    // The rows are gone either way; an object left behind is only wasted space
+    remove_objects(storage, &unused_keys).await;
+    remove_embeddings(opensearch_url, &file_ids).await;
+
+    log::info!(
+        "Deleted account {} and purged {} files, keeping {} referenced by other members",
+        user_id,
+        file_ids.len(),
+        kept.rows_affected()
+    );
+    Ok(file_ids.len())
+}
+
+struct Membership {
+    workspace_id: Uuid,
+    user_id: Uuid,
+    owner: bool,
+    personal: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct WorkspaceCleanup {
+    /// The personal workspace and shared ones nobody else belongs to
+    removed_workspaces: Vec<Uuid>,
+}
+
+/// Decide which of the user's workspaces go with their account. Shared
+/// workspaces with other members stay, as long as one of those is an owner.
+fn plan_workspace_cleanup(
+    user_id: Uuid,
+    memberships: &[Membership],
+) -> Result<WorkspaceCleanup, AccountError> {
+    let mut removed_workspaces = Vec::new();
+
+    for membership in memberships.iter().filter(|m| m.user_id == user_id) {
+        let others: Vec<&Membership> = memberships
+            .iter()
+            .filter(|m| m.workspace_id == membership.workspace_id && m.user_id != user_id)
+            .collect();
+
+        if membership.personal || others.is_empty() {
+            removed_workspaces.push(membership.workspace_id);
+        } else if membership.owner && !others.iter().any(|m| m.owner) {
+            return Err(AccountError::LastOwner);
+        }
+    }
+
+    Ok(WorkspaceCleanup { removed_workspaces })
+}
+
+#[cfg(test)]
+mod accounts_test {
+    use super::*;
+    use crate::services::storage::local::LocalStorage;
+    use crate::services::workspaces::{WorkspaceRole, add_member, create_workspace};
+
+    fn membership(workspace_id: Uuid, user_id: Uuid, owner: bool, personal: bool) -> Membership {
+        Membership {
+            workspace_id,
+            user_id,
+            owner,
+            personal,
+        }
+    }
+
+    #[test]
+    fn test_account_details_validation() {
+        assert!(validate_username("kai").is_ok());
+        assert!(matches!(
+            validate_username("kc"),
+            Err(AccountError::InvalidUsername)
+        ));
+        assert!(validate_email("kai@example.com").is_ok());
+        assert!(matches!(
+            validate_email("kai.example.com"),
+            Err(AccountError::InvalidEmail)
+        ));
+        assert!(validate_password("correct horse").is_ok());
+        assert!(matches!(
+            validate_password("short"),
+            Err(AccountError::WeakPassword)
+        ));
+    }
+
+    #[test]
+    fn test_passwords_are_hashed_once() {
+        let password_hash = hash_password("correct horse").unwrap();
+
+        assert!(bcrypt::verify("correct horse", &password_hash).unwrap());
+        assert!(!bcrypt::verify("wrong horse", &password_hash).unwrap());
+    }
+
+    #[test]
+    fn test_workspace_cleanup_removes_workspaces_only_the_user_holds() {
+        let user = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let personal = Uuid::new_v4();
+        let solo = Uuid::new_v4();
+        let shared = Uuid::new_v4();
+
+        let plan = plan_workspace_cleanup(
+            user,
+            &[
+                membership(personal, user, true, true),
+                membership(solo, user, true, false),
+                membership(shared, user, false, false),
+                membership(shared, other, true, false),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan,
+            WorkspaceCleanup {
+                removed_workspaces: vec![personal, solo]
+            }
+        );
+    }
+
+    #[test]
+    fn test_last_owner_of_a_shared_workspace_cant_delete_their_account() {
+        let user = Uuid::new_v4();
+        let shared = Uuid::new_v4();
+
+        let result = plan_workspace_cleanup(
+            user,
+            &[
+                membership(shared, user, true, false),
+                membership(shared, Uuid::new_v4(), false, false),
+            ],
+        );
+
+        assert!(matches!(result, Err(AccountError::LastOwner)));
+    }
+
+    async fn insert_file(
+        pool: &PgPool,
+        owner_id: Uuid,
+        workspace_id: Uuid,
+        s3_key: Option<&str>,
+        reference_file_id: Option<i32>,
+    ) -> i32 {
+        sqlx::query(
+            "INSERT INTO File (file_name, sha256_hash, s3_key, owner_id, workspace_id, reference_file_id) \
+             VALUES ('report.pdf', '', $1, $2, $3, $4) RETURNING file_id",
+        )
+        .bind(s3_key)
+        .bind(owner_id)
+        .bind(workspace_id)
+        .bind(reference_file_id)
+        .fetch_one(pool)
+        .await
+        .expect("Failed to insert file")
+        .get("file_id")
+    }
+
+    #[tokio::test]
+    async fn test_deleting_an_account_keeps_uploads_other_members_refer_to() {
+        // This test requires the database to be running
+        dotenv::from_filename("../.env").ok();
+        let database_url = std::env::var("DATABASE_URL").expect("Database url not configured");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        sqlx::migrate!("./src/migrations").run(&pool).await.unwrap();
+
+        let suffix = Uuid::new_v4().simple().to_string();
+        let password_hash = bcrypt::hash("correct horse", 4).unwrap();
+        let leaving = create_user(
+            &pool,
+            &format!("leaving-{}", &suffix[..8]),
+            &format!("leaving-{}@example.com", suffix),
+            &password_hash,
+        )
+        .await
+        .unwrap();
+        let staying = create_user(
+            &pool,
+            &format!("staying-{}", &suffix[..8]),
+            &format!("staying-{}@example.com", suffix),
+            &password_hash,
+        )
+        .await
+        .unwrap();
+        let workspace_id = create_workspace(&pool, "Shared", leaving.user_id)
+            .await
+            .unwrap();
+        add_member(
+            &pool,
+            workspace_id,
+            WorkspaceRole::Owner,
+            &staying.email,
+            WorkspaceRole::Owner,
+        )
+        .await
+        .unwrap();
+
+        // The staying member's duplicate was resolved to the leaving member's upload
+        let keeper_id = insert_file(
+            &pool,
+            leaving.user_id,
+            workspace_id,
+            Some("uploads/keeper.pdf"),
+            None,
+        )
+        .await;
+        let reference_id =
+            insert_file(&pool, staying.user_id, workspace_id, None, Some(keeper_id)).await;
+        let unreferenced_id = insert_file(
+            &pool,
+            leaving.user_id,
+            workspace_id,
+            Some("uploads/other.pdf"),
+            None,
+        )
+        .await;
+
+        let root = std::env::temp_dir().join(format!("accounts-test-{}", suffix));
+        let storage = LocalStorage::new(root.to_str().unwrap(), "http://localhost", "secret");
+        let purged = delete_account(
+            &pool,
+            &storage,
+            "http://127.0.0.1:9",
+            leaving.user_id,
+            "correct horse",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(purged, 1);
+        let rows = sqlx::query(
+            "SELECT file_id, owner_id, workspace_id, reference_file_id FROM File \
+             WHERE file_id = ANY($1)",
+        )
+        .bind(vec![keeper_id, reference_id, unreferenced_id])
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let file = |file_id: i32| {
+            rows.iter()
+                .find(|row| row.get::<i32, _>("file_id") == file_id)
+        };
+
+        let keeper = file(keeper_id).expect("Referenced upload was purged");
+        assert_eq!(keeper.get::<Option<Uuid>, _>("owner_id"), None);
+        assert_eq!(
+            keeper.get::<Option<Uuid>, _>("workspace_id"),
+            Some(workspace_id)
+        );
+        let reference = file(reference_id).unwrap();
+        assert_eq!(
+            reference.get::<Option<i32>, _>("reference_file_id"),
+            Some(keeper_id)
+        );
+        assert!(file(unreferenced_id).is_none());
+    }
+}
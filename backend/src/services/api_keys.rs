@@ -0,0 +1,233 @@
+use crate::services::auth::{hash_secret_token, new_secret_token};
+use crate::services::workspaces::Permission;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+// API keys let machine clients such as CI pipelines call the API without a
+// login:
+// 1. A logged in user creates a key with a set of scopes. The key is shown
+//    once; only its hash and a short prefix to recognize it by are stored.
+// 2. Requests send it as `Authorization: Bearer dk_...`, and act as the user
+//    in the workspace they pick, limited to the key's scopes on top of the
+//    user's role there.
+// 3. Managing the account, workspaces and keys themselves needs a login.
+
+/// Start of every API key, telling them apart from JWTs
+pub const API_KEY_PREFIX: &str = "dk_";
+
+/// Characters of a key kept to recognize it by in listings
+const DISPLAY_PREFIX_LEN: usize = 10;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Upload files
+    Upload,
+    /// Read files, duplicates, clusters and jobs
+    Read,
+    /// Resolve duplicates and restore files from the trash
+    Resolve,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiKeyScope::Upload => "upload",
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Resolve => "resolve",
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "upload" => Some(ApiKeyScope::Upload),
+            "read" => Some(ApiKeyScope::Read),
+            "resolve" => Some(ApiKeyScope::Resolve),
+            _ => None,
+        }
+    }
+
+    /// Scope a key needs for an action, `None` if keys can't do it at all
+    pub fn required_for(permission: Permission) -> Option<Self> {
+        match permission {
+            Permission::Upload => Some(ApiKeyScope::Upload),
+            Permission::Resolve => Some(ApiKeyScope::Resolve),
+            Permission::ManageJobs | Permission::ManageMembers | Permission::ManageOwners => None,
+        }
+    }
+}
+
+/// The user and scopes of a valid key
+#[derive(Debug, Clone)]
+pub struct ApiKeyGrant {
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiKeyInfo {
+    pub api_key_id: Uuid,
+    pub name: String,
+    /// Start of the key, to recognize it by
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    /// The key itself, only ever returned here
+    pub key: String,
+}
+
+fn new_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, new_secret_token())
+}
+
+fn scopes_from_row(row: &sqlx::postgres::PgRow) -> Vec<ApiKeyScope> {
+    row.get::<Vec<String>, _>("scopes")
+        .iter()
+        .filter_map(|scope| ApiKeyScope::parse(scope))
+        .collect()
+}
+
+fn api_key_info(row: &sqlx::postgres::PgRow) -> ApiKeyInfo {
+    ApiKeyInfo {
+        api_key_id: row.get("id"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: scopes_from_row(row),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+const API_KEY_COLUMNS: &str = "id, name, prefix, scopes, created_at, last_used_at, revoked_at";
+
+pub async fn create_api_key(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiKeyScope],
+) -> Result<CreatedApiKey, sqlx::Error> {
+    let key = new_api_key();
+    let mut scope_names: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+    scope_names.sort();
+    scope_names.dedup();
+
+    let row = sqlx::query(&format!(
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(name)
+    .bind(&key[..DISPLAY_PREFIX_LEN])
+    .bind(hash_secret_token(&key))
+    .bind(&scope_names)
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(CreatedApiKey {
+        info: api_key_info(&row),
+        key,
+    })
+}
+
+/// The user's keys, including revoked ones, newest first
+pub async fn user_api_keys(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiKeyInfo>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC, id",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(rows.iter().map(api_key_info).collect())
+}
+
+/// Revoke one of the user's keys. Returns `false` if they have no active key
+/// with that id.
+pub async fn revoke_api_key(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    api_key_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = NOW() \
+         WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(api_key_id)
+    .bind(user_id)
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up an active key, recording that it was used. `None` if the key is
+/// unknown or revoked.
+pub async fn authenticate_api_key(
+    db_pool: &PgPool,
+    key: &str,
+) -> Result<Option<ApiKeyGrant>, sqlx::Error> {
+    let row = sqlx::query(
+        "UPDATE api_keys SET last_used_at = NOW() \
+         WHERE key_hash = $1 AND revoked_at IS NULL \
+         RETURNING user_id, scopes",
+    )
+    .bind(hash_secret_token(key))
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(row.map(|row| ApiKeyGrant {
+        user_id: row.get("user_id"),
+        scopes: scopes_from_row(&row),
+    }))
+}
+
+#[cfg(test)]
+mod api_keys_test {
+    use super::*;
+
+    #[test]
+    fn test_api_keys_are_recognizable_and_random() {
+        let key = new_api_key();
+
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert!(key.len() > DISPLAY_PREFIX_LEN);
+        assert_ne!(key, new_api_key());
+    }
+
+    #[test]
+    fn test_scopes_round_trip_through_the_database_names() {
+        for scope in [ApiKeyScope::Upload, ApiKeyScope::Read, ApiKeyScope::Resolve] {
+            assert_eq!(ApiKeyScope::parse(scope.as_str()), Some(scope));
+        }
+        assert_eq!(ApiKeyScope::parse("admin"), None);
+    }
+
+    #[test]
+    fn test_keys_cant_manage_jobs_or_members() {
+        assert_eq!(
+            ApiKeyScope::required_for(Permission::Upload),
+            Some(ApiKeyScope::Upload)
+        );
+        assert_eq!(
+            ApiKeyScope::required_for(Permission::Resolve),
+            Some(ApiKeyScope::Resolve)
+        );
+        assert_eq!(ApiKeyScope::required_for(Permission::ManageJobs), None);
+        assert_eq!(ApiKeyScope::required_for(Permission::ManageMembers), None);
+    }
+}
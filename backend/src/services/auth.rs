@@ -1,68 +1,77 @@
-use crate::database::users::{create_user, get_user_by_email};
+use crate::config::Config;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
 use jwt::{SignWithKey, VerifyWithKey};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-use sqlx::PgPool;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
 
 // Normal JWT login flow
 // 1. User logs in with credentials, client sends user credentials to the backend, encrypted via https
 // 2. The password is compared against a hashed version stored in DB
-// 3. If valid, returns a JWT token in the response header, and set this token into local storage
-// 4. Client sends this JWT token as Bearer <auth_token> using the Authorization header in future requests
+// 3. If valid, returns a short-lived JWT access token and a refresh token
+// 4. Client sends the access token as Bearer <auth_token> using the Authorization header in future requests
+// 5. Once the access token expires, the client trades the refresh token for a new pair. Each
+//    refresh token works once; presenting a used one again revokes its whole login session
+// 6. Logging out revokes the access token until it would have expired, and the refresh tokens
+//    of its session
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Claims {
+    /// User id
+    sub: Uuid,
     username: String,
-    issued_at: DateTime<Utc>,
-    expiration: u64, // minutes since created at before token expiration
+    /// Seconds since the epoch
+    iat: i64,
+    exp: i64,
+    /// Token id, for revocation
+    jti: Uuid,
+}
+
+/// Leeway for clocks of servers issuing and verifying tokens disagreeing
+const CLOCK_SKEW_SECS: i64 = 60;
+
+impl Claims {
+    pub fn user_id(&self) -> Uuid {
+        self.sub
+    }
+
+    pub fn token_id(&self) -> Uuid {
+        self.jti
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.exp, 0).unwrap_or_default()
+    }
+
+    fn validate_times(&self, now: i64) -> Result<(), AuthError> {
+        if self.exp <= now {
+            return Err(AuthError::ExpiredToken);
+        }
+        if self.iat > now + CLOCK_SKEW_SECS {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub enum AuthError {
     InvalidCredentials,
-    UserNotFound,
     TokenGeneration,
     InvalidToken,
+    ExpiredToken,
+    Database(sqlx::Error),
 }
 
-pub async fn authenticate_user(
-    pool: &PgPool,
-    email: &str,
-    password: &str,
-) -> Result<bool, AuthError> {
-    // Get user from database
-    match get_user_by_email(pool, email).await {
-        Ok(Some((_, password_hash))) => {
-            // Verify password using bcrypt
-            match bcrypt::verify(password, &password_hash) {
-                Ok(is_valid) => Ok(is_valid),
-                Err(_) => Err(AuthError::InvalidCredentials),
-            }
-        }
-        Ok(None) => Err(AuthError::UserNotFound),
-        Err(_) => Err(AuthError::InvalidCredentials),
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        AuthError::Database(e)
     }
 }
 
-pub async fn create_user_account(
-    pool: &PgPool,
-    username: &str,
-    email: &str,
-    password: &str,
-) -> Result<(), AuthError> {
-    // Hash the password
-    let password_hash =
-        bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|_| AuthError::InvalidCredentials)?;
-
-    // Create user in database
-    create_user(pool, username, email, &password_hash)
-        .await
-        .map_err(|_| AuthError::InvalidCredentials)?;
-
-    Ok(())
-}
-
 pub fn verify_jwt_token(token: &str, jwt_secret: &str) -> Result<Claims, AuthError> {
     let key: Hmac<Sha256> =
         Hmac::new_from_slice(jwt_secret.as_bytes()).map_err(|_| AuthError::InvalidToken)?;
@@ -70,15 +79,24 @@ pub fn verify_jwt_token(token: &str, jwt_secret: &str) -> Result<Claims, AuthErr
     let claims: Claims = token
         .verify_with_key(&key)
         .map_err(|_| AuthError::InvalidCredentials)?;
+    claims.validate_times(Utc::now().timestamp())?;
 
     Ok(claims)
 }
 
-pub fn generate_jwt_token(username: &str, jwt_secret: &str) -> Result<String, AuthError> {
+pub fn generate_jwt_token(
+    user_id: Uuid,
+    username: &str,
+    ttl_secs: i64,
+    jwt_secret: &str,
+) -> Result<String, AuthError> {
+    let now = Utc::now().timestamp();
     let claims = Claims {
+        sub: user_id,
         username: username.to_string(),
-        issued_at: Utc::now(),
-        expiration: 180,
+        iat: now,
+        exp: now + ttl_secs,
+        jti: Uuid::new_v4(),
     };
 
     let key: Hmac<Sha256> =
@@ -91,6 +109,217 @@ pub fn generate_jwt_token(username: &str, jwt_secret: &str) -> Result<String, Au
     Ok(token_str)
 }
 
+#[derive(Serialize, Debug)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+}
+
+/// Refresh tokens and API keys are only stored hashed, so a leaked table
+/// can't be used to log in
+pub(crate) fn hash_secret_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub(crate) fn new_secret_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().r#gen::<[u8; 32]>())
+}
+
+async fn store_refresh_token(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    family_id: Uuid,
+    config: &Config,
+) -> Result<String, sqlx::Error> {
+    let refresh_token = new_secret_token();
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at) \
+         VALUES ($1, $2, $3, NOW() + make_interval(days => $4))",
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_secret_token(&refresh_token))
+    .bind(config.refresh_token_ttl_days as i32)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(refresh_token)
+}
+
+fn token_pair(
+    user_id: Uuid,
+    username: &str,
+    refresh_token: String,
+    config: &Config,
+) -> Result<TokenPair, AuthError> {
+    Ok(TokenPair {
+        access_token: generate_jwt_token(
+            user_id,
+            username,
+            config.access_token_ttl_secs as i64,
+            &config.jwt_secret,
+        )?,
+        refresh_token,
+        expires_in: config.access_token_ttl_secs,
+    })
+}
+
+/// Start a login session for the user
+pub async fn issue_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+    username: &str,
+    config: &Config,
+) -> Result<TokenPair, AuthError> {
+    let mut transaction = pool.begin().await?;
+    let refresh_token =
+        store_refresh_token(&mut transaction, user_id, Uuid::new_v4(), config).await?;
+    transaction.commit().await?;
+
+    token_pair(user_id, username, refresh_token, config)
+}
+
+/// Trade a refresh token for a new pair, using it up. A token that was
+/// already used may have been stolen, so reusing one revokes its session.
+pub async fn refresh_tokens(
+    pool: &PgPool,
+    refresh_token: &str,
+    config: &Config,
+) -> Result<TokenPair, AuthError> {
+    let mut transaction = pool.begin().await?;
+
+    let row = sqlx::query(
+        "SELECT r.id, r.user_id, r.family_id, r.expires_at <= NOW() AS expired, \
+                r.revoked_at IS NOT NULL AS revoked, u.username \
+         FROM refresh_tokens r JOIN users u ON u.id = r.user_id \
+         WHERE r.token_hash = $1 FOR UPDATE OF r",
+    )
+    .bind(hash_secret_token(refresh_token))
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(AuthError::InvalidToken)?;
+    let user_id: Uuid = row.get("user_id");
+    let family_id: Uuid = row.get("family_id");
+    let username: String = row.get("username");
+
+    if row.get::<bool, _>("revoked") {
+        log::warn!(
+            "Refresh token reused for user {}, revoking its session",
+            user_id
+        );
+        revoke_session(&mut transaction, family_id).await?;
+        transaction.commit().await?;
+        return Err(AuthError::InvalidToken);
+    }
+    if row.get::<bool, _>("expired") {
+        return Err(AuthError::ExpiredToken);
+    }
+
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = NOW() WHERE id = $1")
+        .bind(row.get::<Uuid, _>("id"))
+        .execute(&mut *transaction)
+        .await?;
+    let refresh_token = store_refresh_token(&mut transaction, user_id, family_id, config).await?;
+    transaction.commit().await?;
+
+    token_pair(user_id, &username, refresh_token, config)
+}
+
+/// Revoke an access token until it expires, along with the session of the
+/// refresh token, if given and the user's
+pub async fn logout(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+    token_expires_at: DateTime<Utc>,
+    refresh_token: Option<&str>,
+) -> Result<(), AuthError> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query(
+        "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(token_id)
+    .bind(token_expires_at)
+    .execute(&mut *transaction)
+    .await?;
+
+    if let Some(refresh_token) = refresh_token {
+        let family = sqlx::query(
+            "SELECT family_id FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2",
+        )
+        .bind(hash_secret_token(refresh_token))
+        .bind(user_id)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if let Some(family) = family {
+            revoke_session(&mut transaction, family.get("family_id")).await?;
+        }
+    }
+
+    // Expired tokens are rejected anyway, so their entries are no longer needed
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn revoke_session(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    family_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(family_id)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// End every login session of the user, as after a password change. Access
+/// tokens issued until now are rejected along with the refresh tokens.
+pub async fn revoke_user_sessions(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await?;
+    // Token issue times are whole seconds, so the cutoff is too; a token
+    // issued later in the same second as the cutoff stays valid
+    sqlx::query("UPDATE users SET tokens_valid_after = date_trunc('second', NOW()) WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Whether the access token was revoked by logging out, or issued before the
+/// user's sessions were all ended
+pub async fn is_token_revoked(pool: &PgPool, claims: &Claims) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1) \
+             OR EXISTS (SELECT 1 FROM users WHERE id = $2 AND tokens_valid_after > to_timestamp($3)) \
+             AS revoked",
+    )
+    .bind(claims.jti)
+    .bind(claims.sub)
+    .bind(claims.iat)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.get("revoked"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,8 +330,9 @@ mod tests {
         dotenv().ok();
         let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set for tests");
 
+        let user_id = Uuid::new_v4();
         let username = String::from("KaiCong");
-        let token = generate_jwt_token(&username, &jwt_secret);
+        let token = generate_jwt_token(user_id, &username, 900, &jwt_secret);
         assert!(token.is_ok());
 
         let token = token.unwrap();
@@ -111,6 +341,93 @@ mod tests {
         assert!(claim_result.is_ok());
 
         let verified_claim = claim_result.unwrap();
+        assert_eq!(verified_claim.user_id(), user_id);
         assert_eq!(verified_claim.username, String::from("KaiCong"));
     }
+
+    #[test]
+    fn test_expired_jwt_token_is_rejected() {
+        let token = generate_jwt_token(Uuid::new_v4(), "KaiCong", -10, "test-secret").unwrap();
+
+        assert!(matches!(
+            verify_jwt_token(&token, "test-secret"),
+            Err(AuthError::ExpiredToken)
+        ));
+    }
+
+    #[test]
+    fn test_claims_time_validation() {
+        let claims = |iat, exp| Claims {
+            sub: Uuid::new_v4(),
+            username: String::from("KaiCong"),
+            iat,
+            exp,
+            jti: Uuid::new_v4(),
+        };
+        let now = 1_700_000_000;
+
+        assert!(claims(now, now + 900).validate_times(now).is_ok());
+        assert!(matches!(
+            claims(now - 900, now).validate_times(now),
+            Err(AuthError::ExpiredToken)
+        ));
+        // Issued slightly ahead of this server's clock
+        assert!(claims(now + 30, now + 930).validate_times(now).is_ok());
+        assert!(matches!(
+            claims(now + 3600, now + 4500).validate_times(now),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn test_refresh_tokens_are_random_and_stored_hashed() {
+        let token = new_secret_token();
+
+        assert_ne!(token, new_secret_token());
+        assert_eq!(hash_secret_token(&token).len(), 64);
+        assert_ne!(hash_secret_token(&token), token);
+        assert_eq!(hash_secret_token(&token), hash_secret_token(&token));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_issued_right_after_ending_sessions_are_accepted() {
+        // This test requires the database to be running
+        dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("Database url not configured");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        sqlx::migrate!("./src/migrations").run(&pool).await.unwrap();
+
+        let suffix = Uuid::new_v4().simple().to_string();
+        let user = crate::database::users::create_user(
+            &pool,
+            &format!("sessions-{}", suffix),
+            &format!("sessions-{}@example.com", suffix),
+            "not-a-real-hash",
+        )
+        .await
+        .unwrap();
+        let before = claims_for(user.user_id, Utc::now().timestamp() - 1);
+
+        revoke_user_sessions(&pool, user.user_id).await.unwrap();
+        let after = claims_for(user.user_id, Utc::now().timestamp());
+
+        assert!(is_token_revoked(&pool, &before).await.unwrap());
+        assert!(!is_token_revoked(&pool, &after).await.unwrap());
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user.user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    fn claims_for(user_id: Uuid, iat: i64) -> Claims {
+        Claims {
+            sub: user_id,
+            username: String::from("sessions"),
+            iat,
+            exp: iat + 900,
+            jti: Uuid::new_v4(),
+        }
+    }
 }
@@ -1,2 +1,6 @@
+pub mod accounts;
+pub mod api_keys;
 pub mod auth;
-pub mod files;
+pub mod resolution;
+pub mod storage;
+pub mod workspaces;
@@ -0,0 +1,455 @@
+use crate::services::storage::{ObjectStorage, StorageError};
+use crate::worker::deduplication_service::{IMAGE_EMBEDDINGS_INDEX, TEXT_EMBEDDINGS_INDEX};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// Resolving duplicates keeps one file and removes the others:
+// 1. A plan lists the keeper and the files to resolve. Dry runs stop here.
+// 2. The database changes and the audit entry are committed together.
+//    Deleted files only move to the trash, from which they can be restored
+//    until the worker's trash sweeper purges them.
+// 3. For references, objects no remaining file points at are removed from
+//    storage, and the files' embeddings are removed from OpenSearch. Failures
+//    here only leave orphaned objects behind, so they are recorded on the
+//    audit entry instead of undoing the resolution.
+
+/// Order of cluster members by preference for keeping: files that still hold
+/// content, then the largest (the highest-quality copy of a near-duplicate),
+/// then the oldest
+pub const KEEPER_ORDER: &str =
+    "(reference_file_id IS NOT NULL), file_size DESC NULLS LAST, created_at, file_id";
+
+/// Condition on `Cluster c` for the workspace bound to `$1` to see it: the
+/// cluster belongs to it, or spans several workspaces and holds one of its files
+pub const CLUSTER_ACCESS: &str = "(c.workspace_id = $1 OR (c.workspace_id IS NULL AND EXISTS ( \
+         SELECT 1 FROM File o WHERE o.cluster_id = c.cluster_id AND o.workspace_id = $1)))";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionAction {
+    /// Move the other files to the trash
+    Delete,
+    /// Remove the other files' content but keep their records, pointing at the keeper
+    ReplaceWithReference,
+}
+
+impl ResolutionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResolutionAction::Delete => "delete",
+            ResolutionAction::ReplaceWithReference => "replace_with_reference",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResolutionError {
+    NotFound,
+    InvalidKeeper,
+    NotDuplicates,
+    NothingToResolve,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ResolutionError {
+    fn from(e: sqlx::Error) -> Self {
+        ResolutionError::Database(e)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResolvedFile {
+    pub file_id: i32,
+    pub file_name: String,
+    pub file_size: Option<i64>,
+    #[serde(skip)]
+    s3_key: Option<String>,
+    #[serde(skip)]
+    reference_file_id: Option<i32>,
+}
+
+impl ResolvedFile {
+    fn from_row(row: &sqlx::postgres::PgRow) -> Self {
+        ResolvedFile {
+            file_id: row.get("file_id"),
+            file_name: row.get("file_name"),
+            file_size: row.get("file_size"),
+            s3_key: row.get("s3_key"),
+            reference_file_id: row.get("reference_file_id"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResolutionPlan {
+    /// Workspace holding all the files
+    #[serde(skip)]
+    workspace_id: Uuid,
+    /// Member resolving the files
+    #[serde(skip)]
+    resolved_by: Uuid,
+    pub action: ResolutionAction,
+    pub cluster_id: Option<i32>,
+    pub keeper_file_id: i32,
+    pub files: Vec<ResolvedFile>,
+    /// Size of the files that still hold content
+    pub bytes_reclaimed: i64,
+}
+
+impl ResolutionPlan {
+    fn new(
+        workspace_id: Uuid,
+        resolved_by: Uuid,
+        action: ResolutionAction,
+        cluster_id: Option<i32>,
+        keeper_file_id: i32,
+        files: Vec<ResolvedFile>,
+    ) -> Result<Self, ResolutionError> {
+        // References already point at a keeper, so only deleting them changes anything
+        let files: Vec<ResolvedFile> = files
+            .into_iter()
+            .filter(|file| {
+                action == ResolutionAction::Delete || file.reference_file_id != Some(keeper_file_id)
+            })
+            .collect();
+        if files.is_empty() {
+            return Err(ResolutionError::NothingToResolve);
+        }
+
+        let bytes_reclaimed = files
+            .iter()
+            .filter(|file| file.s3_key.is_some())
+            .filter_map(|file| file.file_size)
+            .sum();
+
+        Ok(ResolutionPlan {
+            workspace_id,
+            resolved_by,
+            action,
+            cluster_id,
+            keeper_file_id,
+            files,
+            bytes_reclaimed,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResolutionOutcome {
+    /// Audit log entry, absent for dry runs
+    pub resolution_id: Option<i32>,
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub plan: ResolutionPlan,
+    pub storage_errors: Vec<String>,
+}
+
+const FILE_COLUMNS: &str = "file_id, file_name, file_size, s3_key, reference_file_id";
+
+/// Keep one of the workspace's files in a cluster and resolve all its others.
+/// Other workspaces' files in the cluster are left alone. Without an explicit
+/// keeper, the first of the workspace's files in `KEEPER_ORDER` is kept.
+pub async fn plan_cluster_resolution(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    resolved_by: Uuid,
+    cluster_id: i32,
+    keeper_file_id: Option<i32>,
+    action: ResolutionAction,
+) -> Result<ResolutionPlan, ResolutionError> {
+    sqlx::query(&format!(
+        "SELECT cluster_id FROM Cluster c WHERE cluster_id = $2 AND {}",
+        CLUSTER_ACCESS
+    ))
+    .bind(workspace_id)
+    .bind(cluster_id)
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(ResolutionError::NotFound)?;
+
+    let mut members = workspace_cluster_files(db_pool, workspace_id, cluster_id).await?;
+
+    let keeper_index = match keeper_file_id {
+        Some(keeper_file_id) => members
+            .iter()
+            .position(|member| member.file_id == keeper_file_id)
+            .ok_or(ResolutionError::InvalidKeeper)?,
+        None if members.is_empty() => return Err(ResolutionError::NothingToResolve),
+        None => 0,
+    };
+    let keeper = members.remove(keeper_index);
+    if keeper.reference_file_id.is_some() {
+        return Err(ResolutionError::InvalidKeeper);
+    }
+
+    ResolutionPlan::new(
+        workspace_id,
+        resolved_by,
+        action,
+        Some(cluster_id),
+        keeper.file_id,
+        members,
+    )
+}
+
+/// The workspace's files in a cluster, in `KEEPER_ORDER`. Resolving the
+/// cluster acts on these, and keeps the first by default.
+async fn workspace_cluster_files(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    cluster_id: i32,
+) -> Result<Vec<ResolvedFile>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM File WHERE cluster_id = $1 AND workspace_id = $2 AND deleted_at IS NULL \
+         ORDER BY {}",
+        FILE_COLUMNS, KEEPER_ORDER
+    ))
+    .bind(cluster_id)
+    .bind(workspace_id)
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(rows.iter().map(ResolvedFile::from_row).collect())
+}
+
+/// The file resolving the cluster keeps when no keeper is given, if that
+/// file still holds content
+pub async fn default_keeper(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    cluster_id: i32,
+) -> Result<Option<i32>, sqlx::Error> {
+    let members = workspace_cluster_files(db_pool, workspace_id, cluster_id).await?;
+    Ok(members
+        .first()
+        .filter(|member| member.reference_file_id.is_none())
+        .map(|member| member.file_id))
+}
+
+/// Keep `keeper_file_id` and resolve `duplicate_file_id`, which must have been
+/// recorded as a match of it. Both files must belong to the workspace.
+pub async fn plan_pair_resolution(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    resolved_by: Uuid,
+    keeper_file_id: i32,
+    duplicate_file_id: i32,
+    action: ResolutionAction,
+) -> Result<ResolutionPlan, ResolutionError> {
+    if keeper_file_id == duplicate_file_id {
+        return Err(ResolutionError::NotDuplicates);
+    }
+
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM File WHERE file_id = ANY($1) AND workspace_id = $2 AND deleted_at IS NULL",
+        FILE_COLUMNS
+    ))
+    .bind(vec![keeper_file_id, duplicate_file_id])
+    .bind(workspace_id)
+    .fetch_all(db_pool)
+    .await?;
+    let mut files: Vec<ResolvedFile> = rows.iter().map(ResolvedFile::from_row).collect();
+
+    let keeper_index = files
+        .iter()
+        .position(|file| file.file_id == keeper_file_id)
+        .ok_or(ResolutionError::NotFound)?;
+    let keeper = files.remove(keeper_index);
+    if files.is_empty() {
+        return Err(ResolutionError::NotFound);
+    }
+    if keeper.reference_file_id.is_some() {
+        return Err(ResolutionError::InvalidKeeper);
+    }
+
+    sqlx::query(
+        "SELECT file_id FROM file_matches \
+         WHERE (file_id = $1 AND matched_file_id = $2) OR (file_id = $2 AND matched_file_id = $1)",
+    )
+    .bind(keeper_file_id)
+    .bind(duplicate_file_id)
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(ResolutionError::NotDuplicates)?;
+
+    ResolutionPlan::new(
+        workspace_id,
+        resolved_by,
+        action,
+        None,
+        keeper_file_id,
+        files,
+    )
+}
+
+/// Carry out a plan, recording it in `resolution_log`
+pub async fn apply_resolution(
+    db_pool: &PgPool,
+    storage: &dyn ObjectStorage,
+    opensearch_url: &str,
+    plan: ResolutionPlan,
+) -> Result<ResolutionOutcome, ResolutionError> {
+    let file_ids: Vec<i32> = plan.files.iter().map(|file| file.file_id).collect();
+    let file_names: Vec<String> = plan.files.iter().map(|f| f.file_name.clone()).collect();
+    let s3_keys: Vec<String> = plan
+        .files
+        .iter()
+        .filter_map(|file| file.s3_key.clone())
+        .collect();
+
+    let mut transaction = db_pool.begin().await?;
+
+    // References to the resolved files now point at the keeper
+    sqlx::query("UPDATE File SET reference_file_id = $1 WHERE reference_file_id = ANY($2)")
+        .bind(plan.keeper_file_id)
+        .bind(&file_ids)
+        .execute(&mut *transaction)
+        .await?;
+
+    match plan.action {
+        ResolutionAction::Delete => {
+            // Clusters, matches and content stay in place so a restore is lossless
+            sqlx::query("UPDATE File SET deleted_at = NOW() WHERE file_id = ANY($1)")
+                .bind(&file_ids)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        ResolutionAction::ReplaceWithReference => {
+            sqlx::query(
+                "UPDATE File SET reference_file_id = $1, s3_key = NULL WHERE file_id = ANY($2)",
+            )
+            .bind(plan.keeper_file_id)
+            .bind(&file_ids)
+            .execute(&mut *transaction)
+            .await?;
+
+            // The content is gone, so its chunks are no longer stored for these files
+            sqlx::query("DELETE FROM file_chunk WHERE file_id = ANY($1)")
+                .bind(&file_ids)
+                .execute(&mut *transaction)
+                .await?;
+        }
+    }
+
+    let unused_keys = unused_object_keys(&mut transaction, &s3_keys).await?;
+
+    let row = sqlx::query(
+        "INSERT INTO resolution_log \
+         (action, cluster_id, keeper_file_id, file_ids, file_names, bytes_reclaimed, owner_id, \
+          workspace_id) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING resolution_id",
+    )
+    .bind(plan.action.as_str())
+    .bind(plan.cluster_id)
+    .bind(plan.keeper_file_id)
+    .bind(&file_ids)
+    .bind(&file_names)
+    .bind(plan.bytes_reclaimed)
+    .bind(plan.resolved_by)
+    .bind(plan.workspace_id)
+    .fetch_one(&mut *transaction)
+    .await?;
+    let resolution_id: i32 = row.get("resolution_id");
+
+    transaction.commit().await?;
+
+    // Trashed files keep their content until the sweeper purges them
+    let mut storage_errors = Vec::new();
+    if plan.action == ResolutionAction::ReplaceWithReference {
+        storage_errors = remove_objects(storage, &unused_keys).await;
+
+        if !storage_errors.is_empty() {
+            sqlx::query("UPDATE resolution_log SET storage_errors = $1 WHERE resolution_id = $2")
+                .bind(&storage_errors)
+                .bind(resolution_id)
+                .execute(db_pool)
+                .await?;
+        }
+
+        remove_embeddings(opensearch_url, &file_ids).await;
+    }
+
+    log::info!(
+        "Resolution {}: {} {} files, kept file_id {}",
+        resolution_id,
+        plan.action.as_str(),
+        file_ids.len(),
+        plan.keeper_file_id
+    );
+
+    Ok(ResolutionOutcome {
+        resolution_id: Some(resolution_id),
+        dry_run: false,
+        plan,
+        storage_errors,
+    })
+}
+
+/// Of the given keys, those no `File` row points at any more. Uploads with the
+/// same name share a key, so an object may still be in use by another file.
+pub async fn unused_object_keys(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    s3_keys: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query("SELECT DISTINCT s3_key FROM File WHERE s3_key = ANY($1)")
+        .bind(s3_keys)
+        .fetch_all(&mut **transaction)
+        .await?;
+    let keys_in_use: HashSet<String> = rows.iter().map(|row| row.get("s3_key")).collect();
+
+    let mut unused: Vec<String> = s3_keys
+        .iter()
+        .filter(|key| !keys_in_use.contains(*key))
+        .cloned()
+        .collect();
+    unused.sort();
+    unused.dedup();
+    Ok(unused)
+}
+
+/// Delete objects from storage, returning a description of each failure.
+/// Objects that are already gone count as deleted.
+pub async fn remove_objects(storage: &dyn ObjectStorage, s3_keys: &[String]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for key in s3_keys {
+        match storage.delete_object(key).await {
+            Ok(()) | Err(StorageError::NotFound) => {}
+            Err(e) => {
+                log::error!("Failed to delete {} from storage: {:?}", key, e);
+                errors.push(format!("{}: {:?}", key, e));
+            }
+        }
+    }
+    errors
+}
+
+/// Remove the files' embeddings so new uploads no longer match them. A stale
+/// embedding only produces a match that is dropped later, so failures are
+/// logged rather than returned.
+pub async fn remove_embeddings(opensearch_url: &str, file_ids: &[i32]) {
+    let client = reqwest::Client::new();
+    for index_name in [IMAGE_EMBEDDINGS_INDEX, TEXT_EMBEDDINGS_INDEX] {
+        for file_id in file_ids {
+            let url = format!("{}/{}/_doc/{}", opensearch_url, index_name, file_id);
+            match client.delete(&url).send().await {
+                Ok(response)
+                    if response.status().is_success()
+                        || response.status() == reqwest::StatusCode::NOT_FOUND => {}
+                Ok(response) => log::warn!(
+                    "Failed to remove embedding for file_id {} from {}: {}",
+                    file_id,
+                    index_name,
+                    response.status()
+                ),
+                Err(e) => log::warn!(
+                    "Failed to remove embedding for file_id {} from {}: {}",
+                    file_id,
+                    index_name,
+                    e
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use super::{
+    MultipartUploadParams, ObjectMetadata, ObjectStorage, ObjectStream, StorageError, StorageResult,
+};
+use crate::config::Config;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use futures_util::stream;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+/// Directory (relative to the storage root) holding in-progress multipart uploads
+const MULTIPART_DIR: &str = ".multipart";
+/// Directory (relative to the storage root) used to assemble objects before
+/// they are atomically moved into place
+const STAGING_DIR: &str = ".staging";
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Query string of a presigned local upload URL. The signature covers every
+/// other field, so the URL can be handed to an unauthenticated client.
+#[derive(Serialize, Deserialize)]
+pub struct LocalUploadQuery {
+    pub key: String,
+    pub expires: u64,
+    pub upload_id: Option<String>,
+    pub part_number: Option<i32>,
+    pub signature: String,
+}
+
+/// Stores objects as plain files under a root directory. Presigned URLs point
+/// back at this server's `/storage/upload` endpoint instead of S3.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_base_url: String,
+    signing_secret: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, public_base_url: &str, signing_secret: &str) -> Self {
+        LocalStorage {
+            root: PathBuf::from(root),
+            public_base_url: public_base_url.trim_end_matches('/').to_string(),
+            signing_secret: signing_secret.to_string(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            &config.local_storage_path,
+            &config.public_base_url,
+            &config.jwt_secret,
+        )
+    }
+
+    fn object_path(&self, key: &str) -> StorageResult<PathBuf> {
+        let path = Path::new(key);
+        let mut components = path.components().peekable();
+
+        // Reject anything that could escape the root or clash with our own
+        // bookkeeping directories
+        match components.peek() {
+            Some(Component::Normal(first)) if !first.to_string_lossy().starts_with('.') => {}
+            _ => return Err(StorageError::InvalidKey),
+        }
+        if !components.all(|c| matches!(c, Component::Normal(_))) {
+            return Err(StorageError::InvalidKey);
+        }
+
+        Ok(self.root.join(path))
+    }
+
+    fn multipart_dir(&self, upload_id: &str) -> StorageResult<PathBuf> {
+        // Upload ids are generated by us, so anything that isn't a UUID is bogus
+        let upload_id = Uuid::parse_str(upload_id).map_err(|_| StorageError::InvalidKey)?;
+        Ok(self.root.join(MULTIPART_DIR).join(upload_id.to_string()))
+    }
+
+    fn staging_path(&self) -> PathBuf {
+        self.root.join(STAGING_DIR).join(Uuid::new_v4().to_string())
+    }
+
+    fn signing_payload(
+        key: &str,
+        expires: u64,
+        upload_id: Option<&str>,
+        part_number: Option<i32>,
+    ) -> String {
+        format!(
+            "{}\n{}\n{}\n{}",
+            key,
+            expires,
+            upload_id.unwrap_or(""),
+            part_number.map(|p| p.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn signer(&self) -> StorageResult<Hmac<Sha256>> {
+        Hmac::new_from_slice(self.signing_secret.as_bytes()).map_err(|_| StorageError::UploadError)
+    }
+
+    fn sign(
+        &self,
+        key: &str,
+        expires: u64,
+        upload_id: Option<&str>,
+        part_number: Option<i32>,
+    ) -> StorageResult<String> {
+        let mut mac = self.signer()?;
+        mac.update(Self::signing_payload(key, expires, upload_id, part_number).as_bytes());
+        Ok(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    /// Check that a presigned upload URL was issued by us and has not expired
+    pub fn verify_presigned(&self, query: &LocalUploadQuery) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(u64::MAX);
+        if query.expires < now {
+            return false;
+        }
+
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(&query.signature) else {
+            return false;
+        };
+        let Ok(mut mac) = self.signer() else {
+            return false;
+        };
+        mac.update(
+            Self::signing_payload(
+                &query.key,
+                query.expires,
+                query.upload_id.as_deref(),
+                query.part_number,
+            )
+            .as_bytes(),
+        );
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Store the body of a presigned upload request, returning its ETag
+    pub async fn accept_presigned_upload(
+        &self,
+        query: &LocalUploadQuery,
+        body: Bytes,
+    ) -> StorageResult<String> {
+        match (&query.upload_id, query.part_number) {
+            (Some(upload_id), Some(part_number)) => {
+                self.upload_part(&query.key, upload_id, part_number, body)
+                    .await
+            }
+            _ => {
+                let e_tag = Self::e_tag_for(&body);
+                self.put_object(&query.key, body).await?;
+                Ok(e_tag)
+            }
+        }
+    }
+
+    fn e_tag_for(body: &[u8]) -> String {
+        format!("\"{:x}\"", Sha256::digest(body))
+    }
+
+    /// Write to a staging file first and rename, so readers never observe a
+    /// partially written object
+    async fn write_atomically(&self, path: &Path, body: &[u8]) -> StorageResult<()> {
+        let staging = self.staging_path();
+        Self::ensure_parent(&staging).await?;
+        Self::ensure_parent(path).await?;
+
+        fs::write(&staging, body)
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+        fs::rename(&staging, path)
+            .await
+            .map_err(|_| StorageError::UploadError)
+    }
+
+    async fn ensure_parent(path: &Path) -> StorageResult<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|_| StorageError::UploadError)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for LocalStorage {
+    async fn put_object(&self, key: &str, body: Bytes) -> StorageResult<()> {
+        let path = self.object_path(key)?;
+        self.write_atomically(&path, &body).await
+    }
+
+    async fn get_object_stream(&self, key: &str) -> StorageResult<ObjectStream> {
+        let path = self.object_path(key)?;
+        let file = fs::File::open(&path).await.map_err(|e| match e.kind() {
+            ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::DownloadError,
+        })?;
+
+        let body = stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut buffer = vec![0; READ_CHUNK_SIZE];
+            match file.read(&mut buffer).await {
+                Ok(0) => None,
+                Ok(bytes_read) => {
+                    buffer.truncate(bytes_read);
+                    Some((Ok(Bytes::from(buffer)), Some(file)))
+                }
+                // Stop after reporting the error instead of retrying forever
+                Err(_) => Some((Err(StorageError::DownloadError), None)),
+            }
+        });
+
+        Ok(body.boxed())
+    }
+
+    async fn head_object(&self, key: &str) -> StorageResult<ObjectMetadata> {
+        let path = self.object_path(key)?;
+        let metadata = fs::metadata(&path)
+            .await
+            .map_err(|_| StorageError::NotFound)?;
+
+        Ok(ObjectMetadata {
+            content_length: metadata.len(),
+        })
+    }
+
+    async fn delete_object(&self, key: &str) -> StorageResult<()> {
+        let path = self.object_path(key)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            // Deleting a missing object is a no-op, like S3
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(_) => Err(StorageError::DeleteError),
+        }
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> StorageResult<String> {
+        self.object_path(key)?;
+
+        let upload_id = Uuid::new_v4().to_string();
+        fs::create_dir_all(self.multipart_dir(&upload_id)?)
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> StorageResult<String> {
+        self.object_path(key)?;
+        // Same part number range S3 accepts
+        if !(1..=10000).contains(&part_number) {
+            return Err(StorageError::UploadError);
+        }
+
+        let upload_dir = self.multipart_dir(upload_id)?;
+        if fs::metadata(&upload_dir).await.is_err() {
+            return Err(StorageError::NotFound);
+        }
+
+        let e_tag = Self::e_tag_for(&body);
+        let part_path = upload_dir.join(part_number.to_string());
+        self.write_atomically(&part_path, &body).await?;
+        fs::write(part_path.with_extension("etag"), &e_tag)
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        Ok(e_tag)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: String,
+        mut parts: Vec<(i32, String)>,
+    ) -> StorageResult<()> {
+        let path = self.object_path(key)?;
+        let upload_dir = self.multipart_dir(&upload_id)?;
+        if parts.is_empty() {
+            return Err(StorageError::UploadError);
+        }
+        parts.sort_by_key(|(part_number, _)| *part_number);
+
+        let staging = self.staging_path();
+        Self::ensure_parent(&staging).await?;
+        let mut output = fs::File::create(&staging)
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        for (part_number, e_tag) in &parts {
+            let part_path = upload_dir.join(part_number.to_string());
+
+            // Clients strip the quotes from ETags, so compare without them
+            let stored_e_tag = fs::read_to_string(part_path.with_extension("etag"))
+                .await
+                .map_err(|_| StorageError::UploadError)?;
+            if stored_e_tag.trim_matches('"') != e_tag.trim_matches('"') {
+                return Err(StorageError::UploadError);
+            }
+
+            let mut part = fs::File::open(&part_path)
+                .await
+                .map_err(|_| StorageError::UploadError)?;
+            tokio::io::copy(&mut part, &mut output)
+                .await
+                .map_err(|_| StorageError::UploadError)?;
+        }
+        drop(output);
+
+        Self::ensure_parent(&path).await?;
+        fs::rename(&staging, &path)
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        if let Err(e) = fs::remove_dir_all(&upload_dir).await {
+            log::warn!("Failed to clean up multipart upload {}: {}", upload_id, e);
+        }
+
+        Ok(())
+    }
+
+    async fn generate_presigned_upload_url(
+        &self,
+        key: &str,
+        expires_in_secs: u64,
+        multipart_params: Option<MultipartUploadParams>,
+    ) -> StorageResult<String> {
+        self.object_path(key)?;
+
+        let expires = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| StorageError::UploadError)?
+            .as_secs()
+            + expires_in_secs;
+        let (upload_id, part_number) = match multipart_params {
+            Some(params) => (Some(params.upload_id), Some(params.part)),
+            None => (None, None),
+        };
+
+        let signature = self.sign(key, expires, upload_id.as_deref(), part_number)?;
+        let query = LocalUploadQuery {
+            key: key.to_string(),
+            expires,
+            upload_id,
+            part_number,
+            signature,
+        };
+        let query_string =
+            serde_urlencoded::to_string(&query).map_err(|_| StorageError::UploadError)?;
+
+        Ok(format!(
+            "{}/storage/upload?{}",
+            self.public_base_url, query_string
+        ))
+    }
+}
+
+#[cfg(test)]
+mod local_storage_tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    fn test_storage() -> (LocalStorage, PathBuf) {
+        let root = std::env::temp_dir().join(format!("local-storage-test-{}", Uuid::new_v4()));
+        let storage = LocalStorage::new(
+            root.to_str().unwrap(),
+            "http://localhost:8080",
+            "test_secret",
+        );
+        (storage, root)
+    }
+
+    async fn read_all(storage: &LocalStorage, key: &str) -> Vec<u8> {
+        let chunks: Vec<Bytes> = storage
+            .get_object_stream(key)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn test_put_get_head_delete() {
+        let (storage, root) = test_storage();
+        let key = "documents/sample.txt";
+
+        storage
+            .put_object(key, Bytes::from_static(b"hello world"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(&storage, key).await, b"hello world");
+        assert_eq!(storage.head_object(key).await.unwrap().content_length, 11);
+
+        storage.delete_object(key).await.unwrap();
+        assert!(matches!(
+            storage.head_object(key).await,
+            Err(StorageError::NotFound)
+        ));
+
+        fs::remove_dir_all(root).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let (storage, root) = test_storage();
+        let key = "documents/large.bin";
+
+        let upload_id = storage.create_multipart_upload(key).await.unwrap();
+        // Upload out of order to check parts are assembled by part number
+        let e_tag_2 = storage
+            .upload_part(key, &upload_id, 2, Bytes::from_static(b"world"))
+            .await
+            .unwrap();
+        let e_tag_1 = storage
+            .upload_part(key, &upload_id, 1, Bytes::from_static(b"hello "))
+            .await
+            .unwrap();
+
+        storage
+            .complete_multipart_upload(
+                key,
+                upload_id,
+                vec![(2, e_tag_2), (1, e_tag_1.trim_matches('"').to_string())],
+            )
+            .await
+            .unwrap();
+        assert_eq!(read_all(&storage, key).await, b"hello world");
+
+        fs::remove_dir_all(root).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_keys_outside_root() {
+        let (storage, _) = test_storage();
+
+        for key in ["../etc/passwd", "/etc/passwd", ".multipart/x", "a/../../b"] {
+            assert!(matches!(
+                storage.put_object(key, Bytes::new()).await,
+                Err(StorageError::InvalidKey)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_presigned_url_round_trip() {
+        let (storage, root) = test_storage();
+        let key = "documents/presigned.txt";
+
+        let url = storage
+            .generate_presigned_upload_url(key, 60, None)
+            .await
+            .unwrap();
+        let query_string = url.split_once('?').unwrap().1;
+        let mut query: LocalUploadQuery = serde_urlencoded::from_str(query_string).unwrap();
+        assert!(storage.verify_presigned(&query));
+
+        storage
+            .accept_presigned_upload(&query, Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(&storage, key).await, b"data");
+
+        query.key = "documents/other.txt".to_string();
+        assert!(!storage.verify_presigned(&query));
+
+        fs::remove_dir_all(root).await.ok();
+    }
+}
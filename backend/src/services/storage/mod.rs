@@ -0,0 +1,91 @@
+pub mod local;
+pub mod s3;
+
+use crate::config::{Config, StorageBackend};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use std::sync::Arc;
+
+pub use local::{LocalStorage, LocalUploadQuery};
+pub use s3::S3Storage;
+
+#[derive(Debug)]
+pub enum StorageError {
+    InvalidKey,
+    NotFound,
+    ReadFolderError,
+    UploadError,
+    DownloadError,
+    DeleteError,
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Object body yielded chunk by chunk, so callers never have to hold a whole
+/// object in memory
+pub type ObjectStream = BoxStream<'static, StorageResult<Bytes>>;
+
+pub struct MultipartUploadParams {
+    pub upload_id: String,
+    pub part: i32,
+}
+
+#[derive(Debug)]
+pub struct ObjectMetadata {
+    pub content_length: u64,
+}
+
+/// Object store used for uploaded documents. Keys are relative to the
+/// configured bucket (S3) or root directory (local disk).
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    async fn put_object(&self, key: &str, body: Bytes) -> StorageResult<()>;
+
+    async fn get_object_stream(&self, key: &str) -> StorageResult<ObjectStream>;
+
+    async fn head_object(&self, key: &str) -> StorageResult<ObjectMetadata>;
+
+    async fn delete_object(&self, key: &str) -> StorageResult<()>;
+
+    // Multipart upload is a three-step process:
+    // 1. You initiate the upload,
+    // 2. upload the object parts,
+    // 3. and—after you've uploaded all the parts—complete the multipart upload.
+    async fn create_multipart_upload(&self, key: &str) -> StorageResult<String>;
+
+    /// Upload a single part server-side, returning its ETag
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> StorageResult<String>;
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: String,
+        parts: Vec<(i32, String)>,
+    ) -> StorageResult<()>;
+
+    // presigned url is basically a URL which the client can use to upload files
+    // wihout needing access to the storage credentials
+    async fn generate_presigned_upload_url(
+        &self,
+        key: &str,
+        expires_in_secs: u64,
+        multipart_params: Option<MultipartUploadParams>,
+    ) -> StorageResult<String>;
+}
+
+/// Build the storage backend selected by `STORAGE_BACKEND`
+pub async fn create_storage(config: &Config) -> Arc<dyn ObjectStorage> {
+    match config.storage_backend {
+        StorageBackend::S3 => {
+            Arc::new(S3Storage::new(&config.aws_profile_name, &config.s3_bucket_name).await)
+        }
+        StorageBackend::Local => Arc::new(LocalStorage::from_config(config)),
+    }
+}
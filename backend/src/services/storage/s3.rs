@@ -0,0 +1,247 @@
+use super::{
+    MultipartUploadParams, ObjectMetadata, ObjectStorage, ObjectStream, StorageError, StorageResult,
+};
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::{Client, presigning::PresigningConfig, types::Object};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use futures_util::stream;
+use std::time::Duration;
+
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    pub async fn new(profile_name: &str, bucket: &str) -> Self {
+        let config = aws_config::from_env()
+            .profile_name(profile_name)
+            .load()
+            .await;
+
+        let client = Client::new(&config);
+
+        S3Storage {
+            client,
+            bucket: bucket.to_string(),
+        }
+    }
+
+    pub async fn list_files(&self, folder: &str) -> StorageResult<Vec<Object>> {
+        let objects = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(folder)
+            .send()
+            .await
+            .map_err(|_| StorageError::ReadFolderError)?;
+
+        match objects.contents {
+            Some(files) => Ok(files),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for S3Storage {
+    async fn put_object(&self, key: &str, body: Bytes) -> StorageResult<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        Ok(())
+    }
+
+    // The returned body is streamed from S3 as it is consumed, so callers can
+    // process arbitrarily large objects without buffering them in memory
+    async fn get_object_stream(&self, key: &str) -> StorageResult<ObjectStream> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| StorageError::DownloadError)?;
+
+        let body = stream::unfold(object.body, |mut body| async move {
+            match body.try_next().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), body)),
+                Ok(None) => None,
+                Err(_) => Some((Err(StorageError::DownloadError), body)),
+            }
+        });
+
+        Ok(body.boxed())
+    }
+
+    async fn head_object(&self, key: &str) -> StorageResult<ObjectMetadata> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                // Throttling, credentials and network failures are not a missing object
+                if e.as_service_error().is_some_and(|e| e.is_not_found()) {
+                    StorageError::NotFound
+                } else {
+                    StorageError::DownloadError
+                }
+            })?;
+
+        Ok(ObjectMetadata {
+            content_length: head.content_length().unwrap_or(0) as u64,
+        })
+    }
+
+    async fn delete_object(&self, key: &str) -> StorageResult<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| StorageError::DeleteError)?;
+
+        Ok(())
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> StorageResult<String> {
+        let resp = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        resp.upload_id()
+            .map(|s| s.to_string())
+            .ok_or(StorageError::UploadError)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> StorageResult<String> {
+        let resp = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        resp.e_tag()
+            .map(|s| s.to_string())
+            .ok_or(StorageError::UploadError)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: String,
+        parts: Vec<(i32, String)>,
+    ) -> StorageResult<()> {
+        // eTag of each single-part upload can be fetched from the response header
+        let completed_parts = parts
+            .into_iter()
+            .map(|(part_number, etag)| {
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .e_tag(etag)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|_| StorageError::UploadError)?;
+
+        Ok(())
+    }
+
+    async fn generate_presigned_upload_url(
+        &self,
+        key: &str,
+        expires_in_secs: u64,
+        multipart_params: Option<MultipartUploadParams>,
+    ) -> StorageResult<String> {
+        let presign_config = PresigningConfig::expires_in(Duration::from_secs(expires_in_secs))
+            .map_err(|_| StorageError::UploadError)?;
+
+        let presigned_req = if let Some(params) = multipart_params {
+            // Multipart upload: generate presigned URL for a part
+            self.client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(params.upload_id)
+                .part_number(params.part)
+                .presigned(presign_config)
+                .await
+                .map_err(|_| StorageError::UploadError)?
+        } else {
+            // Single-part upload: generate presigned URL for the whole object
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .presigned(presign_config)
+                .await
+                .map_err(|_| StorageError::UploadError)?
+        };
+
+        Ok(presigned_req.uri().to_string())
+    }
+}
+
+#[cfg(test)]
+mod s3_upload_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upload() -> Result<(), StorageError> {
+        let profile_name = "sso_profile";
+        let bucket_name = "mmc-did-msdora-s3-bucket";
+        let s3_storage = S3Storage::new(profile_name, bucket_name).await;
+
+        let folder = "documents";
+        let files = s3_storage.list_files(folder).await?;
+
+        assert!(files.len() >= 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+// Workspaces share a deduplication scope between their members:
+// 1. Files, jobs and clusters belong to a workspace, and uploads only match
+//    files in the same workspace.
+// 2. Every user has a personal workspace, created on first use, which
+//    requests use unless they pick another with the `X-Workspace-Id` header.
+// 3. A member's role decides what they can do in the workspace. Every member
+//    can see its files, jobs and clusters.
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    Reviewer,
+    Uploader,
+}
+
+/// Actions that need more than membership of the workspace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Upload files
+    Upload,
+    /// Resolve duplicates and restore files from the trash
+    Resolve,
+    /// Delete and requeue jobs
+    ManageJobs,
+    /// Add members, and change or remove members other than owners
+    ManageMembers,
+    /// Make members owners, and change or remove owners
+    ManageOwners,
+}
+
+impl WorkspaceRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WorkspaceRole::Owner => "owner",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Reviewer => "reviewer",
+            WorkspaceRole::Uploader => "uploader",
+        }
+    }
+
+    fn parse(role: &str) -> Option<Self> {
+        match role {
+            "owner" => Some(WorkspaceRole::Owner),
+            "admin" => Some(WorkspaceRole::Admin),
+            "reviewer" => Some(WorkspaceRole::Reviewer),
+            "uploader" => Some(WorkspaceRole::Uploader),
+            _ => None,
+        }
+    }
+
+    pub fn allows(self, permission: Permission) -> bool {
+        use WorkspaceRole::*;
+
+        match permission {
+            Permission::Upload => matches!(self, Owner | Admin | Uploader),
+            Permission::Resolve => matches!(self, Owner | Admin | Reviewer),
+            Permission::ManageJobs | Permission::ManageMembers => matches!(self, Owner | Admin),
+            Permission::ManageOwners => self == Owner,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WorkspaceError {
+    NotFound,
+    Forbidden,
+    AlreadyMember,
+    /// The change would leave the workspace without an owner
+    LastOwner,
+    /// Personal workspaces only ever have their own user as a member
+    PersonalWorkspace,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for WorkspaceError {
+    fn from(e: sqlx::Error) -> Self {
+        WorkspaceError::Database(e)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkspaceSummary {
+    pub workspace_id: Uuid,
+    pub name: String,
+    pub personal: bool,
+    /// The requesting user's role
+    pub role: WorkspaceRole,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkspaceMemberInfo {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub joined_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+fn role_from_row(row: &sqlx::postgres::PgRow) -> Result<WorkspaceRole, sqlx::Error> {
+    let role: String = row.get("role");
+    WorkspaceRole::parse(&role)
+        .ok_or_else(|| sqlx::Error::Decode(format!("Unknown role {}", role).into()))
+}
+
+/// The user's personal workspace, created along with their membership the
+/// first time it is needed. `None` if the user doesn't exist.
+pub async fn personal_workspace_id(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    if let Some(row) = sqlx::query("SELECT id FROM workspaces WHERE personal_user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db_pool)
+        .await?
+    {
+        return Ok(Some(row.get("id")));
+    }
+
+    // A concurrent request may create it first, in which case the insert does
+    // nothing and the existing workspace is returned instead
+    let row = sqlx::query(
+        "WITH created AS ( \
+             INSERT INTO workspaces (name, personal_user_id) \
+             SELECT username, id FROM users WHERE id = $1 \
+             ON CONFLICT (personal_user_id) DO NOTHING \
+             RETURNING id, personal_user_id \
+         ), membership AS ( \
+             INSERT INTO workspace_members (workspace_id, user_id, role) \
+             SELECT id, personal_user_id, 'owner' FROM created \
+         ) \
+         SELECT id FROM created \
+         UNION ALL SELECT id FROM workspaces WHERE personal_user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(row.map(|row| row.get("id")))
+}
+
+/// The user's role in the workspace, `None` if they aren't a member
+pub async fn member_role(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<WorkspaceRole>, sqlx::Error> {
+    let row =
+        sqlx::query("SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2")
+            .bind(workspace_id)
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?;
+
+    row.as_ref().map(role_from_row).transpose()
+}
+
+/// Create a shared workspace with the user as its owner
+pub async fn create_workspace(
+    db_pool: &PgPool,
+    name: &str,
+    owner_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+
+    let row = sqlx::query("INSERT INTO workspaces (name) VALUES ($1) RETURNING id")
+        .bind(name)
+        .fetch_one(&mut *transaction)
+        .await?;
+    let workspace_id: Uuid = row.get("id");
+
+    sqlx::query(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, 'owner')",
+    )
+    .bind(workspace_id)
+    .bind(owner_id)
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(workspace_id)
+}
+
+/// Workspaces the user is a member of, personal workspace first
+pub async fn user_workspaces(
+    db_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<WorkspaceSummary>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT w.id, w.name, w.personal_user_id IS NOT NULL AS personal, m.role, w.created_at \
+         FROM workspaces w \
+         JOIN workspace_members m ON m.workspace_id = w.id \
+         WHERE m.user_id = $1 \
+         ORDER BY personal DESC, w.name, w.id",
+    )
+    .bind(user_id)
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(WorkspaceSummary {
+                workspace_id: row.get("id"),
+                name: row.get("name"),
+                personal: row.get("personal"),
+                role: role_from_row(row)?,
+                created_at: row.get("created_at"),
+            })
+        })
+        .collect()
+}
+
+pub async fn workspace_members(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+) -> Result<Vec<WorkspaceMemberInfo>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT u.id, u.username, u.email, m.role, m.created_at \
+         FROM workspace_members m \
+         JOIN users u ON u.id = m.user_id \
+         WHERE m.workspace_id = $1 \
+         ORDER BY m.created_at, u.username",
+    )
+    .bind(workspace_id)
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(WorkspaceMemberInfo {
+                user_id: row.get("id"),
+                username: row.get("username"),
+                email: row.get("email"),
+                role: role_from_row(row)?,
+                joined_at: row.get("created_at"),
+            })
+        })
+        .collect()
+}
+
+/// Check that a member with `actor_role` may give or take away `role`
+fn authorize_role_change(
+    actor_role: WorkspaceRole,
+    role: WorkspaceRole,
+) -> Result<(), WorkspaceError> {
+    if !actor_role.allows(Permission::ManageMembers) {
+        return Err(WorkspaceError::Forbidden);
+    }
+    if role == WorkspaceRole::Owner && !actor_role.allows(Permission::ManageOwners) {
+        return Err(WorkspaceError::Forbidden);
+    }
+    Ok(())
+}
+
+/// Add the user with the given email to a shared workspace
+pub async fn add_member(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    actor_role: WorkspaceRole,
+    email: &str,
+    role: WorkspaceRole,
+) -> Result<Uuid, WorkspaceError> {
+    authorize_role_change(actor_role, role)?;
+
+    let workspace = sqlx::query("SELECT personal_user_id FROM workspaces WHERE id = $1")
+        .bind(workspace_id)
+        .fetch_optional(db_pool)
+        .await?
+        .ok_or(WorkspaceError::NotFound)?;
+    if workspace
+        .get::<Option<Uuid>, _>("personal_user_id")
+        .is_some()
+    {
+        return Err(WorkspaceError::PersonalWorkspace);
+    }
+
+    let user_id: Uuid = sqlx::query("SELECT id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(db_pool)
+        .await?
+        .ok_or(WorkspaceError::NotFound)?
+        .get("id");
+
+    let inserted = sqlx::query(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .bind(role.as_str())
+    .execute(db_pool)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(WorkspaceError::AlreadyMember);
+    }
+
+    Ok(user_id)
+}
+
+/// Change a member's role. Owners can only be changed by owners, and the last
+/// owner can't be demoted.
+pub async fn update_member_role(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    actor_role: WorkspaceRole,
+    user_id: Uuid,
+    role: WorkspaceRole,
+) -> Result<(), WorkspaceError> {
+    let mut transaction = db_pool.begin().await?;
+
+    let current_role = locked_member_role(&mut transaction, workspace_id, user_id).await?;
+    authorize_role_change(actor_role, current_role)?;
+    authorize_role_change(actor_role, role)?;
+    if current_role == WorkspaceRole::Owner && role != WorkspaceRole::Owner {
+        ensure_other_owner(&mut transaction, workspace_id, user_id).await?;
+    }
+
+    sqlx::query("UPDATE workspace_members SET role = $1 WHERE workspace_id = $2 AND user_id = $3")
+        .bind(role.as_str())
+        .bind(workspace_id)
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Remove a member, or let a member leave. The last owner can't leave.
+pub async fn remove_member(
+    db_pool: &PgPool,
+    workspace_id: Uuid,
+    actor_id: Uuid,
+    actor_role: WorkspaceRole,
+    user_id: Uuid,
+) -> Result<(), WorkspaceError> {
+    let mut transaction = db_pool.begin().await?;
+
+    let current_role = locked_member_role(&mut transaction, workspace_id, user_id).await?;
+    if user_id != actor_id {
+        authorize_role_change(actor_role, current_role)?;
+    }
+    if current_role == WorkspaceRole::Owner {
+        ensure_other_owner(&mut transaction, workspace_id, user_id).await?;
+    }
+
+    sqlx::query("DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2")
+        .bind(workspace_id)
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Lock the workspace's memberships, so concurrent changes can't remove every
+/// owner between them, and read the member's role
+async fn locked_member_role(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    workspace_id: Uuid,
+    user_id: Uuid,
+) -> Result<WorkspaceRole, WorkspaceError> {
+    let rows = sqlx::query(
+        "SELECT user_id, role FROM workspace_members WHERE workspace_id = $1 FOR UPDATE",
+    )
+    .bind(workspace_id)
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    let row = rows
+        .iter()
+        .find(|row| row.get::<Uuid, _>("user_id") == user_id)
+        .ok_or(WorkspaceError::NotFound)?;
+    Ok(role_from_row(row)?)
+}
+
+async fn ensure_other_owner(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    workspace_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), WorkspaceError> {
+    sqlx::query(
+        "SELECT user_id FROM workspace_members \
+         WHERE workspace_id = $1 AND user_id != $2 AND role = 'owner' LIMIT 1",
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .fetch_optional(&mut **transaction)
+    .await?
+    .map(|_| ())
+    .ok_or(WorkspaceError::LastOwner)
+}
+
+#[cfg(test)]
+mod workspaces_test {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        use Permission::*;
+
+        let allowed = |role: WorkspaceRole| {
+            [Upload, Resolve, ManageJobs, ManageMembers, ManageOwners]
+                .into_iter()
+                .filter(|&permission| role.allows(permission))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            allowed(WorkspaceRole::Owner),
+            vec![Upload, Resolve, ManageJobs, ManageMembers, ManageOwners]
+        );
+        assert_eq!(
+            allowed(WorkspaceRole::Admin),
+            vec![Upload, Resolve, ManageJobs, ManageMembers]
+        );
+        assert_eq!(allowed(WorkspaceRole::Reviewer), vec![Resolve]);
+        assert_eq!(allowed(WorkspaceRole::Uploader), vec![Upload]);
+    }
+
+    #[test]
+    fn test_only_owners_grant_or_change_ownership() {
+        assert!(authorize_role_change(WorkspaceRole::Owner, WorkspaceRole::Owner).is_ok());
+        assert!(authorize_role_change(WorkspaceRole::Admin, WorkspaceRole::Reviewer).is_ok());
+        assert!(matches!(
+            authorize_role_change(WorkspaceRole::Admin, WorkspaceRole::Owner),
+            Err(WorkspaceError::Forbidden)
+        ));
+        assert!(matches!(
+            authorize_role_change(WorkspaceRole::Reviewer, WorkspaceRole::Uploader),
+            Err(WorkspaceError::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn test_roles_round_trip_through_the_database_names() {
+        for role in [
+            WorkspaceRole::Owner,
+            WorkspaceRole::Admin,
+            WorkspaceRole::Reviewer,
+            WorkspaceRole::Uploader,
+        ] {
+            assert_eq!(WorkspaceRole::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(WorkspaceRole::parse("viewer"), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::worker::extraction::ContentKind;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+/// Canonical hash of a file: the extracted text for documents, the decoded
+/// pixels for images. Returns `None` when there is nothing to normalize, or
+/// when only a prefix of the file was downloaded, since a prefix doesn't
+/// identify the whole file.
+pub fn canonical_hash(
+    kind: ContentKind,
+    content: &[u8],
+    text: Option<&str>,
+    truncated: bool,
+) -> Option<String> {
+    if truncated {
+        return None;
+    }
+
+    match (kind, text) {
+        (_, Some(text)) => Some(canonical_text_hash(text)),
+        (ContentKind::Image, None) => canonical_image_hash(content)
+            .map_err(|e| log::warn!("Failed to decode image for canonical hash: {}", e))
+            .ok(),
+        _ => None,
+    }
+}
+
+/// SHA-256 of text after Unicode NFC normalization and collapsing every run
+/// of whitespace (including line breaks) to a single space, so copies that
+/// differ only in encoding details, line endings or reflowed lines match
+pub fn canonical_text_hash(text: &str) -> String {
+    let normalized: String = text.nfc().collect();
+    let canonical = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))
+}
+
+/// SHA-256 of an image's decoded pixels and dimensions. EXIF and other
+/// metadata, as well as lossless re-encoding, don't change the hash.
+pub fn canonical_image_hash(image_bytes: &[u8]) -> Result<String> {
+    let image = image::load_from_memory(image_bytes)?.to_rgba8();
+
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_be_bytes());
+    hasher.update(image.height().to_be_bytes());
+    hasher.update(image.as_raw());
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod canonical_hash_test {
+    use super::*;
+    use image::ImageFormat;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_text_hash_ignores_whitespace_and_normalization() {
+        let composed = "Caf\u{e9} terms\r\napply  to\tall orders.\n";
+        let decomposed = "  Cafe\u{301} terms apply\nto all orders.";
+
+        assert_eq!(
+            canonical_text_hash(composed),
+            canonical_text_hash(decomposed)
+        );
+        assert_ne!(
+            canonical_text_hash(composed),
+            canonical_text_hash("Cafe terms apply to all orders.")
+        );
+    }
+
+    fn encode_png(image: &image::DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_image_hash_ignores_encoding() {
+        let jpeg = std::fs::read("src/worker/test_data/spiderman_meme.jpg").unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+
+        assert_eq!(
+            canonical_image_hash(&jpeg).unwrap(),
+            canonical_image_hash(&encode_png(&decoded)).unwrap()
+        );
+        assert_ne!(
+            canonical_image_hash(&jpeg).unwrap(),
+            canonical_image_hash(&encode_png(&decoded.fliph())).unwrap()
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use sha2::{Digest, Sha256};
+
+/// Chunks are never cut shorter than this, except at the end of a file
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// Target average chunk size
+pub const AVG_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks are always cut at this size, even without a content boundary
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// FastCDC normalized chunking: a stricter mask (more bits must be zero)
+/// before the average size and a looser one after it pulls chunk sizes
+/// towards `AVG_CHUNK_SIZE`. The gear hash shifts left, so the masks use the
+/// high bits, which depend on the most recent bytes.
+const MASK_STRICT: u64 = !(u64::MAX >> 18);
+const MASK_LOOSE: u64 = !(u64::MAX >> 14);
+
+const GEAR: [u64; 256] = gear_table();
+
+/// Random values for the gear rolling hash, generated with SplitMix64 so the
+/// table (and therefore every chunk boundary) is stable across builds
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// A content-defined chunk of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChunk {
+    pub chunk_hash: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Length of the first chunk in `data`. Only the first `MAX_CHUNK_SIZE` bytes
+/// are examined, so the result is the same whether or not more data follows.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = data.len().min(AVG_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+
+    for (i, byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < normal { MASK_STRICT } else { MASK_LOOSE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+
+    end
+}
+
+/// Splits a byte stream into content-defined chunks as it arrives. At most
+/// one maximum-size chunk plus the latest input is buffered, so arbitrarily
+/// large objects are chunked in bounded memory.
+pub struct StreamingChunker {
+    buffer: Vec<u8>,
+    offset: u64,
+    chunks: Vec<FileChunk>,
+}
+
+impl StreamingChunker {
+    pub fn new() -> Self {
+        StreamingChunker {
+            buffer: Vec::new(),
+            offset: 0,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+
+        // Only cut once a full window is available, so boundaries don't
+        // depend on how the stream happened to be split
+        while self.buffer.len() >= MAX_CHUNK_SIZE {
+            self.emit(cut_point(&self.buffer));
+        }
+    }
+
+    /// Chunks cut so far and not yet taken, in order. Taking them as the
+    /// stream is read keeps the manifest out of memory too.
+    pub fn take_chunks(&mut self) -> Vec<FileChunk> {
+        std::mem::take(&mut self.chunks)
+    }
+
+    /// Chunk whatever is left and return the chunks not yet taken, in order
+    pub fn finish(mut self) -> Vec<FileChunk> {
+        while !self.buffer.is_empty() {
+            self.emit(cut_point(&self.buffer));
+        }
+        self.chunks
+    }
+
+    fn emit(&mut self, length: usize) {
+        let chunk_hash = format!("{:x}", Sha256::digest(&self.buffer[..length]));
+        self.chunks.push(FileChunk {
+            chunk_hash,
+            offset: self.offset,
+            length: length as u64,
+        });
+        self.offset += length as u64;
+        self.buffer.drain(..length);
+    }
+}
+
+#[cfg(test)]
+mod cdc_test {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn chunk_all(data: &[u8], piece_size: usize) -> Vec<FileChunk> {
+        let mut chunker = StreamingChunker::new();
+        for piece in data.chunks(piece_size) {
+            chunker.update(piece);
+        }
+        chunker.finish()
+    }
+
+    #[test]
+    fn test_chunk_sizes_and_offsets() {
+        let data = random_bytes(3 * 1024 * 1024, 42);
+        let chunks = chunk_all(&data, 100_000);
+
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|chunk| {
+            chunk.length as usize >= MIN_CHUNK_SIZE && chunk.length as usize <= MAX_CHUNK_SIZE
+        }));
+        assert!(last.length as usize <= MAX_CHUNK_SIZE);
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.length).sum::<u64>(),
+            data.len() as u64
+        );
+        assert!(
+            chunks
+                .windows(2)
+                .all(|pair| pair[0].offset + pair[0].length == pair[1].offset)
+        );
+    }
+
+    #[test]
+    fn test_boundaries_do_not_depend_on_stream_splits() {
+        let data = random_bytes(1024 * 1024, 7);
+
+        assert_eq!(chunk_all(&data, 4096), chunk_all(&data, data.len()));
+        assert_eq!(chunk_all(&data, 333_333), chunk_all(&data, 1));
+    }
+
+    #[test]
+    fn test_insertion_only_changes_nearby_chunks() {
+        let original = random_bytes(2 * 1024 * 1024, 99);
+        let mut edited = b"a few inserted bytes".to_vec();
+        edited.extend_from_slice(&original);
+
+        let original_chunks = chunk_all(&original, 65536);
+        let original_hashes: HashSet<_> = original_chunks.iter().map(|c| &c.chunk_hash).collect();
+        let edited_chunks = chunk_all(&edited, 65536);
+        let shared = edited_chunks
+            .iter()
+            .filter(|chunk| original_hashes.contains(&chunk.chunk_hash))
+            .count();
+
+        assert!(shared + 2 >= original_chunks.len());
+    }
+
+    #[test]
+    fn test_taking_chunks_while_streaming() {
+        let data = random_bytes(2 * 1024 * 1024, 11);
+
+        let mut chunker = StreamingChunker::new();
+        let mut chunks = Vec::new();
+        for piece in data.chunks(100_000) {
+            chunker.update(piece);
+            chunks.extend(chunker.take_chunks());
+        }
+        chunks.extend(chunker.finish());
+
+        assert_eq!(chunks, chunk_all(&data, data.len()));
+    }
+
+    #[test]
+    fn test_empty_and_small_input() {
+        assert!(StreamingChunker::new().finish().is_empty());
+
+        let chunks = chunk_all(b"hello", 5);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].length, 5);
+    }
+}
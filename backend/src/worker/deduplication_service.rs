@@ -1,23 +1,57 @@
-use crate::handlers::jobs::update_job_status_in_db;
+use crate::handlers::jobs::{JobResultSummary, update_job_result_in_db, update_job_status_in_db};
 use crate::handlers::websocket::ConnectionManager;
-use crate::metrics::DeduplicationMetrics;
+use crate::metrics::{DeduplicationMetrics, MetricsTimer};
+use crate::services::storage::{ObjectStorage, StorageError};
+use crate::worker::canonical_hash::canonical_hash;
+use crate::worker::cdc::{FileChunk, StreamingChunker};
 use crate::worker::deduplicator::Deduplicator;
+use crate::worker::embeddings::{EmbeddingProvider, embed_document};
+use crate::worker::extraction::{ContentKind, ExtractionError, extract_text};
 use crate::worker::job_queue::{DeduplicationJob, JobQueue};
+use crate::worker::minhash::{MinHashSignature, NUM_BANDS};
+use crate::worker::perceptual_hash::ImageHashes;
+use crate::worker::rate_limit::RateLimiter;
+use crate::worker::retry::{MAX_JOB_ATTEMPTS, PermanentError, is_retryable, retry_delay};
 use anyhow::Result;
+use futures_util::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::{PgPool, Row};
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use uuid::Uuid;
 
+/// OpenSearch indexes holding image and text embeddings
+pub const IMAGE_EMBEDDINGS_INDEX: &str = "image-embeddings";
+pub const TEXT_EMBEDDINGS_INDEX: &str = "file-embeddings";
+
+/// Content beyond this is not downloaded for text extraction and embedding.
+/// The start of a very large text file is plenty to place it in the vector
+/// space; PDF and Word files this large fail extraction.
+const MAX_CONTENT_BYTES: u64 = 64 * 1024 * 1024;
+/// Chunk manifest rows written per statement while an object streams in
+const MANIFEST_BATCH_SIZE: usize = 1000;
+/// Titan multimodal models reject images larger than 25 MB
+const MAX_IMAGE_EMBEDDING_BYTES: u64 = 20 * 1024 * 1024;
+/// Largest pHash Hamming distance (out of 64 bits) treated as a near-duplicate image
+const PHASH_MAX_DISTANCE: i64 = 10;
+/// dHash must also agree, which filters out pHash collisions between unrelated images
+const DHASH_MAX_DISTANCE: i64 = 16;
+/// Smallest estimated Jaccard similarity of word shingles reported as a
+/// near-duplicate text file
+const MINHASH_MIN_JACCARD: f64 = 0.5;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SimilarFile {
     pub file_id: i32,
     pub file_name: String,
     pub sha256_hash: String,
     pub similarity_score: f64,
+    /// Bytes of content-defined chunks shared with the processed file
+    pub shared_bytes: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,8 +59,71 @@ pub struct DeduplicationResult {
     pub file_id: i32,
     pub sha256_hash: String,
     pub exact_duplicates: Vec<i32>,
+    /// Hash of the normalized content, see `canonical_hash`
+    pub canonical_hash: Option<String>,
+    /// Files with the same normalized content but different bytes, e.g.
+    /// differing only in whitespace, line endings or image metadata
+    pub canonical_duplicates: Vec<i32>,
     pub similar_files: Vec<SimilarFile>,
     pub cluster_id: Option<i32>,
+    /// Bytes of the file already present in the chunk store
+    pub storage_saved_bytes: u64,
+}
+
+impl DeduplicationResult {
+    pub fn summary(&self) -> JobResultSummary {
+        JobResultSummary {
+            exact_duplicates: self.exact_duplicates.len() as i32,
+            canonical_duplicates: self.canonical_duplicates.len() as i32,
+            similar_files: self.similar_files.len() as i32,
+            cluster_id: self.cluster_id,
+            storage_saved_bytes: self.storage_saved_bytes as i64,
+        }
+    }
+}
+
+/// Files a job may match: those of the file's workspace, or every workspace's
+/// files when cross-tenant deduplication is enabled. Files without a workspace
+/// only match each other.
+#[derive(Debug, Clone, Copy)]
+struct MatchScope {
+    workspace_id: Option<Uuid>,
+    all_workspaces: bool,
+}
+
+impl MatchScope {
+    /// OpenSearch filters restricting a search to the scope
+    fn opensearch_filter(&self) -> Vec<serde_json::Value> {
+        match (self.all_workspaces, self.workspace_id) {
+            (true, _) => vec![],
+            (false, Some(workspace_id)) => {
+                vec![json!({ "term": { "workspace_id": workspace_id } })]
+            }
+            (false, None) => {
+                vec![json!({ "bool": { "must_not": { "exists": { "field": "workspace_id" } } } })]
+            }
+        }
+    }
+}
+
+/// What a single streaming pass over an uploaded object yields
+struct ScannedObject {
+    sha256_hash: String,
+    file_size: u64,
+    storage_saved_bytes: u64,
+    /// The first `MAX_CONTENT_BYTES` of the object
+    content: Vec<u8>,
+    truncated: bool,
+}
+
+/// The error for a failed OpenSearch request. A rejected request is rejected
+/// again on retry; throttling and unavailable nodes are not.
+fn opensearch_error(status: reqwest::StatusCode, message: String) -> anyhow::Error {
+    if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        PermanentError(message).into()
+    } else {
+        anyhow::anyhow!(message)
+    }
 }
 
 pub struct DeduplicationService {
@@ -34,8 +131,10 @@ pub struct DeduplicationService {
     job_queue: JobQueue,
     opensearch_client: Client,
     opensearch_url: String,
-    aws_profile: String,
-    bedrock_model_id: String,
+    opensearch_limiter: RateLimiter,
+    cross_tenant: bool,
+    storage: Arc<dyn ObjectStorage>,
+    embedding_provider: Arc<dyn EmbeddingProvider>,
     metrics: Arc<DeduplicationMetrics>,
     connection_manager: Option<Arc<Mutex<ConnectionManager>>>,
 }
@@ -45,8 +144,8 @@ impl DeduplicationService {
         db_pool: PgPool,
         job_queue: JobQueue,
         opensearch_url: String,
-        aws_profile: String,
-        bedrock_model_id: String,
+        storage: Arc<dyn ObjectStorage>,
+        embedding_provider: Arc<dyn EmbeddingProvider>,
     ) -> Self {
         let opensearch_client = Client::new();
         let metrics = Arc::new(DeduplicationMetrics::new());
@@ -56,23 +155,37 @@ impl DeduplicationService {
             job_queue,
             opensearch_client,
             opensearch_url,
-            aws_profile,
-            bedrock_model_id,
+            opensearch_limiter: RateLimiter::new(0.0),
+            cross_tenant: false,
+            storage,
+            embedding_provider,
             metrics,
             connection_manager: None,
         }
     }
 
+    /// Limit requests to OpenSearch across all jobs sharing this service
+    pub fn with_opensearch_rate_limit(mut self, requests_per_second: f64) -> Self {
+        self.opensearch_limiter = RateLimiter::new(requests_per_second);
+        self
+    }
+
+    /// Match files against every user's files, not just the uploader's
+    pub fn with_cross_tenant_deduplication(mut self, cross_tenant: bool) -> Self {
+        self.cross_tenant = cross_tenant;
+        self
+    }
+
     /// Set the WebSocket connection manager for broadcasting job updates
     pub fn set_connection_manager(&mut self, connection_manager: Arc<Mutex<ConnectionManager>>) {
         self.connection_manager = Some(connection_manager);
     }
 
-    fn get_opensearch_index(&self, file_name: &str) -> String {
-        if self.is_image_file(file_name) {
-            "image-embeddings".to_string()
+    fn get_opensearch_index(&self, is_image: bool) -> String {
+        if is_image {
+            IMAGE_EMBEDDINGS_INDEX.to_string()
         } else {
-            "file-embeddings".to_string()
+            TEXT_EMBEDDINGS_INDEX.to_string()
         }
     }
 
@@ -85,7 +198,14 @@ impl DeduplicationService {
         } else {
             "text"
         };
-        self.metrics.record_file_processed(file_type, 0); // File size unknown for now
+        let file_size = match self.storage.head_object(&job.s3_key).await {
+            Ok(metadata) => metadata.content_length,
+            Err(e) => {
+                log::warn!("Failed to read size of {}: {:?}", job.s3_key, e);
+                0
+            }
+        };
+        self.metrics.record_file_processed(file_type, file_size);
 
         let timer = crate::metrics::MetricsTimer::new("deduplication".to_string());
         let start_time = Instant::now();
@@ -96,10 +216,14 @@ impl DeduplicationService {
                 timer.finish_deduplication(&self.metrics, "full_deduplication");
 
                 // Record duplicates found
-                let total_duplicates = result.exact_duplicates.len() + result.similar_files.len();
-                if total_duplicates > 0 {
-                    self.metrics
-                        .record_duplicates_found(total_duplicates as u64, 0); // Storage saved unknown for now
+                let total_duplicates = result.exact_duplicates.len()
+                    + result.canonical_duplicates.len()
+                    + result.similar_files.len();
+                if total_duplicates > 0 || result.storage_saved_bytes > 0 {
+                    self.metrics.record_duplicates_found(
+                        total_duplicates as u64,
+                        result.storage_saved_bytes,
+                    );
                 }
 
                 // Record cluster creation
@@ -114,25 +238,54 @@ impl DeduplicationService {
                 }
 
                 log::info!(
-                    "Deduplication completed for file_id: {}, found {} exact duplicates, {} similar files in {:.2}s",
+                    "Deduplication completed for file_id: {}, found {} exact duplicates, {} canonical duplicates, {} similar files in {:.2}s",
                     job.file_id,
                     result.exact_duplicates.len(),
+                    result.canonical_duplicates.len(),
                     result.similar_files.len(),
                     duration.as_secs_f64()
                 );
 
+                // Keep the summary on the job record for the jobs API
+                if let Ok(job_uuid) = Uuid::parse_str(&job.job_id)
+                    && let Err(e) =
+                        update_job_result_in_db(&self.db_pool, job_uuid, &result.summary()).await
+                {
+                    log::error!("Failed to store job result in database: {}", e);
+                }
+
                 // Update job status in Redis and database
                 self.update_job_status(&job.job_id, "completed", None)
                     .await?;
             }
             Err(e) => {
-                // Record error
-                self.metrics.record_job_failure("deduplication_error");
-
+                // Record error, keeping files we couldn't read apart from pipeline failures
+                let error_type = if e.downcast_ref::<ExtractionError>().is_some() {
+                    "extraction_error"
+                } else {
+                    "deduplication_error"
+                };
                 log::error!("Deduplication failed for job {}: {}", job.job_id, e);
-                // Update job status in Redis and database
-                self.update_job_status(&job.job_id, "failed", Some(e.to_string()))
-                    .await?;
+
+                let job = DeduplicationJob {
+                    attempts: job.attempts + 1,
+                    ..job
+                };
+                if is_retryable(&e) && job.attempts < MAX_JOB_ATTEMPTS {
+                    self.metrics.record_job_retry(error_type);
+                    self.job_queue
+                        .schedule_retry(&job, retry_delay(job.attempts))
+                        .await?;
+                    self.update_job_status(&job.job_id, "retrying", Some(e.to_string()))
+                        .await?;
+                } else {
+                    self.metrics.record_job_failure(error_type);
+                    let job = self.with_file_workspace(job).await;
+                    self.job_queue.dead_letter_job(&job, &e.to_string()).await?;
+                    // Update job status in Redis and database
+                    self.update_job_status(&job.job_id, "failed", Some(e.to_string()))
+                        .await?;
+                }
                 return Err(e);
             }
         }
@@ -141,107 +294,724 @@ impl DeduplicationService {
     }
 
     async fn perform_deduplication(&self, job: &DeduplicationJob) -> Result<DeduplicationResult> {
-        // Step 1: Get file info and generate SHA256 hash
-        let file_info = self.get_file_info(job.file_id).await?;
-        let sha256_hash = self.generate_file_hash(&job.s3_key).await?;
+        // Step 1: Find whose files this one may match, generate SHA256 hash and
+        // content-defined chunks
+        let scope = self.match_scope(job.file_id).await?;
+        let ScannedObject {
+            sha256_hash,
+            file_size,
+            storage_saved_bytes,
+            content,
+            truncated,
+        } = self.scan_object(job.file_id, &job.s3_key).await?;
 
         // Step 2: Check for exact duplicates using SHA256
         let exact_duplicates = self
-            .find_exact_duplicates(&sha256_hash, job.file_id)
+            .find_exact_duplicates(&sha256_hash, job.file_id, scope)
             .await?;
 
-        // Step 3: Generate embeddings for the file
-        let embeddings = self
-            .generate_file_embeddings(&job.s3_key, &job.file_name)
-            .await?;
+        // Identify the file by its content, extract normalized text and hash
+        // the normalized content
+        let kind = ContentKind::detect(&content, &job.file_name);
+        let (content, extracted) = tokio::task::spawn_blocking(move || {
+            let extracted = extract_text(kind, &content, truncated).map(|text| {
+                let canonical_hash = canonical_hash(kind, &content, text.as_deref(), truncated);
+                (text, canonical_hash)
+            });
+            (content, extracted)
+        })
+        .await?;
+        let (text, canonical_hash) = extracted?;
+        if truncated && text.is_some() {
+            log::warn!(
+                "Using only the first {} bytes of {}",
+                MAX_CONTENT_BYTES,
+                job.s3_key
+            );
+        }
 
-        // Step 4: Store embeddings in OpenSearch
-        self.store_embeddings_in_opensearch(job.file_id, &job.file_name, &sha256_hash, &embeddings)
-            .await?;
+        // Format-only duplicates: same content once whitespace, Unicode
+        // normalization or image metadata are ignored
+        let canonical_duplicates = match &canonical_hash {
+            Some(canonical_hash) => {
+                self.find_canonical_duplicates(canonical_hash, &sha256_hash, job.file_id, scope)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+
+        // Step 3: Local near-duplicate detection, which needs no remote model.
+        // Images get perceptual hashes, catching resized and recompressed
+        // copies; text gets MinHash signatures, catching lexical near-duplicates.
+        let local_matches = match (&text, kind) {
+            (Some(text), _) => {
+                self.find_minhash_duplicates(job.file_id, text, scope)
+                    .await?
+            }
+            (None, ContentKind::Image) if !truncated => {
+                self.find_perceptual_duplicates(job.file_id, &content, scope)
+                    .await?
+            }
+            _ => None,
+        };
+        let local_stage_ran = local_matches.is_some();
+        let mut similar_files = local_matches.unwrap_or_default();
 
-        // Step 5: Find similar files using embeddings
-        let similar_files = self
-            .find_similar_files(&embeddings, job.file_id, &job.file_name)
+        // Steps 4-6: Embedding-based similarity through OpenSearch. An image
+        // too large for the embedding model would fail on every retry, so it
+        // keeps the results of the other stages instead.
+        let oversized_image = kind == ContentKind::Image
+            && (truncated || content.len() as u64 > MAX_IMAGE_EMBEDDING_BYTES);
+        if oversized_image {
+            log::warn!(
+                "Image {} exceeds the {} byte embedding limit, skipping embeddings",
+                job.s3_key,
+                MAX_IMAGE_EMBEDDING_BYTES
+            );
+        } else {
+            match self
+                .find_embedding_matches(
+                    job,
+                    scope,
+                    &sha256_hash,
+                    kind,
+                    &content,
+                    text.as_deref(),
+                    truncated,
+                )
+                .await
+            {
+                Ok(embedding_matches) => {
+                    Self::merge_similar_files(&mut similar_files, embedding_matches)
+                }
+                // The local stage still gives a useful result when the embedding
+                // model or OpenSearch is unavailable
+                Err(e) if local_stage_ran => {
+                    log::warn!(
+                        "Skipping embeddings for file_id {}, using local matches only: {}",
+                        job.file_id,
+                        e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.retain_active_files(&mut similar_files, scope).await?;
+
+        // Report how much content each similar file actually shares with this one
+        self.fill_shared_bytes(job.file_id, &mut similar_files)
             .await?;
 
-        // Step 6: Update database with results
+        // Step 7: Persist the matches for the duplicates API and cluster scores
+        self.store_file_matches(
+            job,
+            &exact_duplicates,
+            &canonical_duplicates,
+            &similar_files,
+        )
+        .await?;
+
+        // Step 8: Merge the file and everything it matched into one cluster
+        let identical_files = [exact_duplicates.as_slice(), &canonical_duplicates].concat();
         let cluster_id = self
-            .update_file_clusters(job.file_id, &exact_duplicates, &similar_files)
+            .update_file_clusters(job.file_id, scope, &identical_files, &similar_files)
             .await?;
 
-        // Step 7: Update file record with SHA256 hash
-        self.update_file_hash(job.file_id, &sha256_hash).await?;
+        // Step 9: Update file record with its size, SHA256 and canonical hashes
+        self.update_file_hash(
+            job.file_id,
+            file_size,
+            &sha256_hash,
+            canonical_hash.as_deref(),
+        )
+        .await?;
 
         Ok(DeduplicationResult {
             file_id: job.file_id,
             sha256_hash,
             exact_duplicates,
+            canonical_hash,
+            canonical_duplicates,
             similar_files,
             cluster_id,
+            storage_saved_bytes,
         })
     }
 
-    async fn get_file_info(&self, file_id: i32) -> Result<(String, i64)> {
-        let row = sqlx::query("SELECT file_name FROM File WHERE file_id = $1")
+    #[allow(clippy::too_many_arguments)]
+    async fn find_embedding_matches(
+        &self,
+        job: &DeduplicationJob,
+        scope: MatchScope,
+        sha256_hash: &str,
+        kind: ContentKind,
+        content: &[u8],
+        text: Option<&str>,
+        truncated: bool,
+    ) -> Result<Vec<SimilarFile>> {
+        let is_image = kind == ContentKind::Image;
+
+        // Step 4: Generate embeddings for the file
+        let embeddings = self
+            .generate_file_embeddings(
+                &job.s3_key,
+                &job.file_name,
+                is_image,
+                content,
+                text,
+                truncated,
+            )
+            .await?;
+
+        // Step 5: Store embeddings in OpenSearch
+        self.store_embeddings_in_opensearch(
+            job.file_id,
+            scope.workspace_id,
+            &job.file_name,
+            is_image,
+            sha256_hash,
+            &embeddings,
+        )
+        .await?;
+
+        // Step 6: Find similar files using embeddings
+        self.find_similar_files(&embeddings, job.file_id, scope, is_image)
+            .await
+    }
+
+    /// Add matches from another detection stage, keeping the highest score
+    /// when both stages found the same file
+    fn merge_similar_files(similar_files: &mut Vec<SimilarFile>, matches: Vec<SimilarFile>) {
+        for candidate in matches {
+            match similar_files
+                .iter_mut()
+                .find(|existing| existing.file_id == candidate.file_id)
+            {
+                Some(existing) => {
+                    existing.similarity_score =
+                        existing.similarity_score.max(candidate.similarity_score)
+                }
+                None => similar_files.push(candidate),
+            }
+        }
+    }
+
+    /// Jobs queued before workspaces existed take their file's workspace, so
+    /// the workspace's members can find them in the dead-letter queue
+    async fn with_file_workspace(&self, job: DeduplicationJob) -> DeduplicationJob {
+        if job.workspace_id.is_some() {
+            return job;
+        }
+        match self.match_scope(job.file_id).await {
+            Ok(MatchScope {
+                workspace_id: Some(workspace_id),
+                ..
+            }) => DeduplicationJob {
+                workspace_id: Some(workspace_id.to_string()),
+                ..job
+            },
+            _ => job,
+        }
+    }
+
+    async fn match_scope(&self, file_id: i32) -> Result<MatchScope> {
+        let row = sqlx::query("SELECT workspace_id FROM File WHERE file_id = $1")
             .bind(file_id)
             .fetch_one(&self.db_pool)
             .await?;
 
-        let file_name: String = row.get("file_name");
-        // For now, we'll set file_size to 0 as it's not in the current schema
-        // You might want to add this to your File table
-        Ok((file_name, 0))
+        Ok(MatchScope {
+            workspace_id: row.get("workspace_id"),
+            all_workspaces: self.cross_tenant,
+        })
     }
 
-    async fn generate_file_hash(&self, s3_key: &str) -> Result<String> {
-        // For S3 files, we'll need to download the file temporarily or use S3's ETag
-        // For now, let's use a placeholder implementation
-        // In a real implementation, you'd download the file from S3 and hash it
-        log::warn!("Using placeholder hash generation for S3 file: {}", s3_key);
+    /// Stream the object once, computing its SHA-256 hash, storing its
+    /// content-defined chunk manifest in batches as chunks are cut, and
+    /// keeping the first `MAX_CONTENT_BYTES` for extraction and embedding
+    async fn scan_object(&self, file_id: i32, s3_key: &str) -> Result<ScannedObject> {
+        let s3_timer = MetricsTimer::new("s3_get_object".to_string());
+
+        let mut body = self
+            .storage
+            .get_object_stream(s3_key)
+            .await
+            .map_err(|e| self.download_error(s3_key, e))?;
+
+        // Drop the previous manifest first, in case the file is being
+        // reprocessed. A job that fails part way leaves a partial manifest,
+        // which its retry replaces the same way.
+        sqlx::query("DELETE FROM file_chunk WHERE file_id = $1")
+            .bind(file_id)
+            .execute(&self.db_pool)
+            .await?;
 
-        // Generate a temporary hash based on the S3 key for demonstration
-        use sha2::{Digest, Sha256};
+        // Hash the object chunk by chunk as it streams in, the same way
+        // Deduplicator::generate_sha256_for_file reads local files, so large
+        // multipart uploads are hashed in bounded memory
         let mut hasher = Sha256::new();
-        hasher.update(s3_key.as_bytes());
-        Ok(format!("{:x}", hasher.finalize()))
+        let mut chunker = StreamingChunker::new();
+        let mut pending: Vec<FileChunk> = Vec::new();
+        let mut chunk_count: usize = 0;
+        let mut storage_saved_bytes: u64 = 0;
+        let mut content = Vec::new();
+        let mut total_bytes: u64 = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| {
+                self.metrics.record_s3_error("get_object");
+                anyhow::anyhow!("Failed to read {} from storage: {:?}", s3_key, e)
+            })?;
+            total_bytes += chunk.len() as u64;
+            hasher.update(&chunk);
+
+            let remaining = (MAX_CONTENT_BYTES as usize).saturating_sub(content.len());
+            content.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+
+            chunker.update(&chunk);
+            pending.extend(chunker.take_chunks());
+            if pending.len() >= MANIFEST_BATCH_SIZE {
+                storage_saved_bytes += self
+                    .store_chunk_batch(file_id, chunk_count, &pending)
+                    .await?;
+                chunk_count += pending.len();
+                pending.clear();
+            }
+        }
+        pending.extend(chunker.finish());
+        storage_saved_bytes += self
+            .store_chunk_batch(file_id, chunk_count, &pending)
+            .await?;
+        chunk_count += pending.len();
+
+        s3_timer.finish_s3(&self.metrics, "get_object");
+        log::info!(
+            "Hashed {} bytes for file: {}, stored {} chunks, {} bytes already stored",
+            total_bytes,
+            s3_key,
+            chunk_count,
+            storage_saved_bytes
+        );
+
+        Ok(ScannedObject {
+            sha256_hash: format!("{:x}", hasher.finalize()),
+            file_size: total_bytes,
+            storage_saved_bytes,
+            content,
+            truncated: total_bytes > MAX_CONTENT_BYTES,
+        })
+    }
+
+    /// Append chunks to the file's manifest starting at `first_index`,
+    /// returning how many of their bytes were already stored, either in
+    /// other files' chunks or earlier in the file itself. Reference counts are
+    /// maintained by triggers on `file_chunk`.
+    async fn store_chunk_batch(
+        &self,
+        file_id: i32,
+        first_index: usize,
+        chunks: &[FileChunk],
+    ) -> Result<u64> {
+        if chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let mut transaction = self.db_pool.begin().await?;
+
+        let hashes: Vec<String> = chunks.iter().map(|c| c.chunk_hash.clone()).collect();
+        let sizes: Vec<i64> = chunks.iter().map(|c| c.length as i64).collect();
+        let offsets: Vec<i64> = chunks.iter().map(|c| c.offset as i64).collect();
+        let indexes: Vec<i32> = (first_index..first_index + chunks.len())
+            .map(|i| i as i32)
+            .collect();
+
+        // Chunks from earlier batches of this file are already stored
+        let existing: HashSet<String> =
+            sqlx::query("SELECT chunk_hash FROM chunk WHERE chunk_hash = ANY($1)")
+                .bind(&hashes)
+                .fetch_all(&mut *transaction)
+                .await?
+                .iter()
+                .map(|row| row.get("chunk_hash"))
+                .collect();
+
+        // Only the first occurrence of each chunk not already stored costs space
+        let mut seen = HashSet::new();
+        let storage_saved_bytes = chunks
+            .iter()
+            .filter(|c| existing.contains(&c.chunk_hash) || !seen.insert(&c.chunk_hash))
+            .map(|c| c.length)
+            .sum();
+
+        sqlx::query(
+            "INSERT INTO chunk (chunk_hash, chunk_size) \
+             SELECT * FROM UNNEST($1::CHAR(64)[], $2::BIGINT[]) \
+             ON CONFLICT (chunk_hash) DO NOTHING",
+        )
+        .bind(&hashes)
+        .bind(&sizes)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO file_chunk (file_id, chunk_index, chunk_hash, chunk_offset) \
+             SELECT $1, * FROM UNNEST($2::INT[], $3::CHAR(64)[], $4::BIGINT[])",
+        )
+        .bind(file_id)
+        .bind(&indexes)
+        .bind(&hashes)
+        .bind(&offsets)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(storage_saved_bytes)
+    }
+
+    /// Drop matches with files that are in the trash, that were deleted after
+    /// their embeddings were indexed, or that are outside the scope
+    async fn retain_active_files(
+        &self,
+        similar_files: &mut Vec<SimilarFile>,
+        scope: MatchScope,
+    ) -> Result<()> {
+        if similar_files.is_empty() {
+            return Ok(());
+        }
+
+        let candidate_ids: Vec<i32> = similar_files.iter().map(|f| f.file_id).collect();
+        let rows = sqlx::query(
+            "SELECT file_id FROM File WHERE file_id = ANY($1) AND deleted_at IS NULL \
+               AND ($2 OR workspace_id IS NOT DISTINCT FROM $3)",
+        )
+        .bind(&candidate_ids)
+        .bind(scope.all_workspaces)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+        let active: HashSet<i32> = rows.iter().map(|row| row.get("file_id")).collect();
+
+        similar_files.retain(|similar_file| active.contains(&similar_file.file_id));
+        Ok(())
+    }
+
+    /// Set `shared_bytes` on each similar file to the total size of the
+    /// distinct chunks it has in common with `file_id`
+    async fn fill_shared_bytes(
+        &self,
+        file_id: i32,
+        similar_files: &mut [SimilarFile],
+    ) -> Result<()> {
+        if similar_files.is_empty() {
+            return Ok(());
+        }
+
+        let candidate_ids: Vec<i32> = similar_files.iter().map(|f| f.file_id).collect();
+        let rows = sqlx::query(
+            "SELECT other.file_id, SUM(c.chunk_size)::BIGINT AS shared_bytes \
+             FROM (SELECT DISTINCT chunk_hash FROM file_chunk WHERE file_id = $1) mine \
+             JOIN (SELECT DISTINCT file_id, chunk_hash FROM file_chunk WHERE file_id = ANY($2)) other \
+               ON other.chunk_hash = mine.chunk_hash \
+             JOIN chunk c ON c.chunk_hash = mine.chunk_hash \
+             GROUP BY other.file_id",
+        )
+        .bind(file_id)
+        .bind(&candidate_ids)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        for row in rows {
+            let other_id: i32 = row.get("file_id");
+            if let Some(similar_file) = similar_files.iter_mut().find(|f| f.file_id == other_id) {
+                similar_file.shared_bytes = row.get("shared_bytes");
+            }
+        }
+
+        Ok(())
     }
 
     async fn find_exact_duplicates(
         &self,
         sha256_hash: &str,
         exclude_file_id: i32,
+        scope: MatchScope,
     ) -> Result<Vec<i32>> {
-        let rows = sqlx::query("SELECT file_id FROM File WHERE sha256_hash = $1 AND file_id != $2")
-            .bind(sha256_hash)
-            .bind(exclude_file_id)
-            .fetch_all(&self.db_pool)
-            .await?;
+        let rows = sqlx::query(
+            "SELECT file_id FROM File WHERE sha256_hash = $1 AND file_id != $2 AND deleted_at IS NULL \
+               AND ($3 OR workspace_id IS NOT DISTINCT FROM $4)",
+        )
+        .bind(sha256_hash)
+        .bind(exclude_file_id)
+        .bind(scope.all_workspaces)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.db_pool)
+        .await?;
 
         let duplicates: Vec<i32> = rows.iter().map(|row| row.get("file_id")).collect();
         Ok(duplicates)
     }
 
-    async fn generate_file_embeddings(&self, s3_key: &str, file_name: &str) -> Result<Vec<f64>> {
-        // Determine if it's an image or text file
-        let is_image = self.is_image_file(file_name);
+    /// Files with the same canonical hash that aren't already exact duplicates
+    async fn find_canonical_duplicates(
+        &self,
+        canonical_hash: &str,
+        sha256_hash: &str,
+        exclude_file_id: i32,
+        scope: MatchScope,
+    ) -> Result<Vec<i32>> {
+        let rows = sqlx::query(
+            "SELECT file_id FROM File \
+             WHERE canonical_hash = $1 AND sha256_hash != $2 AND file_id != $3 \
+               AND deleted_at IS NULL AND ($4 OR workspace_id IS NOT DISTINCT FROM $5)",
+        )
+        .bind(canonical_hash)
+        .bind(sha256_hash)
+        .bind(exclude_file_id)
+        .bind(scope.all_workspaces)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let duplicates: Vec<i32> = rows.iter().map(|row| row.get("file_id")).collect();
+        Ok(duplicates)
+    }
 
-        if is_image {
-            // For images, we need to get the base64 representation
-            // This is a placeholder - you'd need to download from S3 and convert to base64
-            let base64_content = format!("placeholder_base64_for_{}", s3_key);
-            Deduplicator::generate_embeddings(
-                &self.aws_profile,
-                &base64_content,
-                &self.bedrock_model_id,
-            )
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to generate image embeddings: {}", e))
+    /// A missing object won't appear on a retry, so it fails the job for good
+    fn download_error(&self, s3_key: &str, error: StorageError) -> anyhow::Error {
+        self.metrics.record_s3_error("get_object");
+        let message = format!("Failed to download {} from storage: {:?}", s3_key, error);
+        match error {
+            StorageError::NotFound | StorageError::InvalidKey => PermanentError(message).into(),
+            _ => anyhow::anyhow!(message),
+        }
+    }
+
+    async fn generate_file_embeddings(
+        &self,
+        s3_key: &str,
+        file_name: &str,
+        is_image: bool,
+        content: &[u8],
+        text: Option<&str>,
+        truncated: bool,
+    ) -> Result<Vec<f64>> {
+        let timer = MetricsTimer::new("embedding_generation".to_string());
+
+        let embeddings = if is_image {
+            if truncated || content.len() as u64 > MAX_IMAGE_EMBEDDING_BYTES {
+                return Err(PermanentError(format!(
+                    "Image {} exceeds the {} byte embedding limit",
+                    s3_key, MAX_IMAGE_EMBEDDING_BYTES
+                ))
+                .into());
+            }
+
+            let base64_content = Deduplicator::generate_base64_for_bytes(content);
+            self.embedding_provider
+                .embed_image(&base64_content)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to generate image embeddings: {}", e))?
         } else {
-            // For text files, use the filename as input (or download content from S3)
-            Deduplicator::generate_embeddings(&self.aws_profile, file_name, &self.bedrock_model_id)
+            let mut text = text.unwrap_or_default();
+            if text.trim().is_empty() {
+                // Nothing to embed from the content, the name is the best signal left
+                log::warn!("No text extracted from {}, embedding file name", s3_key);
+                text = file_name;
+            }
+
+            embed_document(self.embedding_provider.as_ref(), text)
                 .await
-                .map_err(|e| anyhow::anyhow!("Failed to generate text embeddings: {}", e))
-        }
+                .map_err(|e| anyhow::anyhow!("Failed to generate text embeddings: {}", e))?
+        };
+
+        timer.finish_embedding(&self.metrics, if is_image { "image" } else { "text" });
+        Ok(embeddings)
+    }
+
+    /// Compute and store the perceptual hashes of an image, then look up
+    /// images whose hashes are within the Hamming distance thresholds. Returns
+    /// `None` if the image could not be decoded.
+    async fn find_perceptual_duplicates(
+        &self,
+        file_id: i32,
+        content: &[u8],
+        scope: MatchScope,
+    ) -> Result<Option<Vec<SimilarFile>>> {
+        let image_bytes = content.to_vec();
+        let hashes =
+            match tokio::task::spawn_blocking(move || ImageHashes::from_bytes(&image_bytes)).await?
+            {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    // Undecodable images still go through the remaining steps
+                    log::warn!(
+                        "Failed to compute perceptual hash for file_id {}: {}",
+                        file_id,
+                        e
+                    );
+                    return Ok(None);
+                }
+            };
+
+        self.update_image_hashes(file_id, &hashes).await?;
+
+        let rows = sqlx::query(
+            "SELECT file_id, file_name, sha256_hash, \
+                    bit_count((phash # $1)::bit(64)) AS phash_distance \
+             FROM File \
+             WHERE phash IS NOT NULL AND file_id != $2 AND deleted_at IS NULL \
+               AND bit_count((phash # $1)::bit(64)) <= $3 \
+               AND bit_count((dhash # $4)::bit(64)) <= $5 \
+               AND ($6 OR workspace_id IS NOT DISTINCT FROM $7) \
+             ORDER BY phash_distance \
+             LIMIT 10",
+        )
+        .bind(hashes.phash as i64)
+        .bind(file_id)
+        .bind(PHASH_MAX_DISTANCE)
+        .bind(hashes.dhash as i64)
+        .bind(DHASH_MAX_DISTANCE)
+        .bind(scope.all_workspaces)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let similar_files: Vec<SimilarFile> = rows
+            .iter()
+            .map(|row| SimilarFile {
+                file_id: row.get("file_id"),
+                file_name: row.get("file_name"),
+                sha256_hash: row.get("sha256_hash"),
+                similarity_score: 1.0 - row.get::<i64, _>("phash_distance") as f64 / 64.0,
+                shared_bytes: 0,
+            })
+            .collect();
+
+        log::info!(
+            "Found {} perceptually similar images for file_id: {}",
+            similar_files.len(),
+            file_id
+        );
+
+        Ok(Some(similar_files))
+    }
+
+    async fn update_image_hashes(&self, file_id: i32, hashes: &ImageHashes) -> Result<()> {
+        // Hashes are stored as BIGINT, so reinterpret the bits as signed
+        sqlx::query("UPDATE File SET ahash = $1, dhash = $2, phash = $3 WHERE file_id = $4")
+            .bind(hashes.ahash as i64)
+            .bind(hashes.dhash as i64)
+            .bind(hashes.phash as i64)
+            .bind(file_id)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Store the MinHash signature and LSH buckets of a text file, then look
+    /// up files sharing a bucket and keep those whose estimated Jaccard
+    /// similarity clears the threshold. Returns `None` if the text has no words.
+    async fn find_minhash_duplicates(
+        &self,
+        file_id: i32,
+        text: &str,
+        scope: MatchScope,
+    ) -> Result<Option<Vec<SimilarFile>>> {
+        let Some(signature) = MinHashSignature::from_text(text) else {
+            return Ok(None);
+        };
+        let bands: Vec<i32> = (0..NUM_BANDS as i32).collect();
+        let buckets: Vec<i64> = signature
+            .band_buckets()
+            .into_iter()
+            .map(|bucket| bucket as i64)
+            .collect();
+
+        self.update_minhash_signature(file_id, &signature, &bands, &buckets)
+            .await?;
+
+        let rows = sqlx::query(
+            "SELECT f.file_id, f.file_name, f.sha256_hash, s.signature \
+             FROM minhash_signature s \
+             JOIN File f ON f.file_id = s.file_id \
+             WHERE s.file_id IN ( \
+                 SELECT DISTINCT b.file_id FROM minhash_band b \
+                 JOIN UNNEST($1::INT[], $2::BIGINT[]) AS q(band, bucket) \
+                   ON b.band = q.band AND b.bucket = q.bucket \
+                 WHERE b.file_id != $3 \
+             ) AND f.deleted_at IS NULL AND ($4 OR f.workspace_id IS NOT DISTINCT FROM $5)",
+        )
+        .bind(&bands)
+        .bind(&buckets)
+        .bind(file_id)
+        .bind(scope.all_workspaces)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let mut similar_files: Vec<SimilarFile> = rows
+            .iter()
+            .filter_map(|row| {
+                let candidate = MinHashSignature::from_i64s(&row.get::<Vec<i64>, _>("signature"));
+                let jaccard = signature.jaccard_estimate(&candidate);
+                (jaccard >= MINHASH_MIN_JACCARD).then(|| SimilarFile {
+                    file_id: row.get("file_id"),
+                    file_name: row.get("file_name"),
+                    sha256_hash: row.get("sha256_hash"),
+                    similarity_score: jaccard,
+                    shared_bytes: 0,
+                })
+            })
+            .collect();
+        similar_files.sort_by(|a, b| b.similarity_score.total_cmp(&a.similarity_score));
+
+        log::info!(
+            "Found {} lexically similar files for file_id: {}",
+            similar_files.len(),
+            file_id
+        );
+
+        Ok(Some(similar_files))
+    }
+
+    async fn update_minhash_signature(
+        &self,
+        file_id: i32,
+        signature: &MinHashSignature,
+        bands: &[i32],
+        buckets: &[i64],
+    ) -> Result<()> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO minhash_signature (file_id, signature) VALUES ($1, $2) \
+             ON CONFLICT (file_id) DO UPDATE SET signature = EXCLUDED.signature",
+        )
+        .bind(file_id)
+        .bind(signature.to_i64s())
+        .execute(&mut *transaction)
+        .await?;
+
+        // Replace rather than merge, in case the file is being reprocessed
+        sqlx::query("DELETE FROM minhash_band WHERE file_id = $1")
+            .bind(file_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO minhash_band (band, bucket, file_id) \
+             SELECT band, bucket, $3 FROM UNNEST($1::INT[], $2::BIGINT[]) AS q(band, bucket) \
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(bands)
+        .bind(buckets)
+        .bind(file_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(())
     }
 
     fn is_image_file(&self, file_name: &str) -> bool {
@@ -256,14 +1026,17 @@ impl DeduplicationService {
     async fn store_embeddings_in_opensearch(
         &self,
         file_id: i32,
+        workspace_id: Option<Uuid>,
         file_name: &str,
+        is_image: bool,
         sha256_hash: &str,
         embeddings: &[f64],
     ) -> Result<()> {
-        let index_name = self.get_opensearch_index(file_name);
+        let index_name = self.get_opensearch_index(is_image);
 
         let document = json!({
             "file_id": file_id,
+            "workspace_id": workspace_id,
             "file_name": file_name,
             "sha256_hash": sha256_hash,
             "embedding": embeddings,
@@ -272,6 +1045,8 @@ impl DeduplicationService {
 
         let url = format!("{}/{}/_doc/{}", self.opensearch_url, index_name, file_id);
 
+        self.opensearch_limiter.acquire().await;
+
         let response = self
             .opensearch_client
             .put(&url)
@@ -286,11 +1061,12 @@ impl DeduplicationService {
                 index_name
             );
         } else {
+            let status = response.status();
             let error_text = response.text().await?;
             log::error!("Failed to store embeddings: {}", error_text);
-            return Err(anyhow::anyhow!(
-                "Failed to store embeddings: {}",
-                error_text
+            return Err(opensearch_error(
+                status,
+                format!("Failed to store embeddings: {}", error_text),
             ));
         }
 
@@ -301,14 +1077,16 @@ impl DeduplicationService {
         &self,
         embeddings: &[f64],
         exclude_file_id: i32,
-        file_name: &str,
+        scope: MatchScope,
+        is_image: bool,
     ) -> Result<Vec<SimilarFile>> {
-        let index_name = self.get_opensearch_index(file_name);
+        let index_name = self.get_opensearch_index(is_image);
 
         let query = json!({
             "size": 10,
             "query": {
                 "bool": {
+                    "filter": scope.opensearch_filter(),
                     "must_not": {
                         "term": { "file_id": exclude_file_id }
                     }
@@ -325,6 +1103,8 @@ impl DeduplicationService {
 
         let url = format!("{}/{}/_search", self.opensearch_url, index_name);
 
+        self.opensearch_limiter.acquire().await;
+
         let response = self
             .opensearch_client
             .post(&url)
@@ -333,9 +1113,13 @@ impl DeduplicationService {
             .await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
             log::error!("Failed to search similar files: {}", error_text);
-            return Ok(vec![]);
+            return Err(opensearch_error(
+                status,
+                format!("Failed to search similar files: {}", error_text),
+            ));
         }
 
         let search_result: serde_json::Value = response.json().await?;
@@ -361,6 +1145,7 @@ impl DeduplicationService {
                                     .unwrap_or("")
                                     .to_string(),
                                 similarity_score: score,
+                                shared_bytes: 0,
                             });
                         }
                     }
@@ -371,74 +1156,256 @@ impl DeduplicationService {
         Ok(similar_files)
     }
 
+    /// Put the file and every file it matched into a single cluster. Matched
+    /// files may already belong to different clusters, which this file now
+    /// bridges, so all of them are merged into the oldest one and the merges
+    /// are recorded in `cluster_merge`. Runs in one transaction holding the
+    /// involved `Cluster` rows, locked in `cluster_id` order, and the member
+    /// rows, so a concurrent job bridging any of the same clusters through
+    /// other files waits for this one and then sees the merged result.
     async fn update_file_clusters(
         &self,
         file_id: i32,
-        exact_duplicates: &[i32],
+        scope: MatchScope,
+        identical_files: &[i32],
         similar_files: &[SimilarFile],
     ) -> Result<Option<i32>> {
-        // If there are exact duplicates or similar files, create or join a cluster
-        if exact_duplicates.is_empty() && similar_files.is_empty() {
+        if identical_files.is_empty() && similar_files.is_empty() {
             return Ok(None);
         }
 
-        // Check if any of the similar files are already in a cluster
-        let mut existing_cluster_id = None;
+        let mut member_ids: Vec<i32> = std::iter::once(file_id)
+            .chain(identical_files.iter().copied())
+            .chain(similar_files.iter().map(|f| f.file_id))
+            .collect();
+        member_ids.sort_unstable();
+        member_ids.dedup();
 
-        for similar_file in similar_files {
-            let row = sqlx::query(
-                "SELECT cluster_id FROM File WHERE file_id = $1 AND cluster_id IS NOT NULL",
-            )
-            .bind(similar_file.file_id)
-            .fetch_optional(&self.db_pool)
-            .await?;
+        let mut transaction = self.db_pool.begin().await?;
+
+        // Lock the members' clusters in a fixed order, then the members. A
+        // concurrent merge may have moved members to another cluster before
+        // the locks were taken, in which case lock that one too and look again.
+        let mut locked_ids: Vec<i32> = Vec::new();
+        let mut lock_members = false;
+        let cluster_ids = loop {
+            let query = if lock_members {
+                "SELECT cluster_id FROM File WHERE file_id = ANY($1) ORDER BY file_id FOR UPDATE"
+            } else {
+                "SELECT cluster_id FROM File WHERE file_id = ANY($1)"
+            };
+            let mut current_ids: Vec<i32> = sqlx::query(query)
+                .bind(&member_ids)
+                .fetch_all(&mut *transaction)
+                .await?
+                .iter()
+                .filter_map(|row| row.get::<Option<i32>, _>("cluster_id"))
+                .collect();
+            current_ids.sort_unstable();
+            current_ids.dedup();
 
-            if let Some(row) = row {
-                existing_cluster_id = Some(row.get::<i32, _>("cluster_id"));
-                break;
+            if lock_members && current_ids.iter().all(|id| locked_ids.contains(id)) {
+                break current_ids;
             }
-        }
 
-        let cluster_id = if let Some(cluster_id) = existing_cluster_id {
-            // Join existing cluster
-            cluster_id
-        } else {
-            // Create new cluster
-            let row = sqlx::query(
-                "INSERT INTO Cluster (intra_similarity_score) VALUES ($1) RETURNING cluster_id",
+            let rows = sqlx::query(
+                "SELECT cluster_id FROM Cluster WHERE cluster_id = ANY($1) \
+                 ORDER BY cluster_id FOR UPDATE",
             )
-            .bind(0.9) // Default similarity score
-            .fetch_one(&self.db_pool)
+            .bind(&current_ids)
+            .fetch_all(&mut *transaction)
             .await?;
-            row.get("cluster_id")
+            locked_ids.extend(rows.iter().map(|row| row.get::<i32, _>("cluster_id")));
+            lock_members = true;
+        };
+
+        let cluster_id = match cluster_ids.first() {
+            Some(&oldest) => oldest,
+            None => {
+                let row = sqlx::query(
+                    "INSERT INTO Cluster (intra_similarity_score, workspace_id) VALUES (1.0, $1) \
+                     RETURNING cluster_id",
+                )
+                .bind(scope.workspace_id)
+                .fetch_one(&mut *transaction)
+                .await?;
+                row.get("cluster_id")
+            }
         };
 
-        // Update the current file's cluster
-        sqlx::query("UPDATE File SET cluster_id = $1 WHERE file_id = $2")
+        // Fold the other clusters into the surviving one
+        let merged_ids: Vec<i32> = cluster_ids
+            .iter()
+            .copied()
+            .filter(|&id| id != cluster_id)
+            .collect();
+        for merged_id in &merged_ids {
+            let moved = sqlx::query("UPDATE File SET cluster_id = $1 WHERE cluster_id = $2")
+                .bind(cluster_id)
+                .bind(merged_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query(
+                "INSERT INTO cluster_merge (cluster_id, merged_cluster_id, trigger_file_id, files_moved) \
+                 VALUES ($1, $2, $3, $4)",
+            )
             .bind(cluster_id)
+            .bind(merged_id)
             .bind(file_id)
-            .execute(&self.db_pool)
+            .bind(moved.rows_affected() as i32)
+            .execute(&mut *transaction)
             .await?;
 
-        // Update similar files to join the same cluster
-        for similar_file in similar_files {
-            sqlx::query("UPDATE File SET cluster_id = $1 WHERE file_id = $2")
+            log::info!(
+                "Merged cluster {} into cluster {} ({} files) while processing file_id {}",
+                merged_id,
+                cluster_id,
+                moved.rows_affected(),
+                file_id
+            );
+        }
+
+        if !merged_ids.is_empty() {
+            // Jobs, resolutions and earlier merges of the merged clusters now
+            // belong to the survivor
+            for table in ["jobs", "resolution_log", "cluster_merge"] {
+                sqlx::query(&format!(
+                    "UPDATE {} SET cluster_id = $1 WHERE cluster_id = ANY($2)",
+                    table
+                ))
                 .bind(cluster_id)
-                .bind(similar_file.file_id)
-                .execute(&self.db_pool)
+                .bind(&merged_ids)
+                .execute(&mut *transaction)
+                .await?;
+            }
+
+            sqlx::query("DELETE FROM Cluster WHERE cluster_id = ANY($1)")
+                .bind(&merged_ids)
+                .execute(&mut *transaction)
                 .await?;
         }
 
+        sqlx::query("UPDATE File SET cluster_id = $1 WHERE file_id = ANY($2)")
+            .bind(cluster_id)
+            .bind(&member_ids)
+            .execute(&mut *transaction)
+            .await?;
+
+        // Clusters spanning workspaces, from cross-tenant deduplication,
+        // belong to none of them
+        sqlx::query(
+            "UPDATE Cluster SET workspace_id = NULL WHERE cluster_id = $1 AND workspace_id IS NOT NULL \
+               AND EXISTS (SELECT 1 FROM File WHERE cluster_id = $1 \
+                           AND workspace_id IS DISTINCT FROM Cluster.workspace_id)",
+        )
+        .bind(cluster_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        // Average the best recorded score of each matched pair in the cluster.
+        // Pairs are stored in whichever direction they were found, possibly both.
+        sqlx::query(
+            "UPDATE Cluster SET intra_similarity_score = COALESCE(( \
+                 SELECT AVG(pairs.score) FROM ( \
+                     SELECT MAX(m.similarity_score) AS score \
+                     FROM file_matches m \
+                     JOIN File a ON a.file_id = m.file_id \
+                     JOIN File b ON b.file_id = m.matched_file_id \
+                     WHERE a.cluster_id = $1 AND b.cluster_id = $1 \
+                     GROUP BY LEAST(m.file_id, m.matched_file_id), GREATEST(m.file_id, m.matched_file_id) \
+                 ) pairs \
+             ), 1.0) \
+             WHERE cluster_id = $1",
+        )
+        .bind(cluster_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
         Ok(Some(cluster_id))
     }
 
-    async fn update_file_hash(&self, file_id: i32, sha256_hash: &str) -> Result<()> {
-        sqlx::query("UPDATE File SET sha256_hash = $1 WHERE file_id = $2")
-            .bind(sha256_hash)
-            .bind(file_id)
-            .execute(&self.db_pool)
+    async fn update_file_hash(
+        &self,
+        file_id: i32,
+        file_size: u64,
+        sha256_hash: &str,
+        canonical_hash: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE File SET file_size = $1, sha256_hash = $2, canonical_hash = $3 WHERE file_id = $4",
+        )
+        .bind(file_size as i64)
+        .bind(sha256_hash)
+        .bind(canonical_hash)
+        .bind(file_id)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Replace the matches recorded for the file with this run's results
+    async fn store_file_matches(
+        &self,
+        job: &DeduplicationJob,
+        exact_duplicates: &[i32],
+        canonical_duplicates: &[i32],
+        similar_files: &[SimilarFile],
+    ) -> Result<()> {
+        let mut matched_file_ids = Vec::new();
+        let mut match_types = Vec::new();
+        let mut scores = Vec::new();
+        let mut shared_bytes = Vec::new();
+
+        for (file_ids, match_type) in [
+            (exact_duplicates, "exact"),
+            (canonical_duplicates, "canonical"),
+        ] {
+            for &file_id in file_ids {
+                matched_file_ids.push(file_id);
+                match_types.push(match_type.to_string());
+                scores.push(1.0);
+                shared_bytes.push(0);
+            }
+        }
+        for similar_file in similar_files {
+            matched_file_ids.push(similar_file.file_id);
+            match_types.push("similar".to_string());
+            scores.push(similar_file.similarity_score);
+            shared_bytes.push(similar_file.shared_bytes);
+        }
+
+        let mut transaction = self.db_pool.begin().await?;
+
+        sqlx::query("DELETE FROM file_matches WHERE file_id = $1")
+            .bind(job.file_id)
+            .execute(&mut *transaction)
             .await?;
 
+        // The job record may be missing for jobs enqueued outside the upload
+        // flow, and OpenSearch may still return files that have been deleted
+        sqlx::query(
+            "INSERT INTO file_matches \
+             (file_id, matched_file_id, match_type, similarity_score, shared_bytes, job_id) \
+             SELECT $1, matches.matched_file_id, matches.match_type, matches.similarity_score, \
+                    matches.shared_bytes, (SELECT job_id FROM jobs WHERE job_id = $6) \
+             FROM UNNEST($2::INT[], $3::TEXT[], $4::FLOAT8[], $5::BIGINT[]) \
+                  AS matches(matched_file_id, match_type, similarity_score, shared_bytes) \
+             WHERE EXISTS (SELECT 1 FROM File WHERE file_id = matches.matched_file_id) \
+             ON CONFLICT (file_id, matched_file_id) DO NOTHING",
+        )
+        .bind(job.file_id)
+        .bind(&matched_file_ids)
+        .bind(&match_types)
+        .bind(&scores)
+        .bind(&shared_bytes)
+        .bind(Uuid::parse_str(&job.job_id).ok())
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
         Ok(())
     }
 
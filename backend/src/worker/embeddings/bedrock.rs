@@ -0,0 +1,99 @@
+use super::EmbeddingProvider;
+use anyhow::Result;
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::{Client, primitives::Blob};
+use serde_json::json;
+
+/// Titan text models accept 8k tokens; at roughly four characters per token
+/// this leaves headroom for token-dense text
+const TITAN_TEXT_MAX_CHARS: usize = 20_000;
+/// Titan multimodal models only accept 128 tokens of text
+const TITAN_MULTIMODAL_MAX_CHARS: usize = 400;
+
+/// Embeddings from an AWS Bedrock Titan embedding model
+pub struct BedrockEmbeddingProvider {
+    client: Client,
+    model_id: String,
+}
+
+impl BedrockEmbeddingProvider {
+    pub async fn new(profile_name: &str, model_id: &str) -> Self {
+        let config = aws_config::from_env()
+            .profile_name(profile_name)
+            .load()
+            .await;
+
+        BedrockEmbeddingProvider {
+            client: Client::new(&config),
+            model_id: model_id.to_string(),
+        }
+    }
+
+    async fn invoke(&self, request_body: serde_json::Value) -> Result<Vec<f64>> {
+        let vectorized_input = serde_json::to_vec(&request_body)?;
+
+        let response = self
+            .client
+            .invoke_model()
+            .model_id(&self.model_id)
+            .content_type("application/json")
+            .body(Blob::new(vectorized_input))
+            .send()
+            .await
+            .map_err(|err| anyhow::anyhow!("Bedrock invoke_model failed: {}", err))?;
+
+        let response_bytes = response.body().clone().into_inner();
+        let resp: serde_json::Value = serde_json::from_slice(&response_bytes)?;
+
+        // as_f64.ok_or() produces a Result<f64, _), mapping over it produces Vec<Result<f64, _>>
+        // however the collect method collects this iterator into <Result<Vec<f64>, _>>
+        // IF AND ONLY IF every element is Ok, if any throws an Err, it will return the Err
+        let result = resp["embedding"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Failed to extract embeddings array"))?
+            .iter()
+            .map(|v| {
+                v.as_f64()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid embedding value"))
+            })
+            .collect::<Result<Vec<f64>>>()?;
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for BedrockEmbeddingProvider {
+    async fn embed_text(&self, text: &str) -> Result<Vec<f64>> {
+        self.invoke(json!({ "inputText": text })).await
+    }
+
+    async fn embed_image(&self, image_base64: &str) -> Result<Vec<f64>> {
+        // Titan multimodal models take the image separately from any text
+        self.invoke(json!({ "inputImage": image_base64 })).await
+    }
+
+    fn max_text_chars(&self) -> usize {
+        if self.model_id.contains("image") {
+            TITAN_MULTIMODAL_MAX_CHARS
+        } else {
+            TITAN_TEXT_MAX_CHARS
+        }
+    }
+}
+
+#[cfg(test)]
+mod bedrock_embedding_test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generate_embeddings() {
+        let model_id = "amazon.titan-embed-image-v1";
+        let input_text = "Sample text to generate embeddings";
+
+        let provider = BedrockEmbeddingProvider::new("sso_profile", model_id).await;
+        let embeddings = provider.embed_text(input_text).await;
+
+        assert!(embeddings.is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+use super::EmbeddingProvider;
+use anyhow::Result;
+
+/// Upper bound on model calls per document. Text beyond this many chunks is
+/// not embedded, which keeps very large uploads from turning into hundreds of
+/// Bedrock requests.
+pub const MAX_CHUNKS_PER_DOCUMENT: usize = 64;
+
+/// Split text into chunks of at most `max_chars` characters, breaking on
+/// whitespace where possible so words are not cut in half
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<&str> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+
+    while !rest.is_empty() {
+        // Byte offset just past the first `max_chars` characters
+        let limit = match rest.char_indices().nth(max_chars) {
+            Some((offset, _)) => offset,
+            None => {
+                chunks.push(rest);
+                break;
+            }
+        };
+
+        let split_at = rest[..limit]
+            .rfind(char::is_whitespace)
+            .filter(|&offset| offset > 0)
+            .unwrap_or(limit);
+
+        chunks.push(rest[..split_at].trim_end());
+        rest = rest[split_at..].trim_start();
+    }
+
+    chunks
+}
+
+/// Average chunk embeddings into one document vector, weighting each chunk by
+/// its length, and normalize the result to unit length
+pub fn pool_embeddings(embeddings: &[(Vec<f64>, usize)]) -> Result<Vec<f64>> {
+    let dimension = match embeddings.first() {
+        Some((embedding, _)) => embedding.len(),
+        None => return Err(anyhow::anyhow!("No chunk embeddings to pool")),
+    };
+
+    let mut pooled = vec![0.0; dimension];
+    for (embedding, weight) in embeddings {
+        if embedding.len() != dimension {
+            return Err(anyhow::anyhow!(
+                "Chunk embeddings have mismatched dimensions: {} and {}",
+                dimension,
+                embedding.len()
+            ));
+        }
+        for (value, component) in pooled.iter_mut().zip(embedding) {
+            *value += component * *weight as f64;
+        }
+    }
+
+    let norm = pooled.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for value in pooled.iter_mut() {
+            *value /= norm;
+        }
+    }
+
+    Ok(pooled)
+}
+
+/// Embed a document of any length. Text that fits in the provider's input
+/// limit is embedded directly; longer text is chunked and the chunk vectors are
+/// pooled into a single document vector.
+pub async fn embed_document(provider: &dyn EmbeddingProvider, text: &str) -> Result<Vec<f64>> {
+    let chunks = chunk_text(text, provider.max_text_chars());
+
+    match chunks.as_slice() {
+        [] => Err(anyhow::anyhow!("Document has no text to embed")),
+        [single] => provider.embed_text(single).await,
+        _ => {
+            if chunks.len() > MAX_CHUNKS_PER_DOCUMENT {
+                log::warn!(
+                    "Document split into {} chunks, embedding only the first {}",
+                    chunks.len(),
+                    MAX_CHUNKS_PER_DOCUMENT
+                );
+            }
+
+            let mut embeddings = Vec::new();
+            for chunk in chunks.iter().take(MAX_CHUNKS_PER_DOCUMENT) {
+                let embedding = provider.embed_text(chunk).await?;
+                embeddings.push((embedding, chunk.chars().count()));
+            }
+
+            pool_embeddings(&embeddings)
+        }
+    }
+}
+
+#[cfg(test)]
+mod chunking_test {
+    use super::*;
+    use crate::worker::embeddings::LocalEmbeddingProvider;
+
+    #[test]
+    fn test_chunk_text_breaks_on_whitespace() {
+        let chunks = chunk_text("alpha beta gamma delta", 11);
+
+        assert_eq!(chunks, vec!["alpha beta", "gamma delta"]);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 11));
+    }
+
+    #[test]
+    fn test_chunk_text_splits_long_words_and_multibyte_text() {
+        let chunks = chunk_text("ééééééé", 3);
+
+        assert_eq!(chunks, vec!["ééé", "ééé", "é"]);
+        assert!(chunk_text("   ", 10).is_empty());
+    }
+
+    #[test]
+    fn test_pool_embeddings_weights_by_length() {
+        let pooled = pool_embeddings(&[(vec![1.0, 0.0], 3), (vec![0.0, 1.0], 1)]).unwrap();
+
+        assert!(pooled[0] > pooled[1]);
+        assert!((pooled.iter().map(|v| v * v).sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(pool_embeddings(&[(vec![1.0], 1), (vec![1.0, 0.0], 1)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_embed_long_document() {
+        let provider = LocalEmbeddingProvider::new(256);
+        let paragraph = "The supplier shall deliver the goods within thirty days. ";
+        let document = paragraph.repeat(2_000);
+
+        let embedding = embed_document(&provider, &document).await.unwrap();
+        let short = provider.embed_text(paragraph).await.unwrap();
+        let similarity: f64 = embedding.iter().zip(&short).map(|(a, b)| a * b).sum();
+
+        assert!(document.chars().count() > provider.max_text_chars());
+        assert_eq!(embedding.len(), 256);
+        assert!(similarity > 0.9);
+    }
+}
@@ -0,0 +1,285 @@
+use super::EmbeddingProvider;
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as base64_engine;
+use image::imageops::FilterType;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+/// Mean pooling has no hard input limit, but chunking keeps each blocking task
+/// short so one large document doesn't monopolize a blocking thread
+const MAX_TEXT_CHARS: usize = 8_192;
+
+/// In-process CPU embeddings, for running near-duplicate detection without any
+/// remote model (air-gapped deployments and tests).
+///
+/// Text is embedded by mean-pooling static word vectors loaded from disk (GloVe
+/// or word2vec text format). Without a model file, or for text with no known
+/// words, tokens are embedded with the hashing trick over character trigrams,
+/// which still places lexically similar documents close together. Hashed
+/// vectors share no space with word vectors, so with a model loaded a text
+/// with no known words only matches other such texts, and scores between the
+/// two kinds mean nothing.
+///
+/// Images have no model: their vector is a pixel-thumbnail baseline, the
+/// mean-centred pixels of a small grayscale thumbnail. It is stable under
+/// resizing and recompression, but it is not a semantic embedding and only
+/// finds near-identical images, not visually similar ones.
+#[derive(Clone)]
+pub struct LocalEmbeddingProvider {
+    dimension: usize,
+    word_vectors: Option<Arc<HashMap<String, Vec<f32>>>>,
+}
+
+impl LocalEmbeddingProvider {
+    /// Hashing-only provider producing vectors of the given dimension
+    pub fn new(dimension: usize) -> Self {
+        LocalEmbeddingProvider {
+            dimension,
+            word_vectors: None,
+        }
+    }
+
+    /// Load static word vectors, one `word v1 v2 ... vn` entry per line. A
+    /// word2vec `count dimension` header line is skipped if present. The
+    /// vectors must have `expected_dimension` components, the dimension of
+    /// the OpenSearch indexes.
+    pub fn load(model_path: &str, expected_dimension: usize) -> Result<Self> {
+        let reader = BufReader::new(File::open(model_path)?);
+        let mut word_vectors = HashMap::new();
+        let mut dimension = 0;
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            let vector = fields
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| {
+                    anyhow::anyhow!("Invalid vector on line {}: {}", line_number + 1, e)
+                })?;
+
+            if line_number == 0 && vector.len() == 1 {
+                continue;
+            }
+            if dimension == 0 {
+                dimension = vector.len();
+            } else if vector.len() != dimension {
+                return Err(anyhow::anyhow!(
+                    "Expected {} dimensions on line {}, found {}",
+                    dimension,
+                    line_number + 1,
+                    vector.len()
+                ));
+            }
+
+            word_vectors.insert(word.to_lowercase(), vector);
+        }
+
+        if word_vectors.is_empty() {
+            return Err(anyhow::anyhow!("No word vectors found in {}", model_path));
+        }
+        if dimension != expected_dimension {
+            return Err(anyhow::anyhow!(
+                "Word vectors in {} have {} dimensions, but LOCAL_EMBEDDING_DIMENSION is {}",
+                model_path,
+                dimension,
+                expected_dimension
+            ));
+        }
+
+        log::info!(
+            "Loaded {} word vectors ({} dimensions) from {}",
+            word_vectors.len(),
+            dimension,
+            model_path
+        );
+
+        Ok(LocalEmbeddingProvider {
+            dimension,
+            word_vectors: Some(Arc::new(word_vectors)),
+        })
+    }
+
+    fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|token| !token.is_empty())
+            .map(|token| token.to_lowercase())
+    }
+
+    fn embed_text_sync(&self, text: &str) -> Vec<f64> {
+        let mut embedding = vec![0.0; self.dimension];
+
+        if let Some(word_vectors) = &self.word_vectors {
+            let mut matched = 0;
+            for token in Self::tokenize(text) {
+                if let Some(vector) = word_vectors.get(&token) {
+                    for (value, component) in embedding.iter_mut().zip(vector) {
+                        *value += *component as f64;
+                    }
+                    matched += 1;
+                }
+            }
+            if matched > 0 {
+                return normalize(embedding);
+            }
+        }
+
+        for token in Self::tokenize(text) {
+            self.add_hashed_token(&token, &mut embedding);
+        }
+        normalize(embedding)
+    }
+
+    fn add_hashed_token(&self, token: &str, embedding: &mut [f64]) {
+        let padded: Vec<char> = format!("<{}>", token).chars().collect();
+        for trigram in padded.windows(3) {
+            let hash = fnv1a(trigram.iter().collect::<String>().as_bytes());
+            let index = (hash % self.dimension as u64) as usize;
+            // Use a separate hash bit for the sign so collisions cancel out on average
+            let sign = if hash & (1 << 63) == 0 { 1.0 } else { -1.0 };
+            embedding[index] += sign;
+        }
+    }
+
+    /// The pixel-thumbnail baseline used in place of an image embedding
+    fn pixel_thumbnail_vector(&self, image_bytes: &[u8]) -> Result<Vec<f64>> {
+        let image = image::load_from_memory(image_bytes)?;
+
+        // Largest square thumbnail that fits in the embedding dimension; any
+        // remaining components stay zero and don't affect cosine similarity
+        let side = (self.dimension as f64).sqrt() as u32;
+        if side == 0 {
+            return Err(anyhow::anyhow!("Embedding dimension is too small"));
+        }
+        let thumbnail = image
+            .resize_exact(side, side, FilterType::Triangle)
+            .to_luma8();
+
+        let pixels: Vec<f64> = thumbnail.pixels().map(|p| p.0[0] as f64).collect();
+        let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+
+        let mut embedding = vec![0.0; self.dimension];
+        for (value, pixel) in embedding.iter_mut().zip(&pixels) {
+            *value = pixel - mean;
+        }
+
+        Ok(normalize(embedding))
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for LocalEmbeddingProvider {
+    async fn embed_text(&self, text: &str) -> Result<Vec<f64>> {
+        let provider = self.clone();
+        let text = text.to_string();
+        Ok(tokio::task::spawn_blocking(move || provider.embed_text_sync(&text)).await?)
+    }
+
+    async fn embed_image(&self, image_base64: &str) -> Result<Vec<f64>> {
+        let image_bytes = base64_engine.decode(image_base64)?;
+        let provider = self.clone();
+        tokio::task::spawn_blocking(move || provider.pixel_thumbnail_vector(&image_bytes)).await?
+    }
+
+    fn max_text_chars(&self) -> usize {
+        MAX_TEXT_CHARS
+    }
+}
+
+fn normalize(mut vector: Vec<f64>) -> Vec<f64> {
+    let norm = vector.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for value in vector.iter_mut() {
+            *value /= norm;
+        }
+    }
+    vector
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod local_embedding_test {
+    use super::*;
+    use crate::worker::Deduplicator;
+
+    fn cosine(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[tokio::test]
+    async fn test_hashed_text_embeddings() {
+        let provider = LocalEmbeddingProvider::new(384);
+
+        let original = provider
+            .embed_text("The quick brown fox jumps over the lazy dog")
+            .await
+            .unwrap();
+        let edited = provider
+            .embed_text("The quick brown fox jumped over the lazy dog")
+            .await
+            .unwrap();
+        let unrelated = provider
+            .embed_text("Quarterly revenue grew by twelve percent")
+            .await
+            .unwrap();
+
+        assert_eq!(original.len(), 384);
+        assert!(cosine(&original, &edited) > 0.8);
+        assert!(cosine(&original, &unrelated) < cosine(&original, &edited));
+    }
+
+    #[tokio::test]
+    async fn test_word_vector_model() {
+        let model_path = std::env::temp_dir().join("local-embedding-test-vectors.txt");
+        std::fs::write(&model_path, "2 3\ncat 1 0 0\ndog 0.9 0.1 0\ncar 0 0 1\n").unwrap();
+
+        let provider = LocalEmbeddingProvider::load(model_path.to_str().unwrap(), 3).unwrap();
+        let cat = provider.embed_text("Cat").await.unwrap();
+        let dog = provider.embed_text("dog").await.unwrap();
+        let car = provider.embed_text("car").await.unwrap();
+
+        assert_eq!(cat.len(), 3);
+        assert!(cosine(&cat, &dog) > cosine(&cat, &car));
+
+        std::fs::remove_file(model_path).ok();
+    }
+
+    #[test]
+    fn test_word_vector_model_must_match_the_index_dimension() {
+        let model_path = std::env::temp_dir().join("local-embedding-test-dimension.txt");
+        std::fs::write(&model_path, "cat 1 0 0\ndog 0.9 0.1 0\n").unwrap();
+
+        let result = LocalEmbeddingProvider::load(model_path.to_str().unwrap(), 1536);
+
+        assert!(result.is_err());
+        std::fs::remove_file(model_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_pixel_thumbnail_image_vectors() {
+        let provider = LocalEmbeddingProvider::new(256);
+        let image_base64 =
+            Deduplicator::generate_base64_for_image("src/worker/test_data/spiderman_meme.jpg")
+                .unwrap();
+
+        let embedding = provider.embed_image(&image_base64).await.unwrap();
+
+        assert_eq!(embedding.len(), 256);
+        assert!((cosine(&embedding, &embedding) - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,57 @@
+pub mod bedrock;
+pub mod chunking;
+pub mod local;
+pub mod rate_limited;
+
+use crate::config::{Config, EmbeddingProviderKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub use bedrock::BedrockEmbeddingProvider;
+pub use chunking::embed_document;
+pub use local::LocalEmbeddingProvider;
+pub use rate_limited::RateLimitedEmbeddingProvider;
+
+/// Turns file content into vectors for near-duplicate search in OpenSearch.
+/// Every provider must return vectors of the dimension the OpenSearch indexes
+/// were created with.
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    async fn embed_text(&self, text: &str) -> Result<Vec<f64>>;
+
+    async fn embed_image(&self, image_base64: &str) -> Result<Vec<f64>>;
+
+    /// Longest text, in characters, accepted by a single `embed_text` call.
+    /// Longer documents go through `embed_document`.
+    fn max_text_chars(&self) -> usize;
+}
+
+/// Build the embedding provider selected by `EMBEDDING_PROVIDER`
+pub async fn create_embedding_provider(config: &Config) -> Result<Arc<dyn EmbeddingProvider>> {
+    let provider: Arc<dyn EmbeddingProvider> = match config.embedding_provider {
+        EmbeddingProviderKind::Bedrock => Arc::new(
+            BedrockEmbeddingProvider::new(&config.aws_profile_name, &config.bedrock_model_id).await,
+        ),
+        EmbeddingProviderKind::Local => match &config.local_embedding_model_path {
+            Some(model_path) => Arc::new(LocalEmbeddingProvider::load(
+                model_path,
+                config.local_embedding_dimension,
+            )?),
+            None => Arc::new(LocalEmbeddingProvider::new(
+                config.local_embedding_dimension,
+            )),
+        },
+    };
+
+    // The local provider runs in-process and has nothing to be throttled by
+    if config.embedding_provider == EmbeddingProviderKind::Local
+        || config.embedding_requests_per_second <= 0.0
+    {
+        return Ok(provider);
+    }
+    Ok(Arc::new(RateLimitedEmbeddingProvider::new(
+        provider,
+        config.embedding_requests_per_second,
+    )))
+}
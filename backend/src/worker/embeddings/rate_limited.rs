@@ -0,0 +1,38 @@
+use super::EmbeddingProvider;
+use crate::worker::rate_limit::RateLimiter;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Holds every request to the wrapped provider to a shared rate, so parallel
+/// jobs don't get throttled by Bedrock
+pub struct RateLimitedEmbeddingProvider {
+    inner: Arc<dyn EmbeddingProvider>,
+    limiter: RateLimiter,
+}
+
+impl RateLimitedEmbeddingProvider {
+    pub fn new(inner: Arc<dyn EmbeddingProvider>, requests_per_second: f64) -> Self {
+        Self {
+            inner,
+            limiter: RateLimiter::new(requests_per_second),
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for RateLimitedEmbeddingProvider {
+    async fn embed_text(&self, text: &str) -> Result<Vec<f64>> {
+        self.limiter.acquire().await;
+        self.inner.embed_text(text).await
+    }
+
+    async fn embed_image(&self, image_base64: &str) -> Result<Vec<f64>> {
+        self.limiter.acquire().await;
+        self.inner.embed_image(image_base64).await
+    }
+
+    fn max_text_chars(&self) -> usize {
+        self.inner.max_text_chars()
+    }
+}
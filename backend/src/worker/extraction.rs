@@ -0,0 +1,375 @@
+use pulldown_cmark::{Event as MarkdownEvent, Parser, TagEnd};
+use quick_xml::Reader;
+use quick_xml::events::Event as XmlEvent;
+use std::fmt;
+use std::io::{Cursor, Read};
+
+/// Bytes inspected when sniffing formats that have no magic number
+const SNIFF_BYTES: usize = 1024;
+/// Width used when rendering HTML; wide enough that paragraphs aren't wrapped
+const HTML_RENDER_WIDTH: usize = 10_000;
+
+/// Content type of a file, decided from its bytes rather than its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    Image,
+    Pdf,
+    Docx,
+    Html,
+    Markdown,
+    PlainText,
+    /// Binary content with no text extractor, e.g. archives or executables
+    Unsupported,
+}
+
+impl ContentKind {
+    /// Sniff the content type from the leading bytes. The file name is only
+    /// consulted to tell Markdown apart from other plain text, since Markdown
+    /// has no signature of its own.
+    pub fn detect(content: &[u8], file_name: &str) -> Self {
+        if let Some(kind) = infer::get(content) {
+            return match kind.mime_type() {
+                mime if mime.starts_with("image/") => ContentKind::Image,
+                "application/pdf" => ContentKind::Pdf,
+                // Word documents are zip archives; infer only recognizes
+                // them when the archive entries happen to be in a certain order
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+                | "application/zip"
+                    if is_docx(content) =>
+                {
+                    ContentKind::Docx
+                }
+                "text/html" => ContentKind::Html,
+                _ => ContentKind::Unsupported,
+            };
+        }
+
+        let head = &content[..content.len().min(SNIFF_BYTES)];
+        if !looks_like_text(head) {
+            return ContentKind::Unsupported;
+        }
+
+        let lowercase_head = String::from_utf8_lossy(head).to_lowercase();
+        let trimmed = lowercase_head.trim_start_matches('\u{feff}').trim_start();
+        if trimmed.starts_with("<!doctype html")
+            || trimmed.starts_with("<html")
+            || lowercase_head.contains("<body")
+        {
+            return ContentKind::Html;
+        }
+
+        let extension = file_name.rsplit('.').next().unwrap_or("").to_lowercase();
+        if matches!(extension.as_str(), "md" | "markdown") {
+            ContentKind::Markdown
+        } else {
+            ContentKind::PlainText
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentKind::Image => "image",
+            ContentKind::Pdf => "pdf",
+            ContentKind::Docx => "docx",
+            ContentKind::Html => "html",
+            ContentKind::Markdown => "markdown",
+            ContentKind::PlainText => "text",
+            ContentKind::Unsupported => "unsupported",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExtractionError {
+    /// The object was larger than the download limit, and this format cannot
+    /// be parsed from a truncated prefix
+    Truncated(ContentKind),
+    /// The content claimed to be this format but could not be parsed
+    Malformed(ContentKind, String),
+}
+
+impl fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractionError::Truncated(kind) => {
+                write!(
+                    f,
+                    "{} file is too large to extract text from",
+                    kind.as_str()
+                )
+            }
+            ExtractionError::Malformed(kind, message) => {
+                write!(
+                    f,
+                    "Failed to extract text from {}: {}",
+                    kind.as_str(),
+                    message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractionError {}
+
+/// Extract normalized plain text from a document. Returns `None` for images
+/// and unsupported binary content, which have no text. `truncated` says
+/// whether `content` is only a prefix of the object.
+pub fn extract_text(
+    kind: ContentKind,
+    content: &[u8],
+    truncated: bool,
+) -> Result<Option<String>, ExtractionError> {
+    let text = match kind {
+        ContentKind::Image | ContentKind::Unsupported => return Ok(None),
+        // Container formats can't be parsed from a prefix
+        ContentKind::Pdf | ContentKind::Docx if truncated => {
+            return Err(ExtractionError::Truncated(kind));
+        }
+        ContentKind::Pdf => extract_pdf(content)?,
+        ContentKind::Docx => extract_docx(content)?,
+        ContentKind::Html => html2text::config::plain_no_decorate()
+            .string_from_read(content, HTML_RENDER_WIDTH)
+            .map_err(|e| ExtractionError::Malformed(kind, e.to_string()))?,
+        ContentKind::Markdown => extract_markdown(&String::from_utf8_lossy(content)),
+        ContentKind::PlainText => String::from_utf8_lossy(content).into_owned(),
+    };
+
+    Ok(Some(normalize_text(&text)))
+}
+
+/// Normalize extracted text so formatting differences don't affect hashing
+/// or similarity: line endings become `\n`, control characters are dropped,
+/// runs of spaces and tabs collapse to one space, and blank lines collapse to
+/// a single paragraph break.
+pub fn normalize_text(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+
+    for line in text.lines() {
+        let line = line
+            .split(|c: char| c.is_whitespace() || c.is_control())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        normalized.push_str(&line);
+        blank_lines = 0;
+    }
+
+    normalized
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sniffed prefix may end in the middle of a multi-byte character
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+fn is_docx(content: &[u8]) -> bool {
+    zip::ZipArchive::new(Cursor::new(content))
+        .map(|archive| archive.index_for_name("word/document.xml").is_some())
+        .unwrap_or(false)
+}
+
+fn extract_pdf(content: &[u8]) -> Result<String, ExtractionError> {
+    // pdf-extract panics on some malformed files rather than returning an error
+    let content = content.to_vec();
+    match std::panic::catch_unwind(move || pdf_extract::extract_text_from_mem(&content)) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(ExtractionError::Malformed(ContentKind::Pdf, e.to_string())),
+        Err(_) => Err(ExtractionError::Malformed(
+            ContentKind::Pdf,
+            "PDF parser panicked".to_string(),
+        )),
+    }
+}
+
+fn extract_docx(content: &[u8]) -> Result<String, ExtractionError> {
+    let malformed = |message: String| ExtractionError::Malformed(ContentKind::Docx, message);
+
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(content)).map_err(|e| malformed(e.to_string()))?;
+    let mut document_xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| malformed(e.to_string()))?
+        .read_to_string(&mut document_xml)
+        .map_err(|e| malformed(e.to_string()))?;
+
+    let mut reader = Reader::from_str(&document_xml);
+    let mut text = String::new();
+    let mut in_text_run = false;
+
+    loop {
+        match reader.read_event().map_err(|e| malformed(e.to_string()))? {
+            XmlEvent::Start(e) if e.local_name().as_ref() == "t" => in_text_run = true,
+            XmlEvent::End(e) => match e.local_name().as_ref() {
+                "t" => in_text_run = false,
+                "p" => text.push('\n'),
+                _ => {}
+            },
+            XmlEvent::Empty(e) => match e.local_name().as_ref() {
+                "tab" => text.push('\t'),
+                "br" | "cr" => text.push('\n'),
+                _ => {}
+            },
+            XmlEvent::Text(e) if in_text_run => text.push_str(&e.xml10_content()),
+            XmlEvent::GeneralRef(e) if in_text_run => {
+                let entity = format!("&{};", &*e);
+                let resolved =
+                    quick_xml::escape::unescape(&entity).map_err(|e| malformed(e.to_string()))?;
+                text.push_str(&resolved);
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(text)
+}
+
+fn extract_markdown(markdown: &str) -> String {
+    let mut text = String::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            MarkdownEvent::Text(content) | MarkdownEvent::Code(content) => text.push_str(&content),
+            MarkdownEvent::SoftBreak => text.push(' '),
+            MarkdownEvent::HardBreak => text.push('\n'),
+            MarkdownEvent::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::CodeBlock
+                | TagEnd::TableRow,
+            ) => text.push_str("\n\n"),
+            MarkdownEvent::End(TagEnd::TableCell) => text.push(' '),
+            _ => {}
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod extraction_test {
+    use super::*;
+    use std::io::Write;
+
+    fn docx_with_body(body: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut zip = zip::ZipWriter::new(Cursor::new(&mut bytes));
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("[Content_Types].xml", options).unwrap();
+            zip.write_all(b"<?xml version=\"1.0\"?><Types/>").unwrap();
+            zip.start_file("word/document.xml", options).unwrap();
+            write!(
+                zip,
+                "<?xml version=\"1.0\"?><w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"><w:body>{}</w:body></w:document>",
+                body
+            )
+            .unwrap();
+            zip.finish().unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_detects_by_content_not_extension() {
+        let image = std::fs::read("src/worker/test_data/spiderman_meme.jpg").unwrap();
+        let html = b"<!DOCTYPE html><html><body><p>Hi</p></body></html>";
+
+        assert_eq!(ContentKind::detect(&image, "notes.txt"), ContentKind::Image);
+        assert_eq!(
+            ContentKind::detect(b"%PDF-1.7\n", "a.txt"),
+            ContentKind::Pdf
+        );
+        assert_eq!(ContentKind::detect(html, "export.txt"), ContentKind::Html);
+        assert_eq!(
+            ContentKind::detect(b"# Title", "README.md"),
+            ContentKind::Markdown
+        );
+        assert_eq!(
+            ContentKind::detect(b"plain", "data.bin"),
+            ContentKind::PlainText
+        );
+        assert_eq!(
+            ContentKind::detect(&[0, 159, 146, 150, 0, 1], "a.txt"),
+            ContentKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn test_extracts_docx_paragraphs() {
+        let docx = docx_with_body(
+            "<w:p><w:r><w:t>Terms &amp; Conditions</w:t></w:r></w:p>\
+             <w:p><w:r><w:t xml:space=\"preserve\">Payment is </w:t></w:r><w:r><w:t>due.</w:t></w:r></w:p>",
+        );
+
+        assert_eq!(
+            ContentKind::detect(&docx, "contract.bin"),
+            ContentKind::Docx
+        );
+        assert_eq!(
+            extract_text(ContentKind::Docx, &docx, false)
+                .unwrap()
+                .unwrap(),
+            "Terms & Conditions\nPayment is due."
+        );
+    }
+
+    #[test]
+    fn test_extracts_html_and_markdown() {
+        let html = b"<html><head><style>p { color: red; }</style></head>\
+                     <body><h1>Notice</h1><p>Payment is <b>due</b>.</p></body></html>";
+        let text = extract_text(ContentKind::Html, html, false)
+            .unwrap()
+            .unwrap();
+        assert!(text.contains("Notice"));
+        assert!(text.contains("Payment is due."));
+        assert!(!text.contains("color"));
+
+        let markdown = b"# Notice\n\nPayment is **due**\nwithin `30` days.";
+        assert_eq!(
+            extract_text(ContentKind::Markdown, markdown, false)
+                .unwrap()
+                .unwrap(),
+            "Notice\n\nPayment is due within 30 days."
+        );
+    }
+
+    #[test]
+    fn test_extraction_failures() {
+        assert!(matches!(
+            extract_text(ContentKind::Pdf, b"%PDF-1.7 garbage", false),
+            Err(ExtractionError::Malformed(ContentKind::Pdf, _))
+        ));
+        assert!(matches!(
+            extract_text(ContentKind::Docx, b"PK\x03\x04", true),
+            Err(ExtractionError::Truncated(ContentKind::Docx))
+        ));
+        assert_eq!(extract_text(ContentKind::Image, b"", false).unwrap(), None);
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(
+            normalize_text("  Line\tone \r\nline   two\r\n\r\n\r\n\x07para\u{0}graph  "),
+            "Line one\nline two\n\npara graph"
+        );
+    }
+}
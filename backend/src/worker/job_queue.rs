@@ -1,9 +1,172 @@
 use anyhow::Result;
 use redis::{Client, Commands, Connection};
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+// Jobs are delivered at least once. A worker claims a job by atomically moving
+// it from the queue into its own processing list, then takes out a lease that
+// it keeps extending while the job runs. Jobs are acknowledged (removed) only
+// once processed. The reaper puts a job back on the queue when its lease
+// expires, or when its worker stops heartbeating before leasing it.
+//
+// Each priority has one queue per owner and a rotation of the owners that have
+// jobs queued. Claims take the highest priority with queued jobs, and within
+// it the next owner in the rotation, so one owner's bulk import doesn't hold up
+// everyone else's uploads.
+const QUEUE_KEY_PREFIX: &str = "deduplication_jobs:queue:";
+const OWNERS_KEY_PREFIX: &str = "deduplication_jobs:owners:";
+/// Single queue used before priorities, drained after all of them
+const LEGACY_QUEUE_KEY: &str = "deduplication_jobs";
+const PROCESSING_KEY_PREFIX: &str = "deduplication_jobs:processing:";
+/// Sorted set of job ids scored by lease deadline (unix seconds)
+const LEASES_KEY: &str = "deduplication_jobs:leases";
+/// Hash of job id to the claiming worker and the job's raw payload
+const INFLIGHT_KEY: &str = "deduplication_jobs:inflight";
+const WORKER_KEY_PREFIX: &str = "deduplication_workers:";
+/// Sorted set of job payloads waiting to be retried, scored by when they are due
+const DELAYED_KEY: &str = "deduplication_jobs:delayed";
+/// Hash of job id to dead job, for jobs that won't be retried automatically
+const DEAD_JOBS_KEY: &str = "deduplication_jobs:dead_jobs";
+/// One list of dead job ids per workspace, most recent first
+const DEAD_KEY_PREFIX: &str = "deduplication_jobs:dead:";
+/// One list per owner for dead jobs without a workspace
+const UNSCOPED_DEAD_KEY_PREFIX: &str = "deduplication_jobs:dead:owner:";
+/// Dead jobs kept per list; the oldest are dropped beyond this
+const MAX_DEAD_JOBS_PER_LIST: usize = 1000;
+
+pub const DEFAULT_VISIBILITY_TIMEOUT_SECS: u64 = 300;
+/// A worker that hasn't heartbeated for this long is considered dead
+pub const WORKER_HEARTBEAT_TTL_SECS: u64 = 30;
+
+/// Add a job to the back of its owner's queue, and the owner to the rotation.
+/// KEYS: owner queue, owner rotation; ARGV: payload, owner.
+const ENQUEUE_SCRIPT: &str = r"
+redis.call('LPUSH', KEYS[1], ARGV[1])
+if not redis.call('LPOS', KEYS[2], ARGV[2]) then
+    redis.call('RPUSH', KEYS[2], ARGV[2])
+end
+";
+
+/// Claim the job at the back of the owner's queue, if the owner is still at
+/// the head of the rotation, and move the owner to the back of the rotation,
+/// or out of it once their queue is empty.
+/// KEYS: processing list, owner rotation, owner queue; ARGV: owner.
+const CLAIM_SCRIPT: &str = r"
+if redis.call('LINDEX', KEYS[2], 0) ~= ARGV[1] then
+    return false
+end
+redis.call('LMOVE', KEYS[2], KEYS[2], 'LEFT', 'RIGHT')
+local payload = redis.call('RPOP', KEYS[3])
+if redis.call('LLEN', KEYS[3]) == 0 then
+    redis.call('LREM', KEYS[2], 0, ARGV[1])
+end
+if payload then
+    redis.call('LPUSH', KEYS[1], payload)
+end
+return payload
+";
+
+/// Move a claimed job back to the front of its owner's queue, and the owner
+/// to the front of the rotation, unless the job was already acknowledged or
+/// requeued. KEYS: processing list, owner queue, owner rotation, leases,
+/// inflight; ARGV: payload, job id, owner.
+const REQUEUE_SCRIPT: &str = r"
+local removed = redis.call('LREM', KEYS[1], 1, ARGV[1])
+if removed > 0 then
+    redis.call('RPUSH', KEYS[2], ARGV[1])
+    if not redis.call('LPOS', KEYS[3], ARGV[3]) then
+        redis.call('LPUSH', KEYS[3], ARGV[3])
+    end
+end
+redis.call('ZREM', KEYS[4], ARGV[2])
+redis.call('HDEL', KEYS[5], ARGV[2])
+return removed
+";
+
+/// Move a due retry to the back of its owner's queue, unless another worker
+/// already did. KEYS: delayed set, owner queue, owner rotation; ARGV: payload, owner.
+const PROMOTE_SCRIPT: &str = r"
+local removed = redis.call('ZREM', KEYS[1], ARGV[1])
+if removed > 0 then
+    redis.call('LPUSH', KEYS[2], ARGV[1])
+    if not redis.call('LPOS', KEYS[3], ARGV[2]) then
+        redis.call('RPUSH', KEYS[3], ARGV[2])
+    end
+end
+return removed
+";
+
+/// Add a job to the dead-letter queue, dropping the oldest jobs of its list
+/// beyond the limit. KEYS: dead jobs, dead job ids; ARGV: job id, dead job, limit.
+const DEAD_LETTER_SCRIPT: &str = r"
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+redis.call('LPUSH', KEYS[2], ARGV[1])
+while redis.call('LLEN', KEYS[2]) > tonumber(ARGV[3]) do
+    redis.call('HDEL', KEYS[1], redis.call('RPOP', KEYS[2]))
+end
+";
+
+/// Take a job off the dead-letter queue, unless another request already did.
+/// KEYS: dead jobs, dead job ids; ARGV: job id.
+const REMOVE_DEAD_SCRIPT: &str = r"
+local removed = redis.call('HDEL', KEYS[1], ARGV[1])
+if removed > 0 then
+    redis.call('LREM', KEYS[2], 1, ARGV[1])
+end
+return removed
+";
+
+fn queue_key(priority: JobPriority, owner: &str) -> String {
+    format!("{}{}:{}", QUEUE_KEY_PREFIX, priority.as_str(), owner)
+}
+
+fn owners_key(priority: JobPriority) -> String {
+    format!("{}{}", OWNERS_KEY_PREFIX, priority.as_str())
+}
+
+fn processing_key(worker_id: &str) -> String {
+    format!("{}{}", PROCESSING_KEY_PREFIX, worker_id)
+}
+
+fn worker_key(worker_id: &str) -> String {
+    format!("{}{}", WORKER_KEY_PREFIX, worker_id)
+}
+
+fn now_secs() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Jobs of a higher priority are always claimed first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    /// Files a user just uploaded and is waiting on
+    #[default]
+    Interactive,
+    /// Files from bulk imports
+    Bulk,
+    /// Re-running deduplication over files already processed
+    Reindex,
+}
+
+impl JobPriority {
+    /// Highest priority first
+    pub const ALL: [JobPriority; 3] = [
+        JobPriority::Interactive,
+        JobPriority::Bulk,
+        JobPriority::Reindex,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobPriority::Interactive => "interactive",
+            JobPriority::Bulk => "bulk",
+            JobPriority::Reindex => "reindex",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeduplicationJob {
     pub job_id: String,
@@ -12,20 +175,85 @@ pub struct DeduplicationJob {
     pub file_path: String,
     pub s3_key: String,
     pub created_at: u64,
+    /// Failed attempts so far
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// Id of the user the job is queued for; jobs are shared out fairly
+    /// between users
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Id of the workspace the job's file belongs to
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+}
+
+impl DeduplicationJob {
+    fn queue_key(&self) -> String {
+        queue_key(self.priority, self.owner_key())
+    }
+
+    /// Jobs without an owner share one queue
+    fn owner_key(&self) -> &str {
+        self.owner.as_deref().unwrap_or("")
+    }
+}
+
+/// Where dead jobs are listed: with their workspace, or for jobs without one,
+/// such as those queued before workspaces, with their owner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeadJobList<'a> {
+    Workspace(&'a str),
+    Unscoped { owner: &'a str },
+}
+
+impl<'a> DeadJobList<'a> {
+    fn of(job: &'a DeduplicationJob) -> Self {
+        match &job.workspace_id {
+            Some(workspace_id) => DeadJobList::Workspace(workspace_id),
+            None => DeadJobList::Unscoped {
+                owner: job.owner_key(),
+            },
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            DeadJobList::Workspace(workspace_id) => format!("{}{}", DEAD_KEY_PREFIX, workspace_id),
+            DeadJobList::Unscoped { owner } => format!("{}{}", UNSCOPED_DEAD_KEY_PREFIX, owner),
+        }
+    }
+}
+
+/// A job that failed permanently or ran out of attempts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeadJob {
+    pub job: DeduplicationJob,
+    pub error_message: String,
+    pub failed_at: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JobStatus {
     pub job_id: String,
-    pub status: String, // "pending", "processing", "completed", "failed"
+    pub status: String, // "pending", "processing", "retrying", "completed", "failed"
     pub created_at: u64,
     pub updated_at: u64,
     pub error_message: Option<String>,
 }
 
+/// A claimed job's owner and payload, kept until the job is acknowledged
+#[derive(Debug, Serialize, Deserialize)]
+struct InflightJob {
+    worker_id: String,
+    payload: String,
+}
+
 #[derive(Clone)]
 pub struct JobQueue {
     redis_client: Client,
+    visibility_timeout_secs: u64,
 }
 
 impl JobQueue {
@@ -33,9 +261,21 @@ impl JobQueue {
         let client = Client::open(redis_url)?;
         Ok(JobQueue {
             redis_client: client,
+            visibility_timeout_secs: DEFAULT_VISIBILITY_TIMEOUT_SECS,
         })
     }
 
+    /// How long a claimed job may go without a lease extension before it is
+    /// handed to another worker
+    pub fn with_visibility_timeout(mut self, visibility_timeout_secs: u64) -> Self {
+        self.visibility_timeout_secs = visibility_timeout_secs;
+        self
+    }
+
+    pub fn visibility_timeout_secs(&self) -> u64 {
+        self.visibility_timeout_secs
+    }
+
     pub fn get_connection(&self) -> Result<Connection> {
         Ok(self.redis_client.get_connection()?)
     }
@@ -47,7 +287,12 @@ impl JobQueue {
         let job_data = serde_json::to_string(&job)?;
 
         // Add to the job queue
-        let _: () = conn.lpush("deduplication_jobs", &job_data)?;
+        let _: () = redis::Script::new(ENQUEUE_SCRIPT)
+            .key(job.queue_key())
+            .key(owners_key(job.priority))
+            .arg(&job_data)
+            .arg(job.owner_key())
+            .invoke(&mut conn)?;
 
         // Store job status as pending
         self.update_job_status(&job.job_id, "pending", None).await?;
@@ -56,25 +301,381 @@ impl JobQueue {
         Ok(job.job_id)
     }
 
-    pub async fn dequeue_job(&self) -> Result<Option<DeduplicationJob>> {
+    /// Claim the next job for `worker_id`, if any is queued. The job must be
+    /// acknowledged once processed.
+    pub async fn dequeue_job(&self, worker_id: &str) -> Result<Option<DeduplicationJob>> {
+        let mut conn = self.get_connection()?;
+        let processing_key = processing_key(worker_id);
+
+        let Some(payload) = self.claim_payload(&mut conn, &processing_key)? else {
+            return Ok(None);
+        };
+
+        let job: DeduplicationJob = match serde_json::from_str(&payload) {
+            Ok(job) => job,
+            Err(e) => {
+                // Drop payloads that can never be processed instead of redelivering them
+                let _: () = conn.lrem(&processing_key, 1, &payload)?;
+                return Err(e.into());
+            }
+        };
+
+        let inflight = serde_json::to_string(&InflightJob {
+            worker_id: worker_id.to_string(),
+            payload,
+        })?;
+        let _: () = redis::pipe()
+            .atomic()
+            .zadd(
+                LEASES_KEY,
+                &job.job_id,
+                now_secs()? + self.visibility_timeout_secs,
+            )
+            .ignore()
+            .hset(INFLIGHT_KEY, &job.job_id, inflight)
+            .ignore()
+            .query(&mut conn)?;
+
+        // Update job status to processing
+        self.update_job_status(&job.job_id, "processing", None)
+            .await?;
+
+        Ok(Some(job))
+    }
+
+    /// Move the next job into the worker's processing list in one step, so it
+    /// is never held only in the worker's memory: the highest priority with
+    /// jobs queued, and within it the owner at the head of the rotation
+    fn claim_payload(&self, conn: &mut Connection, processing_key: &str) -> Result<Option<String>> {
+        let script = redis::Script::new(CLAIM_SCRIPT);
+
+        for priority in JobPriority::ALL {
+            let owners_key = owners_key(priority);
+            // A claim fails when another worker moved the rotation on first,
+            // or takes the owner out of it when their queue is empty, so try
+            // the new head until the rotation is empty
+            loop {
+                let owner: Option<String> = conn.lindex(&owners_key, 0)?;
+                let Some(owner) = owner else {
+                    break;
+                };
+
+                let payload: Option<String> = script
+                    .key(processing_key)
+                    .key(&owners_key)
+                    .key(queue_key(priority, &owner))
+                    .arg(&owner)
+                    .invoke(conn)?;
+                if payload.is_some() {
+                    return Ok(payload);
+                }
+            }
+        }
+
+        Ok(conn.lmove(
+            LEGACY_QUEUE_KEY,
+            processing_key,
+            redis::Direction::Right,
+            redis::Direction::Left,
+        )?)
+    }
+
+    /// Push the job's lease deadline out by the visibility timeout. Returns
+    /// false if `worker_id` no longer holds the job, e.g. because it was reaped.
+    pub async fn extend_lease(&self, worker_id: &str, job_id: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+
+        match self.inflight_job(&mut conn, job_id)? {
+            Some(inflight) if inflight.worker_id == worker_id => {
+                let _: () = conn.zadd(
+                    LEASES_KEY,
+                    job_id,
+                    now_secs()? + self.visibility_timeout_secs,
+                )?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Remove a processed job from the worker's processing list and leases
+    pub async fn acknowledge_job(&self, worker_id: &str, job_id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        // A job reaped from this worker may already belong to another one
+        let Some(inflight) = self
+            .inflight_job(&mut conn, job_id)?
+            .filter(|inflight| inflight.worker_id == worker_id)
+        else {
+            log::warn!("Job {} was no longer held by worker {}", job_id, worker_id);
+            return Ok(());
+        };
+
+        let _: () = redis::pipe()
+            .atomic()
+            .lrem(processing_key(worker_id), 1, &inflight.payload)
+            .ignore()
+            .zrem(LEASES_KEY, job_id)
+            .ignore()
+            .hdel(INFLIGHT_KEY, job_id)
+            .ignore()
+            .query(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Mark the worker as alive; its claimed jobs are requeued once it stops
+    pub async fn heartbeat_worker(&self, worker_id: &str) -> Result<()> {
         let mut conn = self.get_connection()?;
 
-        // Block for up to 5 seconds waiting for a job
-        let result: Option<Vec<String>> = conn.brpop("deduplication_jobs", 5.0)?;
+        let _: () = redis::cmd("SET")
+            .arg(worker_key(worker_id))
+            .arg(now_secs()?)
+            .arg("EX")
+            .arg(WORKER_HEARTBEAT_TTL_SECS)
+            .query(&mut conn)?;
 
-        if let Some(job_data) = result {
-            if job_data.len() >= 2 {
-                let job: DeduplicationJob = serde_json::from_str(&job_data[1])?;
+        Ok(())
+    }
 
-                // Update job status to processing
-                self.update_job_status(&job.job_id, "processing", None)
-                    .await?;
+    /// Put jobs back on the queue whose lease expired, or that were claimed by
+    /// a worker that died before leasing them. Returns the requeued job ids.
+    pub async fn requeue_abandoned_jobs(&self) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+        let mut requeued = Vec::new();
+
+        let expired: Vec<String> = conn.zrangebyscore(LEASES_KEY, "-inf", now_secs()?)?;
+        for job_id in expired {
+            let Some(inflight) = self.inflight_job(&mut conn, &job_id)? else {
+                let _: () = conn.zrem(LEASES_KEY, &job_id)?;
+                continue;
+            };
+
+            requeued.extend(self.requeue_claimed_job(
+                &mut conn,
+                &inflight.worker_id,
+                &inflight.payload,
+            )?);
+        }
 
-                return Ok(Some(job));
+        let processing_keys: Vec<String> = conn
+            .scan_match::<_, String>(format!("{}*", PROCESSING_KEY_PREFIX))?
+            .collect();
+        for key in processing_keys {
+            let worker_id = key.trim_start_matches(PROCESSING_KEY_PREFIX);
+            let alive: bool = conn.exists(worker_key(worker_id))?;
+            if alive {
+                continue;
             }
+
+            requeued.extend(self.requeue_processing_jobs(&mut conn, worker_id)?);
+        }
+
+        for job_id in &requeued {
+            log::warn!("Requeued abandoned job: {}", job_id);
+        }
+        Ok(requeued)
+    }
+
+    /// Put every job still claimed by `worker_id` back on the queue, for a
+    /// worker that is shutting down. Returns the requeued job ids.
+    pub async fn release_worker_jobs(&self, worker_id: &str) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+
+        let requeued = self.requeue_processing_jobs(&mut conn, worker_id)?;
+        let _: () = conn.del(worker_key(worker_id))?;
+
+        for job_id in &requeued {
+            log::warn!("Returned unfinished job {} to the queue", job_id);
+        }
+        Ok(requeued)
+    }
+
+    fn requeue_processing_jobs(
+        &self,
+        conn: &mut Connection,
+        worker_id: &str,
+    ) -> Result<Vec<String>> {
+        let mut requeued = Vec::new();
+
+        // Newest first, so the oldest ends up at the front of its queue
+        let payloads: Vec<String> = conn.lrange(processing_key(worker_id), 0, -1)?;
+        for payload in payloads {
+            requeued.extend(self.requeue_claimed_job(conn, worker_id, &payload)?);
+        }
+
+        Ok(requeued)
+    }
+
+    /// Put a job claimed by `worker_id` back at the front of its queue, so it
+    /// is picked up next. Returns the job id, or `None` if the job was no
+    /// longer claimed.
+    fn requeue_claimed_job(
+        &self,
+        conn: &mut Connection,
+        worker_id: &str,
+        payload: &str,
+    ) -> Result<Option<String>> {
+        let Ok(job) = serde_json::from_str::<DeduplicationJob>(payload) else {
+            // Drop payloads that can never be processed instead of redelivering them
+            let _: () = conn.lrem(processing_key(worker_id), 1, payload)?;
+            return Ok(None);
+        };
+
+        let removed: i64 = redis::Script::new(REQUEUE_SCRIPT)
+            .key(processing_key(worker_id))
+            .key(job.queue_key())
+            .key(owners_key(job.priority))
+            .key(LEASES_KEY)
+            .key(INFLIGHT_KEY)
+            .arg(payload)
+            .arg(&job.job_id)
+            .arg(job.owner_key())
+            .invoke(conn)?;
+
+        Ok((removed > 0).then_some(job.job_id))
+    }
+
+    /// Schedule another attempt of a failed job after `delay`
+    pub async fn schedule_retry(&self, job: &DeduplicationJob, delay: Duration) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        let job_data = serde_json::to_string(job)?;
+        let _: () = conn.zadd(DELAYED_KEY, &job_data, now_secs()? + delay.as_secs())?;
+
+        log::info!(
+            "Scheduled retry {} of job {} in {}s",
+            job.attempts + 1,
+            job.job_id,
+            delay.as_secs()
+        );
+        Ok(())
+    }
+
+    /// Move retries that are due onto the queue, returning how many were moved
+    pub async fn promote_delayed_jobs(&self) -> Result<usize> {
+        let mut conn = self.get_connection()?;
+
+        let due: Vec<String> =
+            conn.zrangebyscore_limit(DELAYED_KEY, "-inf", now_secs()?, 0, 100)?;
+        let mut promoted = 0;
+        for payload in due {
+            let Ok(job) = serde_json::from_str::<DeduplicationJob>(&payload) else {
+                let _: () = conn.zrem(DELAYED_KEY, &payload)?;
+                continue;
+            };
+
+            let removed: i64 = redis::Script::new(PROMOTE_SCRIPT)
+                .key(DELAYED_KEY)
+                .key(job.queue_key())
+                .key(owners_key(job.priority))
+                .arg(&payload)
+                .arg(job.owner_key())
+                .invoke(&mut conn)?;
+            promoted += removed as usize;
+        }
+
+        Ok(promoted)
+    }
+
+    /// Move a job to the dead-letter queue
+    pub async fn dead_letter_job(&self, job: &DeduplicationJob, error_message: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+
+        let dead_job = DeadJob {
+            job: job.clone(),
+            error_message: error_message.to_string(),
+            failed_at: now_secs()?,
+        };
+        let _: () = redis::Script::new(DEAD_LETTER_SCRIPT)
+            .key(DEAD_JOBS_KEY)
+            .key(DeadJobList::of(job).key())
+            .arg(&job.job_id)
+            .arg(serde_json::to_string(&dead_job)?)
+            .arg(MAX_DEAD_JOBS_PER_LIST)
+            .invoke(&mut conn)?;
+
+        log::warn!(
+            "Moved job {} to the dead-letter queue after {} attempts",
+            job.job_id,
+            job.attempts
+        );
+        Ok(())
+    }
+
+    /// A page of the list's dead jobs, most recent first, and the total
+    /// number of them
+    pub async fn get_dead_jobs(
+        &self,
+        list: DeadJobList<'_>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<DeadJob>, i64)> {
+        let mut conn = self.get_connection()?;
+        let dead_key = list.key();
+
+        let total: i64 = conn.llen(&dead_key)?;
+        let job_ids: Vec<String> =
+            conn.lrange(&dead_key, offset as isize, (offset + limit - 1) as isize)?;
+        if job_ids.is_empty() {
+            return Ok((Vec::new(), total));
+        }
+
+        let entries: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(DEAD_JOBS_KEY)
+            .arg(&job_ids)
+            .query(&mut conn)?;
+        let dead_jobs = entries
+            .iter()
+            .flatten()
+            .filter_map(|entry| serde_json::from_str::<DeadJob>(entry).ok())
+            .collect();
+
+        Ok((dead_jobs, total))
+    }
+
+    /// Take a job off the dead-letter queue and enqueue it again with a fresh
+    /// attempt count. Returns `None` if the list has no dead job with that id.
+    pub async fn requeue_dead_job(
+        &self,
+        list: DeadJobList<'_>,
+        job_id: &str,
+    ) -> Result<Option<DeduplicationJob>> {
+        let mut conn = self.get_connection()?;
+
+        let entry: Option<String> = conn.hget(DEAD_JOBS_KEY, job_id)?;
+        let Some(dead_job) = entry.and_then(|entry| serde_json::from_str::<DeadJob>(&entry).ok())
+        else {
+            return Ok(None);
+        };
+        if DeadJobList::of(&dead_job.job) != list {
+            return Ok(None);
+        }
+
+        // Another request may have requeued it in the meantime
+        let removed: i64 = redis::Script::new(REMOVE_DEAD_SCRIPT)
+            .key(DEAD_JOBS_KEY)
+            .key(list.key())
+            .arg(job_id)
+            .invoke(&mut conn)?;
+        if removed == 0 {
+            return Ok(None);
         }
 
-        Ok(None)
+        let job = DeduplicationJob {
+            attempts: 0,
+            ..dead_job.job
+        };
+        self.enqueue_deduplication_job(job.clone()).await?;
+        Ok(Some(job))
+    }
+
+    fn inflight_job(&self, conn: &mut Connection, job_id: &str) -> Result<Option<InflightJob>> {
+        let inflight: Option<String> = conn.hget(INFLIGHT_KEY, job_id)?;
+        Ok(match inflight {
+            Some(data) => Some(serde_json::from_str(&data)?),
+            None => None,
+        })
     }
 
     pub async fn update_job_status(
@@ -130,6 +731,9 @@ impl JobQueue {
         file_name: String,
         file_path: String,
         s3_key: String,
+        priority: JobPriority,
+        owner: Option<String>,
+        workspace_id: Option<String>,
     ) -> DeduplicationJob {
         DeduplicationJob {
             job_id: Uuid::new_v4().to_string(),
@@ -141,6 +745,10 @@ impl JobQueue {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            attempts: 0,
+            priority,
+            owner,
+            workspace_id,
         }
     }
 }
@@ -160,6 +768,9 @@ mod tests {
             "test_file.txt".to_string(),
             "/tmp/test_file.txt".to_string(),
             "uploads/test_file.txt".to_string(),
+            JobPriority::Interactive,
+            Some("test-user".to_string()),
+            Some("test-workspace".to_string()),
         );
 
         // Test enqueue
@@ -172,10 +783,16 @@ mod tests {
         assert_eq!(status.unwrap().status, "pending");
 
         // Test dequeue
-        let dequeued_job = queue.dequeue_job().await.unwrap();
+        let dequeued_job = queue.dequeue_job("test-worker").await.unwrap();
         assert!(dequeued_job.is_some());
         assert_eq!(dequeued_job.unwrap().job_id, job_id);
 
+        // Test lease extension and acknowledgement
+        assert!(queue.extend_lease("test-worker", &job_id).await.unwrap());
+        assert!(!queue.extend_lease("other-worker", &job_id).await.unwrap());
+        queue.acknowledge_job("test-worker", &job_id).await.unwrap();
+        assert!(!queue.extend_lease("test-worker", &job_id).await.unwrap());
+
         // Test status update
         queue
             .update_job_status(&job_id, "completed", None)
@@ -184,5 +801,154 @@ mod tests {
         let updated_status = queue.get_job_status(&job_id).await.unwrap();
         assert!(updated_status.is_some());
         assert_eq!(updated_status.unwrap().status, "completed");
+
+        // Test retry scheduling and the dead-letter queue
+        let failed_job = DeduplicationJob {
+            attempts: 1,
+            ..job.clone()
+        };
+        queue
+            .schedule_retry(&failed_job, Duration::from_secs(0))
+            .await
+            .unwrap();
+        assert!(queue.promote_delayed_jobs().await.unwrap() >= 1);
+        let retried_job = queue.dequeue_job("test-worker").await.unwrap().unwrap();
+        assert_eq!(retried_job.job_id, job_id);
+        assert_eq!(retried_job.attempts, 1);
+        queue.acknowledge_job("test-worker", &job_id).await.unwrap();
+
+        queue.dead_letter_job(&retried_job, "boom").await.unwrap();
+        let (dead_jobs, _) = queue
+            .get_dead_jobs(DeadJobList::Workspace("test-workspace"), 0, 100)
+            .await
+            .unwrap();
+        assert!(dead_jobs.iter().any(|dead| dead.job.job_id == job_id));
+        let (other_dead_jobs, _) = queue
+            .get_dead_jobs(DeadJobList::Workspace("other-workspace"), 0, 100)
+            .await
+            .unwrap();
+        assert!(!other_dead_jobs.iter().any(|dead| dead.job.job_id == job_id));
+
+        // Only the job's workspace can requeue it
+        assert!(
+            queue
+                .requeue_dead_job(DeadJobList::Workspace("other-workspace"), &job_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let requeued_job = queue
+            .requeue_dead_job(DeadJobList::Workspace("test-workspace"), &job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(requeued_job.attempts, 0);
+        assert!(
+            queue
+                .requeue_dead_job(DeadJobList::Workspace("test-workspace"), &job_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let dequeued_job = queue.dequeue_job("test-worker").await.unwrap().unwrap();
+        assert_eq!(dequeued_job.job_id, job_id);
+        queue.acknowledge_job("test-worker", &job_id).await.unwrap();
+
+        // Jobs without a workspace are listed for their owner
+        let unscoped_job = DeduplicationJob {
+            workspace_id: None,
+            ..dequeued_job
+        };
+        queue.dead_letter_job(&unscoped_job, "boom").await.unwrap();
+        let (owner_dead_jobs, _) = queue
+            .get_dead_jobs(DeadJobList::Unscoped { owner: "test-user" }, 0, 100)
+            .await
+            .unwrap();
+        assert!(owner_dead_jobs.iter().any(|dead| dead.job.job_id == job_id));
+        assert!(
+            queue
+                .requeue_dead_job(DeadJobList::Unscoped { owner: "test-user" }, &job_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        queue.dequeue_job("test-worker").await.unwrap();
+        queue.acknowledge_job("test-worker", &job_id).await.unwrap();
+    }
+
+    #[test]
+    fn test_dead_jobs_without_a_workspace_are_listed_for_their_owner() {
+        let mut job = JobQueue::create_deduplication_job(
+            1,
+            "test_file.txt".to_string(),
+            "/tmp/test_file.txt".to_string(),
+            "uploads/test_file.txt".to_string(),
+            JobPriority::Reindex,
+            Some("test-user".to_string()),
+            Some("test-workspace".to_string()),
+        );
+        assert_eq!(
+            DeadJobList::of(&job),
+            DeadJobList::Workspace("test-workspace")
+        );
+
+        job.workspace_id = None;
+        let list = DeadJobList::of(&job);
+        assert_eq!(list, DeadJobList::Unscoped { owner: "test-user" });
+        assert_ne!(list.key(), DeadJobList::Workspace("test-user").key());
+    }
+
+    #[tokio::test]
+    async fn test_job_queue_priority_and_fairness() {
+        // This test requires Redis to be running
+        let queue = JobQueue::new("redis://127.0.0.1:6379").unwrap();
+        let worker_id = format!("test-worker-{}", Uuid::new_v4());
+        let importer = format!("importer-{}", Uuid::new_v4());
+        let other_user = format!("other-{}", Uuid::new_v4());
+
+        let new_job = |priority, owner: &str| {
+            JobQueue::create_deduplication_job(
+                1,
+                "test_file.txt".to_string(),
+                "/tmp/test_file.txt".to_string(),
+                "uploads/test_file.txt".to_string(),
+                priority,
+                Some(owner.to_string()),
+                None,
+            )
+        };
+
+        let mut import_ids = Vec::new();
+        for _ in 0..3 {
+            let job = new_job(JobPriority::Bulk, &importer);
+            import_ids.push(queue.enqueue_deduplication_job(job).await.unwrap());
+        }
+        let other_bulk_id = queue
+            .enqueue_deduplication_job(new_job(JobPriority::Bulk, &other_user))
+            .await
+            .unwrap();
+        let interactive_id = queue
+            .enqueue_deduplication_job(new_job(JobPriority::Interactive, &other_user))
+            .await
+            .unwrap();
+
+        let mut claimed = Vec::new();
+        for _ in 0..5 {
+            let job = queue.dequeue_job(&worker_id).await.unwrap().unwrap();
+            queue
+                .acknowledge_job(&worker_id, &job.job_id)
+                .await
+                .unwrap();
+            claimed.push(job.job_id);
+        }
+
+        // Interactive first, then the other user's job doesn't wait for the whole import
+        assert_eq!(claimed[0], interactive_id);
+        assert_eq!(claimed[2], other_bulk_id);
+        let import_order: Vec<String> = claimed
+            .into_iter()
+            .filter(|job_id| import_ids.contains(job_id))
+            .collect();
+        assert_eq!(import_order, import_ids);
     }
 }
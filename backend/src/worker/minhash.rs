@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+
+/// Words per shingle. Five-word shingles are long enough that boilerplate
+/// phrases shared by unrelated documents don't dominate the estimate.
+pub const SHINGLE_SIZE: usize = 5;
+/// Number of hash functions in a signature
+pub const NUM_PERMUTATIONS: usize = 128;
+/// LSH bands; with `ROWS_PER_BAND` rows each they must cover the signature.
+/// 32 bands of 4 rows make pairs above a Jaccard similarity of ~0.42 likely
+/// to share at least one bucket.
+pub const NUM_BANDS: usize = 32;
+pub const ROWS_PER_BAND: usize = NUM_PERMUTATIONS / NUM_BANDS;
+
+/// MinHash signature of a document's word shingles. The Jaccard similarity of
+/// two documents' shingle sets is estimated by the fraction of matching slots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinHashSignature(pub Vec<u64>);
+
+impl MinHashSignature {
+    /// Signature of the text, or `None` if it contains no words
+    pub fn from_text(text: &str) -> Option<Self> {
+        let shingles = shingles(text);
+        if shingles.is_empty() {
+            return None;
+        }
+
+        let seeds: Vec<u64> = (0..NUM_PERMUTATIONS as u64).map(splitmix64).collect();
+        let mut signature = vec![u64::MAX; NUM_PERMUTATIONS];
+        for shingle in &shingles {
+            for (slot, seed) in signature.iter_mut().zip(&seeds) {
+                *slot = (*slot).min(splitmix64(shingle ^ seed));
+            }
+        }
+
+        Some(MinHashSignature(signature))
+    }
+
+    pub fn jaccard_estimate(&self, other: &MinHashSignature) -> f64 {
+        let matching = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        matching as f64 / self.0.len().max(1) as f64
+    }
+
+    /// LSH bucket for each band. Documents sharing any bucket are candidates.
+    pub fn band_buckets(&self) -> Vec<u64> {
+        self.0
+            .chunks(ROWS_PER_BAND)
+            .map(|rows| {
+                let bytes: Vec<u8> = rows.iter().flat_map(|row| row.to_le_bytes()).collect();
+                fnv1a(&bytes)
+            })
+            .collect()
+    }
+
+    /// Signature as stored in Postgres BIGINT columns
+    pub fn to_i64s(&self) -> Vec<i64> {
+        self.0.iter().map(|&value| value as i64).collect()
+    }
+
+    pub fn from_i64s(values: &[i64]) -> Self {
+        MinHashSignature(values.iter().map(|&value| value as u64).collect())
+    }
+}
+
+/// Hashes of the overlapping `SHINGLE_SIZE`-word windows of the text. Words
+/// are lowercased and punctuation is ignored, so formatting changes don't
+/// affect the shingles.
+fn shingles(text: &str) -> HashSet<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+
+    // Documents shorter than one shingle are treated as a single shingle
+    let window = SHINGLE_SIZE.min(words.len()).max(1);
+    words
+        .windows(window)
+        .map(|shingle| fnv1a(shingle.join(" ").as_bytes()))
+        .collect()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// SplitMix64 finalizer, used both to derive per-permutation seeds and to mix
+/// a shingle hash with a seed into an independent-looking permutation
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod minhash_test {
+    use super::*;
+
+    const CONTRACT: &str = "This Agreement is entered into by and between the Supplier and the \
+        Customer. The Supplier shall deliver the goods described in Schedule A within thirty \
+        days of the order date. Payment is due within sixty days of delivery. Either party may \
+        terminate this Agreement with ninety days written notice. This Agreement is governed \
+        by the laws of the State of New York.";
+
+    #[test]
+    fn test_identical_text_after_reformatting() {
+        let original = MinHashSignature::from_text(CONTRACT).unwrap();
+        let reformatted =
+            MinHashSignature::from_text(&CONTRACT.to_uppercase().replace(". ", ".\n\n")).unwrap();
+
+        assert_eq!(original.jaccard_estimate(&reformatted), 1.0);
+        assert_eq!(original.band_buckets(), reformatted.band_buckets());
+    }
+
+    #[test]
+    fn test_lightly_edited_text_is_a_candidate() {
+        let original = MinHashSignature::from_text(CONTRACT).unwrap();
+        let edited = MinHashSignature::from_text(
+            &CONTRACT
+                .replace("thirty", "forty five")
+                .replace("New York", "Delaware"),
+        )
+        .unwrap();
+
+        let estimate = original.jaccard_estimate(&edited);
+        assert!(
+            estimate > 0.5 && estimate < 1.0,
+            "estimate was {}",
+            estimate
+        );
+        assert!(
+            original
+                .band_buckets()
+                .iter()
+                .zip(edited.band_buckets())
+                .any(|(a, b)| *a == b)
+        );
+    }
+
+    #[test]
+    fn test_unrelated_text_is_dissimilar() {
+        let contract = MinHashSignature::from_text(CONTRACT).unwrap();
+        let recipe = MinHashSignature::from_text(
+            "Preheat the oven to two hundred degrees. Mix the flour, sugar and butter until \
+             crumbly, then press the mixture into a lined tin and bake for twenty minutes.",
+        )
+        .unwrap();
+
+        assert!(contract.jaccard_estimate(&recipe) < 0.1);
+    }
+
+    #[test]
+    fn test_signature_round_trips_through_i64() {
+        let signature = MinHashSignature::from_text(CONTRACT).unwrap();
+
+        assert_eq!(signature.0.len(), NUM_PERMUTATIONS);
+        assert_eq!(signature.band_buckets().len(), NUM_BANDS);
+        assert_eq!(MinHashSignature::from_i64s(&signature.to_i64s()), signature);
+        assert!(MinHashSignature::from_text(" \n\t.,").is_none());
+    }
+}
@@ -1,9 +1,18 @@
+pub mod canonical_hash;
+pub mod cdc;
 pub mod deduplication_service;
 pub mod deduplicator;
+pub mod embeddings;
+pub mod extraction;
 pub mod job_queue;
+pub mod minhash;
+pub mod perceptual_hash;
+pub mod rate_limit;
+pub mod retry;
+pub mod trash_sweeper;
 pub mod worker_process;
 
 pub use deduplication_service::{DeduplicationResult, DeduplicationService, SimilarFile};
 pub use deduplicator::Deduplicator;
-pub use job_queue::{DeduplicationJob, JobQueue, JobStatus};
+pub use job_queue::{DeadJobList, DeduplicationJob, JobPriority, JobQueue, JobStatus};
 pub use worker_process::spawn_worker_process;
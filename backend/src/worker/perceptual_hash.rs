@@ -0,0 +1,163 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+/// Side of the grayscale thumbnail the pHash DCT runs over
+const PHASH_SIZE: usize = 32;
+/// Side of the low-frequency DCT block kept for the pHash bits
+const PHASH_LOW_FREQUENCIES: usize = 8;
+
+/// 64-bit perceptual hashes of an image. Visually similar images (resized,
+/// recompressed, lightly edited) have hashes a small Hamming distance apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageHashes {
+    /// Average hash: each pixel of an 8x8 thumbnail compared to the mean
+    pub ahash: u64,
+    /// Difference hash: each pixel of a 9x8 thumbnail compared to its right neighbour
+    pub dhash: u64,
+    /// DCT hash: low-frequency DCT coefficients compared to their median
+    pub phash: u64,
+}
+
+impl ImageHashes {
+    /// Decode an image and compute all three hashes
+    pub fn from_bytes(image_bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory(image_bytes)?;
+        Ok(Self::from_image(&image))
+    }
+
+    pub fn from_image(image: &DynamicImage) -> Self {
+        ImageHashes {
+            ahash: average_hash(image),
+            dhash: difference_hash(image),
+            phash: dct_hash(image),
+        }
+    }
+}
+
+fn thumbnail(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+}
+
+fn bits_from(flags: impl Iterator<Item = bool>) -> u64 {
+    flags.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+fn average_hash(image: &DynamicImage) -> u64 {
+    let pixels: Vec<f64> = thumbnail(image, 8, 8)
+        .pixels()
+        .map(|p| p.0[0] as f64)
+        .collect();
+    let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+
+    bits_from(pixels.iter().map(|&pixel| pixel > mean))
+}
+
+fn difference_hash(image: &DynamicImage) -> u64 {
+    let thumbnail = thumbnail(image, 9, 8);
+
+    bits_from((0..8).flat_map(|y| {
+        let thumbnail = &thumbnail;
+        (0..8).map(move |x| thumbnail.get_pixel(x, y).0[0] < thumbnail.get_pixel(x + 1, y).0[0])
+    }))
+}
+
+fn dct_hash(image: &DynamicImage) -> u64 {
+    let thumbnail = thumbnail(image, PHASH_SIZE as u32, PHASH_SIZE as u32);
+    let pixels: Vec<f64> = thumbnail.pixels().map(|p| p.0[0] as f64).collect();
+
+    // Separable 2D DCT-II: transform the rows, then the columns of the result.
+    // Only the low-frequency corner is needed, so both passes stop early.
+    let cosines: Vec<Vec<f64>> = (0..PHASH_LOW_FREQUENCIES)
+        .map(|u| {
+            (0..PHASH_SIZE)
+                .map(|x| {
+                    (std::f64::consts::PI * (2 * x + 1) as f64 * u as f64 / (2 * PHASH_SIZE) as f64)
+                        .cos()
+                })
+                .collect()
+        })
+        .collect();
+
+    let rows: Vec<Vec<f64>> = pixels
+        .chunks(PHASH_SIZE)
+        .map(|row| cosines.iter().map(|cosine| dot(row, cosine)).collect())
+        .collect();
+
+    let mut coefficients = Vec::with_capacity(PHASH_LOW_FREQUENCIES * PHASH_LOW_FREQUENCIES);
+    for cosine in &cosines {
+        for u in 0..PHASH_LOW_FREQUENCIES {
+            let column: Vec<f64> = rows.iter().map(|row| row[u]).collect();
+            coefficients.push(dot(&column, cosine));
+        }
+    }
+
+    // The DC term only reflects overall brightness, so leave it out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    bits_from(coefficients.iter().map(|&coefficient| coefficient > median))
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod perceptual_hash_test {
+    use super::*;
+    use image::ImageFormat;
+    use std::io::Cursor;
+
+    fn hamming_distance(a: u64, b: u64) -> u32 {
+        (a ^ b).count_ones()
+    }
+
+    fn test_image() -> DynamicImage {
+        image::open("src/worker/test_data/spiderman_meme.jpg").unwrap()
+    }
+
+    fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_resized_recompressed_copy_is_close() {
+        let original = test_image();
+        let resized = original.resize(
+            original.width() / 2,
+            original.height() / 2,
+            FilterType::Lanczos3,
+        );
+        let recompressed = encode(&resized.to_rgb8().into(), ImageFormat::Png);
+
+        let original_hashes =
+            ImageHashes::from_bytes(&encode(&original, ImageFormat::Png)).unwrap();
+        let copy_hashes = ImageHashes::from_bytes(&recompressed).unwrap();
+
+        assert!(hamming_distance(original_hashes.phash, copy_hashes.phash) <= 6);
+        assert!(hamming_distance(original_hashes.dhash, copy_hashes.dhash) <= 10);
+        assert!(hamming_distance(original_hashes.ahash, copy_hashes.ahash) <= 6);
+    }
+
+    #[test]
+    fn test_different_images_are_far_apart() {
+        let original = ImageHashes::from_image(&test_image());
+        let flipped = ImageHashes::from_image(&test_image().flipv());
+
+        assert!(hamming_distance(original.phash, flipped.phash) > 16);
+        assert!(hamming_distance(original.dhash, flipped.dhash) > 16);
+    }
+
+    #[test]
+    fn test_rejects_non_image_bytes() {
+        assert!(ImageHashes::from_bytes(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Spaces out calls to an external service to at most `per_second`, allowing
+/// a burst of up to one second's worth after a quiet period. Callers that
+/// would exceed the rate wait for their turn, in order. Shared by all jobs
+/// running in a worker process, so the limit applies per process.
+pub struct RateLimiter {
+    interval: Duration,
+    burst_tolerance: Duration,
+    /// When the next call would be due if calls were evenly spaced
+    next_due: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// A rate of zero or less disables limiting
+    pub fn new(per_second: f64) -> Self {
+        if per_second <= 0.0 {
+            return Self {
+                interval: Duration::ZERO,
+                burst_tolerance: Duration::ZERO,
+                next_due: Mutex::new(None),
+            };
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / per_second);
+        let burst = per_second.ceil().max(1.0) as u32;
+        Self {
+            interval,
+            burst_tolerance: interval * (burst - 1),
+            next_due: Mutex::new(None),
+        }
+    }
+
+    /// Wait until a call is allowed
+    pub async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Claim the next slot, returning how long to wait for it
+    fn reserve(&self, now: Instant) -> Duration {
+        if self.interval.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let mut next_due = self.next_due.lock().unwrap();
+        let due = next_due.map_or(now, |due| due.max(now));
+        *next_due = Some(due + self.interval);
+
+        due.saturating_duration_since(now + self.burst_tolerance)
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_test {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_never_waits() {
+        let limiter = RateLimiter::new(0.0);
+        let now = Instant::now();
+
+        for _ in 0..1000 {
+            assert_eq!(limiter.reserve(now), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_allows_a_burst_then_spaces_calls() {
+        let limiter = RateLimiter::new(4.0);
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            assert_eq!(limiter.reserve(now), Duration::ZERO);
+        }
+        assert_eq!(limiter.reserve(now), Duration::from_millis(250));
+        assert_eq!(limiter.reserve(now), Duration::from_millis(500));
+
+        // Quiet periods refill the burst, but never beyond it
+        let later = now + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert_eq!(limiter.reserve(later), Duration::ZERO);
+        }
+        assert!(limiter.reserve(later) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_slow_rates_allow_one_call_at_a_time() {
+        let limiter = RateLimiter::new(0.5);
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(now), Duration::ZERO);
+        assert_eq!(limiter.reserve(now), Duration::from_secs(2));
+    }
+}
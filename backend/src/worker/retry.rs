@@ -0,0 +1,95 @@
+use crate::worker::extraction::ExtractionError;
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
+
+/// Attempts a job gets before it is moved to the dead-letter queue
+pub const MAX_JOB_ATTEMPTS: u32 = 5;
+/// Delay before the first retry; each later retry waits up to twice as long
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// A failure that will fail the same way however often the job is retried,
+/// such as a missing object or a request the remote service rejected
+#[derive(Debug)]
+pub struct PermanentError(pub String);
+
+impl fmt::Display for PermanentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PermanentError {}
+
+/// Whether a failed job is worth retrying. Failures are treated as transient
+/// (throttling, timeouts, unavailable services) unless known to be permanent;
+/// the attempt limit bounds the cost of a misclassified one.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    for cause in error.chain() {
+        if cause.is::<PermanentError>()
+            || cause.is::<ExtractionError>()
+            || cause.is::<serde_json::Error>()
+        {
+            return false;
+        }
+
+        if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+            return match e {
+                // The file was deleted while the job was queued
+                sqlx::Error::RowNotFound => false,
+                // Deadlocks and serialization failures succeed when retried
+                sqlx::Error::Database(db_error) => {
+                    matches!(db_error.code().as_deref(), Some("40001" | "40P01"))
+                }
+                _ => true,
+            };
+        }
+    }
+
+    true
+}
+
+/// Delay before retrying a job that has failed `attempts` times: exponential
+/// backoff with equal jitter, so retries of jobs that failed together (e.g.
+/// during a throttling spike) spread out instead of failing together again
+pub fn retry_delay(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16);
+    let ceiling = BASE_RETRY_DELAY
+        .saturating_mul(1 << exponent)
+        .min(MAX_RETRY_DELAY);
+
+    let half = ceiling / 2;
+    half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+#[cfg(test)]
+mod retry_test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_grows_and_is_capped() {
+        for attempts in 1..=10 {
+            let ceiling = (BASE_RETRY_DELAY * 2u32.pow(attempts - 1)).min(MAX_RETRY_DELAY);
+            let delay = retry_delay(attempts);
+
+            assert!(delay >= ceiling / 2, "attempt {}: {:?}", attempts, delay);
+            assert!(delay <= ceiling, "attempt {}: {:?}", attempts, delay);
+        }
+        assert!(retry_delay(u32::MAX) <= MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_classifies_errors() {
+        assert!(is_retryable(&anyhow::anyhow!(
+            "Bedrock invoke_model failed: throttled"
+        )));
+        assert!(!is_retryable(&anyhow::Error::new(PermanentError(
+            "not found".to_string()
+        ))));
+        assert!(!is_retryable(
+            &anyhow::Error::new(sqlx::Error::RowNotFound).context("Failed to load file")
+        ));
+        assert!(is_retryable(&anyhow::Error::new(sqlx::Error::PoolTimedOut)));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::Config;
+use crate::services::resolution::{remove_embeddings, remove_objects, unused_object_keys};
+use crate::services::storage::ObjectStorage;
+use anyhow::Result;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How often the trash is checked for files past the retention window
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Permanently removes files that have been in the trash longer than
+/// `TRASH_RETENTION_DAYS`: their rows (cascading to matches, chunk manifests
+/// and signatures), their objects in storage and their embeddings.
+pub struct TrashSweeper {
+    db_pool: PgPool,
+    storage: Arc<dyn ObjectStorage>,
+    opensearch_url: String,
+    retention_days: u32,
+}
+
+impl TrashSweeper {
+    pub fn new(db_pool: PgPool, config: &Config, storage: Arc<dyn ObjectStorage>) -> Self {
+        Self {
+            db_pool,
+            storage,
+            opensearch_url: config.opensearch_url.clone(),
+            retention_days: config.trash_retention_days,
+        }
+    }
+
+    pub async fn start(&self, mut shutdown_signal: tokio::sync::watch::Receiver<bool>) {
+        log::info!(
+            "Starting trash sweeper, retention {} days",
+            self.retention_days
+        );
+
+        loop {
+            if *shutdown_signal.borrow() {
+                break;
+            }
+
+            match self.sweep().await {
+                Ok(0) => {}
+                Ok(purged) => log::info!("Purged {} files from the trash", purged),
+                Err(e) => log::error!("Failed to sweep the trash: {}", e),
+            }
+
+            tokio::select! {
+                _ = sleep(SWEEP_INTERVAL) => {}
+                Ok(()) = shutdown_signal.changed() => {}
+            }
+        }
+
+        log::info!("Trash sweeper stopped");
+    }
+
+    /// Purge expired files, returning how many were removed
+    pub async fn sweep(&self) -> Result<usize> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        let rows = sqlx::query(
+            "DELETE FROM File \
+             WHERE deleted_at < NOW() - make_interval(days => $1) \
+             RETURNING file_id, s3_key",
+        )
+        .bind(self.retention_days as i32)
+        .fetch_all(&mut *transaction)
+        .await?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let file_ids: Vec<i32> = rows.iter().map(|row| row.get("file_id")).collect();
+        let s3_keys: Vec<String> = rows
+            .iter()
+            .filter_map(|row| row.get::<Option<String>, _>("s3_key"))
+            .collect();
+        let unused_keys = unused_object_keys(&mut transaction, &s3_keys).await?;
+
+        transaction.commit().await?;
+
+        // The rows are gone either way; an object left behind is only wasted space
+        remove_objects(self.storage.as_ref(), &unused_keys).await;
+        remove_embeddings(&self.opensearch_url, &file_ids).await;
+
+        Ok(file_ids.len())
+    }
+}
@@ -1,51 +1,82 @@
+use crate::config::Config;
 use crate::handlers::websocket::ConnectionManager;
+use crate::services::storage::ObjectStorage;
 use crate::worker::deduplication_service::DeduplicationService;
-use crate::worker::job_queue::JobQueue;
+use crate::worker::embeddings::{EmbeddingProvider, create_embedding_provider};
+use crate::worker::job_queue::{DeduplicationJob, JobQueue, WORKER_HEARTBEAT_TTL_SECS};
+use crate::worker::trash_sweeper::TrashSweeper;
 use anyhow::Result;
 use sqlx::PgPool;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::time::sleep;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+/// How often each worker looks for abandoned jobs to requeue
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct WorkerProcess {
-    deduplication_service: DeduplicationService,
+    worker_id: String,
+    deduplication_service: Arc<DeduplicationService>,
     job_queue: JobQueue,
+    concurrency: usize,
+    /// One permit per job this worker may run at once
+    job_slots: Arc<Semaphore>,
+    /// Running jobs, so shutdown can wait for them or stop them
+    jobs: JoinSet<()>,
+    shutdown_timeout: Duration,
     shutdown_signal: tokio::sync::watch::Receiver<bool>,
 }
 
 impl WorkerProcess {
     pub fn new(
         db_pool: PgPool,
-        redis_url: String,
-        opensearch_url: String,
-        aws_profile: String,
-        bedrock_model_id: String,
+        config: &Config,
+        storage: Arc<dyn ObjectStorage>,
+        embedding_provider: Arc<dyn EmbeddingProvider>,
         shutdown_signal: tokio::sync::watch::Receiver<bool>,
         connection_manager: Option<Arc<Mutex<ConnectionManager>>>,
     ) -> Result<Self> {
-        let job_queue = JobQueue::new(&redis_url)?;
+        let job_queue = JobQueue::new(&config.redis_url)?
+            .with_visibility_timeout(config.job_visibility_timeout_secs);
         let mut deduplication_service = DeduplicationService::new(
             db_pool,
             job_queue.clone(),
-            opensearch_url,
-            aws_profile,
-            bedrock_model_id,
-        );
+            config.opensearch_url.clone(),
+            storage,
+            embedding_provider,
+        )
+        .with_opensearch_rate_limit(config.opensearch_requests_per_second)
+        .with_cross_tenant_deduplication(config.cross_tenant_deduplication);
 
         // Set connection manager if provided
         if let Some(conn_mgr) = connection_manager {
             deduplication_service.set_connection_manager(conn_mgr);
         }
 
+        let concurrency = config.worker_concurrency.max(1);
         Ok(WorkerProcess {
-            deduplication_service,
+            worker_id: Uuid::new_v4().to_string(),
+            deduplication_service: Arc::new(deduplication_service),
             job_queue,
+            concurrency,
+            job_slots: Arc::new(Semaphore::new(concurrency)),
+            jobs: JoinSet::new(),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout_secs),
             shutdown_signal,
         })
     }
 
     pub async fn start(&mut self) -> Result<()> {
-        log::info!("Starting worker process...");
+        log::info!(
+            "Starting worker process {} with {} job slots...",
+            self.worker_id,
+            self.concurrency
+        );
+
+        let mut last_reap: Option<Instant> = None;
 
         loop {
             // Check for shutdown signal
@@ -54,75 +85,246 @@ impl WorkerProcess {
                 break;
             }
 
+            // Forget jobs that have finished
+            while self.jobs.try_join_next().is_some() {}
+
+            if let Err(e) = self.job_queue.heartbeat_worker(&self.worker_id).await {
+                log::error!("Failed to record worker heartbeat: {}", e);
+            }
+
+            if last_reap.is_none_or(|at| at.elapsed() >= REAP_INTERVAL) {
+                self.requeue_abandoned_jobs().await;
+                last_reap = Some(Instant::now());
+            }
+
+            if let Err(e) = self.job_queue.promote_delayed_jobs().await {
+                log::error!("Failed to promote delayed jobs: {}", e);
+            }
+
+            // Wait for a free slot before claiming a job, so claimed jobs
+            // never sit waiting behind running ones
+            let slot = tokio::select! {
+                slot = self.job_slots.clone().acquire_owned() => slot?,
+                Ok(()) = self.shutdown_signal.changed() => continue,
+            };
+
             // Try to dequeue a job
-            match self.job_queue.dequeue_job().await {
-                Ok(Some(job)) => {
-                    log::info!("Processing job: {}", job.job_id);
+            match self.job_queue.dequeue_job(&self.worker_id).await {
+                Ok(Some(job)) => self.spawn_job(job, slot),
+                Ok(None) => {
+                    // No jobs available, wait a bit before checking again
+                    drop(slot);
+                    self.pause(Duration::from_secs(1)).await;
+                }
+                Err(e) => {
+                    drop(slot);
+                    log::error!("Error dequeuing job: {}", e);
+                    self.pause(Duration::from_secs(5)).await;
+                }
+            }
+        }
+
+        self.drain().await;
+
+        log::info!("Worker process stopped");
+        Ok(())
+    }
+
+    /// Sleep, waking early on shutdown
+    async fn pause(&mut self, duration: Duration) {
+        tokio::select! {
+            _ = sleep(duration) => {}
+            Ok(()) = self.shutdown_signal.changed() => {}
+        }
+    }
 
-                    // Update job status to processing using deduplication service for WebSocket broadcasting
+    /// Wait up to the shutdown timeout for running jobs to finish, then stop
+    /// the rest and put them back on the queue for another worker
+    async fn drain(&mut self) {
+        if !self.jobs.is_empty() {
+            log::info!(
+                "Waiting up to {}s for {} running jobs",
+                self.shutdown_timeout.as_secs(),
+                self.jobs.len()
+            );
+        }
+
+        let jobs = &mut self.jobs;
+        let finished = timeout(self.shutdown_timeout, async {
+            while jobs.join_next().await.is_some() {}
+        })
+        .await;
+        if finished.is_err() {
+            log::warn!(
+                "Stopping {} jobs that did not finish in time",
+                self.jobs.len()
+            );
+            self.jobs.shutdown().await;
+        }
+
+        match self.job_queue.release_worker_jobs(&self.worker_id).await {
+            Ok(job_ids) => {
+                for job_id in job_ids {
                     if let Err(e) = self
                         .deduplication_service
-                        .update_job_status(&job.job_id, "processing", None)
+                        .update_job_status(&job_id, "pending", None)
                         .await
                     {
-                        log::error!("Failed to update job status to processing: {}", e);
+                        log::error!("Failed to reset status of job {}: {}", job_id, e);
                     }
+                }
+            }
+            // The reaper requeues them once this worker's heartbeat expires
+            Err(e) => log::error!("Failed to return unfinished jobs to the queue: {}", e),
+        }
+    }
 
-                    // Process the job
+    /// Process a job in the background, freeing its slot once it has been
+    /// acknowledged
+    fn spawn_job(&mut self, job: DeduplicationJob, slot: OwnedSemaphorePermit) {
+        let deduplication_service = self.deduplication_service.clone();
+        let job_queue = self.job_queue.clone();
+        let worker_id = self.worker_id.clone();
+
+        self.jobs.spawn(async move {
+            log::info!("Processing job: {}", job.job_id);
+            let job_id = job.job_id.clone();
+
+            // Update job status to processing using deduplication service for WebSocket broadcasting
+            if let Err(e) = deduplication_service
+                .update_job_status(&job.job_id, "processing", None)
+                .await
+            {
+                log::error!("Failed to update job status to processing: {}", e);
+            }
+
+            // Process the job, keeping its lease alive however long it takes
+            let heartbeat =
+                spawn_lease_heartbeat(job_queue.clone(), worker_id.clone(), job_id.clone());
+            if let Err(e) = deduplication_service.process_deduplication_job(job).await {
+                log::error!("Failed to process job: {}", e);
+            }
+            drop(heartbeat);
+
+            if let Err(e) = job_queue.acknowledge_job(&worker_id, &job_id).await {
+                log::error!("Failed to acknowledge job {}: {}", job_id, e);
+            }
+            drop(slot);
+        });
+    }
+
+    async fn requeue_abandoned_jobs(&self) {
+        match self.job_queue.requeue_abandoned_jobs().await {
+            Ok(job_ids) => {
+                for job_id in job_ids {
                     if let Err(e) = self
                         .deduplication_service
-                        .process_deduplication_job(job)
+                        .update_job_status(&job_id, "pending", None)
                         .await
                     {
-                        log::error!("Failed to process job: {}", e);
+                        log::error!("Failed to reset status of job {}: {}", job_id, e);
                     }
                 }
-                Ok(None) => {
-                    // No jobs available, wait a bit before checking again
-                    sleep(Duration::from_secs(1)).await;
-                }
-                Err(e) => {
-                    log::error!("Error dequeuing job: {}", e);
-                    sleep(Duration::from_secs(5)).await;
+            }
+            Err(e) => log::error!("Failed to requeue abandoned jobs: {}", e),
+        }
+    }
+}
+
+/// Keeps a job's lease alive; stops when dropped, including when the job's
+/// task is aborted
+struct LeaseHeartbeat(tokio::task::JoinHandle<()>);
+
+impl Drop for LeaseHeartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Extend the job's lease, and the worker's heartbeat, several times per
+/// visibility timeout
+fn spawn_lease_heartbeat(job_queue: JobQueue, worker_id: String, job_id: String) -> LeaseHeartbeat {
+    let interval = Duration::from_secs(
+        (job_queue.visibility_timeout_secs() / 3).clamp(1, WORKER_HEARTBEAT_TTL_SECS / 3),
+    );
+
+    LeaseHeartbeat(tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+
+            if let Err(e) = job_queue.heartbeat_worker(&worker_id).await {
+                log::warn!("Failed to record worker heartbeat: {}", e);
+            }
+            match job_queue.extend_lease(&worker_id, &job_id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    log::warn!("Lost the lease on job {}, it may run again", job_id);
+                    break;
                 }
+                Err(e) => log::warn!("Failed to extend lease on job {}: {}", job_id, e),
             }
         }
+    }))
+}
 
-        log::info!("Worker process stopped");
-        Ok(())
+/// A running worker process and its trash sweeper
+pub struct WorkerHandle {
+    shutdown_tx: tokio::sync::watch::Sender<bool>,
+    worker: tokio::task::JoinHandle<Result<()>>,
+    sweeper: tokio::task::JoinHandle<()>,
+}
+
+impl WorkerHandle {
+    /// Stop claiming jobs and wait for the worker to drain: running jobs get
+    /// `SHUTDOWN_TIMEOUT_SECS` to finish, the rest go back on the queue
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown_tx.send(true);
+
+        self.sweeper.await?;
+        self.worker.await?
     }
 }
 
 pub async fn spawn_worker_process(
     db_pool: PgPool,
-    redis_url: String,
-    opensearch_url: String,
-    aws_profile: String,
-    bedrock_model_id: String,
+    config: &Config,
+    storage: Arc<dyn ObjectStorage>,
     connection_manager: Option<Arc<Mutex<ConnectionManager>>>,
-) -> Result<tokio::task::JoinHandle<Result<()>>> {
+) -> Result<WorkerHandle> {
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
+    let embedding_provider = create_embedding_provider(config).await?;
+    let db_pool_for_sweeper = db_pool.clone();
+    let storage_for_sweeper = storage.clone();
+    let sweeper_shutdown_rx = shutdown_rx.clone();
+
     let mut worker = WorkerProcess::new(
         db_pool,
-        redis_url,
-        opensearch_url,
-        aws_profile,
-        bedrock_model_id,
+        config,
+        storage,
+        embedding_provider,
         shutdown_rx,
         connection_manager,
     )?;
 
-    let handle = tokio::spawn(async move { worker.start().await });
+    let worker = tokio::spawn(async move { worker.start().await });
+
+    let sweeper = TrashSweeper::new(db_pool_for_sweeper, config, storage_for_sweeper);
+    let sweeper = tokio::spawn(async move { sweeper.start(sweeper_shutdown_rx).await });
 
-    // Store the shutdown sender somewhere accessible if you need graceful shutdown
-    // For now, we'll just return the handle
-    Ok(handle)
+    Ok(WorkerHandle {
+        shutdown_tx,
+        worker,
+        sweeper,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{EmbeddingProviderKind, StorageBackend};
+    use crate::services::storage::LocalStorage;
+    use crate::worker::embeddings::LocalEmbeddingProvider;
 
     #[tokio::test]
     async fn test_worker_process_creation() {
@@ -141,19 +343,47 @@ mod tests {
         }
 
         let pool = pool.unwrap();
-        let redis_url = "redis://127.0.0.1:6379".to_string();
-        let opensearch_url = "http://localhost:9200".to_string();
-        let aws_profile = "default".to_string();
-        let bedrock_model_id = "amazon.titan-embed-text-v1".to_string();
+        let config = Config {
+            jwt_secret: "test_secret".to_string(),
+            database_url: db_url,
+            aws_profile_name: "default".to_string(),
+            s3_bucket_name: "file-dedup-test".to_string(),
+            s3_document_prefix: "documents".to_string(),
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            opensearch_url: "http://localhost:9200".to_string(),
+            bedrock_model_id: "amazon.titan-embed-text-v1".to_string(),
+            otel_exporter_otlp_endpoint: "http://localhost:4317".to_string(),
+            storage_backend: StorageBackend::Local,
+            local_storage_path: std::env::temp_dir()
+                .join("file-dedup-test")
+                .to_string_lossy()
+                .to_string(),
+            public_base_url: "http://localhost:8080".to_string(),
+            embedding_provider: EmbeddingProviderKind::Local,
+            local_embedding_model_path: None,
+            local_embedding_dimension: 1536,
+            trash_retention_days: 30,
+            job_visibility_timeout_secs: 300,
+            worker_concurrency: 4,
+            embedding_requests_per_second: 10.0,
+            opensearch_requests_per_second: 0.0,
+            shutdown_timeout_secs: 25,
+            cross_tenant_deduplication: false,
+            access_token_ttl_secs: 900,
+            refresh_token_ttl_days: 30,
+        };
+        let storage: Arc<dyn ObjectStorage> = Arc::new(LocalStorage::from_config(&config));
+        let embedding_provider: Arc<dyn EmbeddingProvider> = Arc::new(LocalEmbeddingProvider::new(
+            config.local_embedding_dimension,
+        ));
 
         let (_, shutdown_rx) = tokio::sync::watch::channel(false);
 
         let worker_result = WorkerProcess::new(
             pool,
-            redis_url,
-            opensearch_url,
-            aws_profile,
-            bedrock_model_id,
+            &config,
+            storage,
+            embedding_provider,
             shutdown_rx,
             None, // No connection manager for tests
         );
@@ -28,6 +28,9 @@ resource "opensearch_index" "file_embeddings" {
         sha256_hash = {
           type = "keyword"
         }
+        workspace_id = {
+          type = "keyword"
+        }
         embedding = {
           type       = "dense_vector"
           dims       = 1536
@@ -70,6 +73,9 @@ resource "opensearch_index" "image_embeddings" {
         sha256_hash = {
           type = "keyword"
         }
+        workspace_id = {
+          type = "keyword"
+        }
         embedding = {
           type       = "dense_vector"
           dims       = 1024
@@ -114,6 +120,9 @@ resource "opensearch_index_template" "file_embeddings_template" {
           sha256_hash = {
             type = "keyword"
           }
+          workspace_id = {
+            type = "keyword"
+          }
           embedding = {
             type       = "dense_vector"
             dims       = 1536
@@ -159,6 +168,9 @@ resource "opensearch_index_template" "image_embeddings_template" {
           sha256_hash = {
             type = "keyword"
           }
+          workspace_id = {
+            type = "keyword"
+          }
           embedding = {
             type       = "dense_vector"
             dims       = 1024